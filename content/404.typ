@@ -0,0 +1,13 @@
+#metadata((
+  title: "Page Not Found",
+  desc: "This page doesn't exist",
+)) <page>
+
+#import "_shared/template.typ": template, link
+#show: template.with(styles: ("collection",))
+
+#html.div(class: "preface")[
+  = Page Not Found
+
+  Whatever you were looking for isn't here (anymore). Try the #link("home page", "/"), #link("blog", "/blog"), or #link("projects", "/projects").
+]
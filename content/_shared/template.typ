@@ -1,5 +1,8 @@
 #let page = sys.inputs.at("page", default: (:))
 
+// fingerprinted url of an asset (cached forever), `/styles/main.css` -> `/styles/main.<hash>.css`
+#let asset(url) = sys.inputs.at("assets", default: (:)).at(url, default: url)
+
 #let link(text, href) = {
   html.a(href: href)[#text]
 }
@@ -18,7 +21,7 @@
       #html.div(class: "left")[
         #link("IV", "/")
       ]
-      #html.nav(class:"nav")[#html.a(class: "light-dark", aria-label: "Toggle dark mode")[ #html.div(class: "moon", style: "display: none")[ #html.img( src: "/icons/moon.svg", alt: "Enable dark mode icon", width: 26, height: 26 ) ] #html.div(class: "sun", style: "display: none")[ #html.img( src: "/icons/sun.svg", alt: "Enable light mode icon", width: 26, height: 26 ) ] ]#html.a(href:"/blog")[BLOG]#html.a(href:"/projects")[PROJECTS]]
+      #html.nav(class:"nav")[#html.a(class: "light-dark", aria-label: "Toggle dark mode")[ #html.div(class: "moon", style: "display: none")[ #html.img( src: asset("/icons/moon.svg"), alt: "Enable dark mode icon", width: 26, height: 26 ) ] #html.div(class: "sun", style: "display: none")[ #html.img( src: asset("/icons/sun.svg"), alt: "Enable light mode icon", width: 26, height: 26 ) ] ]#html.a(href:"/blog")[BLOG]#html.a(href:"/projects")[PROJECTS]]
     ]
   ]
 }
@@ -28,7 +31,7 @@
     #html.div[
       #html.a(target: "_blank", href: "mailto:mail@liamsnow.com")[
         #html.img(
-          src: "/icons/email.svg",
+          src: asset("/icons/email.svg"),
           alt: "Email Icon",
           width: 20,
           height: 20
@@ -39,7 +42,7 @@
     #html.div[
       #html.a(target: "_blank", href: "https://www.linkedin.com/in/william-snow-iv-140438169/")[
         #html.img(
-          src: "/icons/linkedin.svg",
+          src: asset("/icons/linkedin.svg"),
           alt: "LinkedIn Icon",
           width: 20,
           height: 20
@@ -50,7 +53,7 @@
     #html.div[
       #html.a(target: "_blank", href: "https://github.com/liamsnow")[
         #html.img(
-          src: "/icons/github.svg",
+          src: asset("/icons/github.svg"),
           alt: "GitHub Icon",
           width: 20,
           height: 20
@@ -61,7 +64,7 @@
     #html.div[
       #html.a(target: "_blank", href: "https://github.com/LiamSnow/resume/blob/main/resume.pdf")[
         #html.img(
-          src: "/icons/resume.svg",
+          src: asset("/icons/resume.svg"),
           alt: "Resume Icon",
           width: 20,
           height: 20
@@ -83,7 +86,7 @@
         #linebreak()
         #html.div[
           #html.a(target: "_blank", href: "https://github.com/liamsnow/liamsnow.com")[
-            #html.img(src: "/icons/code.svg", alt: "Source Code Icon", width: 20, height: 20)
+            #html.img(src: asset("/icons/code.svg"), alt: "Source Code Icon", width: 20, height: 20)
             Source Code
           ]
         ]
@@ -127,20 +130,18 @@
       #html.link(rel: "alternate", type: "application/rss+xml", title: "Liam Snow's Projects", href: "/projects/rss.xml")
 
       #if page.at("url", default: "") == "/" {
-        html.elem("link", attrs: (rel: "preload", href: "/fonts/DINNextSlabBlack.woff2", ("as"): "font", type: "font/woff2", crossorigin: "anonymous"))
+        html.elem("link", attrs: (rel: "preload", href: asset("/fonts/DINNextSlabBlack.woff2"), ("as"): "font", type: "font/woff2", crossorigin: "anonymous"))
       }
 
-      #html.elem("link", attrs: (rel: "preload", href: "/fonts/SpaceGrotesk-Regular.woff2", ("as"): "font", type: "font/woff2", crossorigin: "anonymous"))
-      #html.elem("link", attrs: (rel: "preload", href: "/fonts/SpaceGrotesk-Bold.woff2", ("as"): "font", type: "font/woff2", crossorigin: "anonymous"))
+      #html.elem("link", attrs: (rel: "preload", href: asset("/fonts/SpaceGrotesk-Regular.woff2"), ("as"): "font", type: "font/woff2", crossorigin: "anonymous"))
+      #html.elem("link", attrs: (rel: "preload", href: asset("/fonts/SpaceGrotesk-Bold.woff2"), ("as"): "font", type: "font/woff2", crossorigin: "anonymous"))
 
       #for style in styles {
-        html.elem("link", attrs: (rel: "preload", href: "/styles/" + style + ".css", ("as"): "style"))
+        html.elem("link", attrs: (rel: "preload", href: asset("/styles/" + style + ".css"), ("as"): "style"))
       }
 
-      #html.style()[#read("fonts.css")]
-
       #for style in styles {
-        html.link(rel: "stylesheet", href: "/styles/" + style + ".css")
+        html.link(rel: "stylesheet", href: asset("/styles/" + style + ".css"))
       }
 
       #if "css" in sys.inputs {
@@ -174,19 +175,19 @@
 
 #let lang-icon(lang) = {
   if lang == "Rust" {
-    html.img(src: "/icons/cuddlyferris.svg", alt: "Rust Icon", width: 22, height: 16)
+    html.img(src: asset("/icons/cuddlyferris.svg"), alt: "Rust Icon", width: 22, height: 16)
   } else if lang == "SystemVerilog" {
-    html.img(src: "/icons/xor.svg", alt: "SystemVerilog Icon", width: 22, height: 15) 
+    html.img(src: asset("/icons/xor.svg"), alt: "SystemVerilog Icon", width: 22, height: 15) 
   } else {
-    html.img(src: "/icons/code.svg", alt: "Other Programming Language Icon", width: 22, height: 22)
+    html.img(src: asset("/icons/code.svg"), alt: "Other Programming Language Icon", width: 22, height: 22)
   }
 }
 
 #let quick-link-icon(link) = {
   if link.contains("github") {
-    html.img(src: "/icons/github.svg", alt: "GitHub Icon", width: 20, height: 20)
+    html.img(src: asset("/icons/github.svg"), alt: "GitHub Icon", width: 20, height: 20)
   } else {
-    html.img(src: "/icons/link.svg", alt: "Generic Link Icon", width: 20, height: 20)
+    html.img(src: asset("/icons/link.svg"), alt: "Generic Link Icon", width: 20, height: 20)
   }
 }
 
@@ -196,6 +197,29 @@
   html.p[#lang]
 }
 
+// responsive image using the variants generated for `src` (an absolute url)
+#let picture(src, alt: "", sizes: "(max-width: 800px) 100vw, 800px", style: none) = {
+  let image = sys.inputs.at("images", default: (:)).at(src, default: none)
+  let attrs = (src: asset(src), alt: alt, loading: "lazy", decoding: "async")
+  if style != none {
+    attrs.insert("style", style)
+  }
+  if image == none {
+    return html.elem("img", attrs: attrs)
+  }
+
+  attrs.insert("width", str(image.width))
+  attrs.insert("height", str(image.height))
+  attrs.insert("srcset", image.srcset)
+  attrs.insert("sizes", sizes)
+  html.elem("picture", {
+    for source in image.sources {
+      html.elem("source", attrs: (type: source.type, srcset: source.srcset, sizes: sizes))
+    }
+    html.elem("img", attrs: attrs)
+  })
+}
+
 #let post(body) = {
   template(
     [
@@ -208,7 +232,7 @@
         #html.ul(id: "post-stats")[
           #if "written" in page {
             html.li[
-              #html.img(src: "/icons/written.svg", alt: "Written Icon", width: 22, height: 22)
+              #html.img(src: asset("/icons/written.svg"), alt: "Written Icon", width: 22, height: 22)
               #html.p[Written:]
               #html.p(class: "date")[
                 #page.at("written")
@@ -218,7 +242,7 @@
         
           #if "updated" in page {
             html.li[
-              #html.img(src: "/icons/updated.svg", alt: "Updated Icon", width: 22, height: 22)
+              #html.img(src: asset("/icons/updated.svg"), alt: "Updated Icon", width: 22, height: 22)
               #html.p[Updated:]
               #html.p(class: "date")[
                 #page.at("updated")
@@ -228,7 +252,7 @@
 
           #if "started" in page {
             html.li[
-              #html.img(src: "/icons/rocket_launch.svg", alt: "Started Icon", width: 22, height: 22)
+              #html.img(src: asset("/icons/rocket_launch.svg"), alt: "Started Icon", width: 22, height: 22)
               #html.p[Started:]
               #html.p(class: "date")[
                 #page.at("started")
@@ -240,10 +264,10 @@
             html.li[
               #let ended = page.at("ended")
               #if ended == "Now" {
-                html.img(src: "/icons/infinite.svg", alt: "Ongoing Project Icon", width: 22)
+                html.img(src: asset("/icons/infinite.svg"), alt: "Ongoing Project Icon", width: 22)
                 html.p[Ongoing]
               } else {
-                html.img(src: "/icons/done_all.svg", alt: "Project End Date Icon", height: 22)
+                html.img(src: asset("/icons/done_all.svg"), alt: "Project End Date Icon", height: 22)
                 html.p[Ended:]
                 html.p(class: "date")[
                   #ended
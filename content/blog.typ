@@ -3,9 +3,11 @@
   desc: "Liam Snow's Blog. Programming, systems, backend, Rust and more.",
 )) <page>
 
-#metadata((blogs: "/blog/")) <query>
+#metadata((blogs: (prefix: "/blog/", sort: "-written"))) <query>
 
-#import "_shared/template.typ": template, link, link-new-tab
+#metadata((feed: "/blog/", content: true, author: "Liam Snow")) <feed>
+
+#import "_shared/template.typ": template, link, link-new-tab, asset
 #show: template.with(styles: ("collection",))
 
 #html.div(class: "preface")[
@@ -15,7 +17,6 @@
 
 #let posts = {
   sys.inputs.at("blogs", default: ())
-    .sorted(key: p => p.at("written", default: "")).rev()
 }
 
 #html.ol(class: "posts")[
@@ -29,14 +30,14 @@
       ]
       #html.div(class: "stats")[
         #html.div[
-          #html.img(src: "/icons/written.svg", alt: "Blog start date icon", width: 22, height: 22)
+          #html.img(src: asset("/icons/written.svg"), alt: "Blog start date icon", width: 22, height: 22)
           #html.p[Written:]
           #html.p(class: "date")[
             #post.at("written", default: "")
           ]
         ]
         #html.div[
-          #html.img(src: "/icons/updated.svg", alt: "Blog updated icon", width: 22, height: 22)
+          #html.img(src: asset("/icons/updated.svg"), alt: "Blog updated icon", width: 22, height: 22)
           #html.p[Updated:]
           #html.p(class: "date")[
             #post.at("updated", default: "")
@@ -3,9 +3,11 @@
   desc: "Liam Snow's personal website! Programming, systems, backend, Rust and more.",
 )) <page>
 
-#metadata((projects: "/projects/")) <query>
-#metadata((blogs: "/blog/")) <query>
-#metadata((notes: "/notes/")) <query>
+#metadata((
+  projects: (prefix: "/projects/", where: (homepage: true), sort: "-ended"),
+  blogs: (prefix: "/blog/", where: (homepage: true), sort: "-written"),
+  notes: (prefix: "/notes/", where: (homepage: true), sort: "-written"),
+)) <query>
 #metadata((css: "/styles/index.scss")) <css>
 
 #import "_shared/template.typ": template, link, link-new-tab, link-new-tab-highlight, social, lang-icon
@@ -74,18 +76,15 @@
 }
 
 #let projects = {
-  sys.inputs.at("projects", default: ()).filter(item => item.at("homepage", default: false))
-    .sorted(key: item => item.at("ended", default: "")).rev()
+  sys.inputs.at("projects", default: ())
 }
 
 #let blogs = {
-  sys.inputs.at("blogs", default: ()).filter(item => item.at("homepage", default: false))
-    .sorted(key: item => item.at("written", default: "")).rev()
+  sys.inputs.at("blogs", default: ())
 }
 
 #let notes = {
-  sys.inputs.at("notes", default: ()).filter(item => item.at("homepage", default: false))
-    .sorted(key: item => item.at("written", default: "")).rev()
+  sys.inputs.at("notes", default: ())
 }
 
 #html.div(id: "sections")[
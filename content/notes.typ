@@ -2,7 +2,7 @@
   title: "Liam's Notes",
 )) <page>
 
-#metadata((notes: "/notes/")) <query>
+#metadata((notes: (prefix: "/notes/", sort: "-updated"))) <query>
 
 #import "_shared/template.typ": template, link, link-new-tab
 #show: template.with(styles: ("collection",))
@@ -15,7 +15,6 @@
 
 #let posts = {
   sys.inputs.at("notes", default: ())
-    .sorted(key: p => p.at("updated", default: "")).rev()
 }
 
 #html.ol(class: "posts")[
@@ -3,9 +3,9 @@
   desc: "Liam Snow's Projects. Programming, systems, backend, Rust and more.",
 )) <page>
 
-#metadata((projects: "/projects/")) <query>
+#metadata((projects: (prefix: "/projects/", sort: "-ended"))) <query>
 
-#import "_shared/template.typ": template, link, link-new-tab, lang-display
+#import "_shared/template.typ": template, link, link-new-tab, lang-display, asset
 #show: template.with(styles: ("collection",))
 
 #html.div(class: "preface")[
@@ -17,7 +17,6 @@
 
 #let posts = {
   sys.inputs.at("projects", default: ())
-    .sorted(key: p => p.at("ended", default: "")).rev()
 }
 
 #html.ol(class: "posts")[
@@ -31,7 +30,7 @@
       ]
       #html.div(class: "stats")[
         #html.div[
-          #html.img(src: "/icons/rocket_launch.svg", alt: "Project start date icon")
+          #html.img(src: asset("/icons/rocket_launch.svg"), alt: "Project start date icon")
           #html.p[Started:]
           #html.p(class: "date")[
             #post.at("started", default: "")
@@ -40,10 +39,10 @@
         #html.div[
           #let ended = post.at("ended", default: "")
           #if ended == "Now" {
-            html.img(src: "/icons/infinite.svg", alt: "Ongoing project icon", width: 22, height: 22)
+            html.img(src: asset("/icons/infinite.svg"), alt: "Ongoing project icon", width: 22, height: 22)
             html.p[Ongoing]
           } else {
-            html.img(src: "/icons/done_all.svg", alt: "Project end date icon", width: 22, height: 22)
+            html.img(src: asset("/icons/done_all.svg"), alt: "Project end date icon", width: 22, height: 22)
             html.p[Ended:]
             html.p(class: "date")[
               #ended
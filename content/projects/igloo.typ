@@ -7,7 +7,7 @@
   homepage: true,
 )) <page>
 
-#metadata((blogs: "/blog/igloo/")) <query>
+#metadata((blogs: (prefix: "/blog/igloo/", sort: "-written"))) <query>
 
 #import "../_shared/template.typ": post, link-new-tab 
 #show: post
@@ -97,7 +97,7 @@ I also just didn't want to be spending my time adding Python support and develop
 = Blog Posts
 
 #let posts = {
-  sys.inputs.at("blogs", default: ()).sorted(key: p => p.at("written", default: "")).rev()
+  sys.inputs.at("blogs", default: ())
 }
 
 
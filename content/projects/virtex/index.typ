@@ -13,7 +13,7 @@
   homepage: false
 )) <page>
 
-#import "../../_shared/template.typ": post, link-new-tab
+#import "../../_shared/template.typ": post, link-new-tab, picture
 #show: post
 
 = Context
@@ -56,16 +56,10 @@ While somewhat helpful, doing this also hurt me, because I'd try to map traditio
 
 
 = Images
-#html.elem("img", attrs: (
-  src: "virtex/virtex_main_pcb_back.jpeg",
-  width: "50%"
-))
+#picture("/projects/virtex/virtex_main_pcb_back.jpeg", style: "width: 50%", sizes: "400px")
 
-#html.elem("img", attrs: (
-  src: "virtex/virtex_main_pcb_front.jpeg",
-  width: "50%"
-))
+#picture("/projects/virtex/virtex_main_pcb_front.jpeg", style: "width: 50%", sizes: "400px")
 
-#html.img(src: "virtex/virtex2_on.jpeg")
+#picture("/projects/virtex/virtex2_on.jpeg")
 
-#html.img(src: "virtex/virtex2_desk.jpeg")
+#picture("/projects/virtex/virtex2_desk.jpeg")
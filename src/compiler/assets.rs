@@ -0,0 +1,295 @@
+//! Fingerprinted asset urls (`/styles/main.css` → `/styles/main.<xxh3>.css`)
+//!
+//! Assets are served at both urls, the fingerprinted one being cached
+//! forever. Templates link to it through `sys.inputs.assets`, stylesheets
+//! have their `url()`s rewritten to it (see `rewrite_css`)
+
+use crate::RoutingTable;
+use crate::indexer::{FileSlot, SlotType, Slots};
+use mime_guess::mime::{FONT, IMAGE, JAVASCRIPT, TEXT};
+use rustc_hash::FxHashMap;
+use typst::foundations::{Dict, Value};
+
+pub const INPUTS_KEY: &str = "assets";
+
+/// Url → fingerprinted url
+pub type Assets = FxHashMap<String, String>;
+
+/// Stylesheets, scripts, fonts and images
+pub fn is_asset(slot: &FileSlot) -> bool {
+    if slot.hidden {
+        return false;
+    }
+    match &slot.ty {
+        SlotType::Typst(_) => false,
+        SlotType::Scss | SlotType::Image(_) => true,
+        SlotType::Other => match (slot.mime.type_(), slot.mime.subtype()) {
+            (FONT | IMAGE, _) => true,
+            (_, JAVASCRIPT) => true,
+            (TEXT, sub) => sub == "css",
+            _ => false,
+        },
+    }
+}
+
+/// Compiled after every other asset, since their `url()`s are fingerprinted
+pub fn is_stylesheet(slot: &FileSlot) -> bool {
+    match &slot.ty {
+        SlotType::Scss => true,
+        SlotType::Other => slot.mime.type_() == TEXT && slot.mime.subtype() == "css",
+        _ => false,
+    }
+}
+
+/// `/styles/main.css` → `/styles/main.<hash>.css`
+pub fn fingerprint(url: &str, hash: u64) -> String {
+    let hash = hash as u32;
+    match url.rsplit_once('.') {
+        Some((stem, ext)) if !ext.contains('/') => format!("{stem}.{hash:08x}.{ext}"),
+        _ => format!("{url}.{hash:08x}"),
+    }
+}
+
+/// Points `assets` at the routes compiled for each visible asset, removing
+/// routes of outdated fingerprints. Returns if any fingerprint changed
+///
+/// An asset whose route is an error page keeps its last fingerprint
+pub fn sync(slots: &Slots, assets: &mut Assets, routing_table: &mut RoutingTable) -> bool {
+    let mut synced = Assets::default();
+
+    for slot in slots.values().filter(|slot| is_asset(slot)) {
+        let current = routing_table
+            .get(&slot.url)
+            .map(|route| fingerprint(&slot.url, route.hash));
+        let fingerprinted = current
+            .into_iter()
+            .chain(assets.get(&slot.url).cloned())
+            .find(|url| routing_table.contains_key(url));
+        if let Some(fingerprinted) = fingerprinted {
+            synced.insert(slot.url.clone(), fingerprinted);
+        }
+    }
+
+    for (url, old) in assets.iter() {
+        if synced.get(url) != Some(old) {
+            routing_table.remove(old);
+        }
+    }
+
+    let changed = synced != *assets;
+    *assets = synced;
+    changed
+}
+
+pub fn inputs(assets: &Assets) -> Dict {
+    assets
+        .iter()
+        .map(|(url, fingerprinted)| {
+            (
+                url.as_str().into(),
+                Value::Str(fingerprinted.as_str().into()),
+            )
+        })
+        .collect()
+}
+
+/// Points every `url()` in `css` (quoted or not) which is an asset at its
+/// fingerprinted url, from `inputs` (see `inputs`). Relative urls are
+/// resolved against `base`, the stylesheet's url
+pub fn rewrite_css(css: &str, base: &str, inputs: &Dict) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(i) = rest
+        .as_bytes()
+        .windows(4)
+        .position(|w| w.eq_ignore_ascii_case(b"url("))
+    {
+        let (before, after) = rest.split_at(i + 4);
+        out.push_str(before);
+
+        let mut start = after.len() - after.trim_start().len();
+        let quote = after[start..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''));
+        start += quote.map_or(0, char::len_utf8);
+        let Some(len) = after[start..].find(quote.unwrap_or(')')) else {
+            rest = after;
+            break;
+        };
+        let url = match quote {
+            Some(_) => &after[start..start + len],
+            None => after[start..start + len].trim_end(),
+        };
+
+        out.push_str(&after[..start]);
+        match fingerprinted(url, base, inputs) {
+            Some(fingerprinted) => out.push_str(&fingerprinted),
+            None => out.push_str(url),
+        }
+        rest = &after[start + url.len()..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// `url`'s fingerprinted url, keeping any query or fragment
+fn fingerprinted(url: &str, base: &str, inputs: &Dict) -> Option<String> {
+    let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    match inputs.get(&resolve(base, path)?) {
+        Ok(Value::Str(fingerprinted)) => Some(format!("{fingerprinted}{suffix}")),
+        _ => None,
+    }
+}
+
+/// `path` against the url `base`, `None` if it's
+/// on another origin or not a path (`data:`, `#id`)
+fn resolve(base: &str, path: &str) -> Option<String> {
+    if path.is_empty() || path.starts_with("//") || path.contains(':') {
+        return None;
+    }
+    if path.starts_with('/') {
+        return Some(path.into());
+    }
+
+    let mut segments = base.split('/').collect::<Vec<_>>();
+    segments.pop();
+    for segment in path.split('/') {
+        match segment {
+            "." => {}
+            ".." if segments.len() > 1 => {
+                segments.pop();
+            }
+            ".." => {}
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::route::Route;
+    use mime_guess::{Mime, mime};
+    use typst::foundations::Bytes;
+    use typst::syntax::{FileId, VirtualPath};
+
+    fn slot(url: &str, mime: Mime, ty: SlotType) -> FileSlot {
+        FileSlot {
+            url: url.into(),
+            hidden: false,
+            mime,
+            file: Bytes::new(vec![]),
+            ty,
+        }
+    }
+
+    fn route(content: &str) -> Route {
+        let id = FileId::new_fake(VirtualPath::new("test"));
+        Route::compile(&id, content.into(), &mime::TEXT_CSS, true).unwrap()
+    }
+
+    #[test]
+    fn test_is_asset() {
+        assert!(is_asset(&slot("/a.css", mime::TEXT_CSS, SlotType::Scss)));
+        assert!(is_asset(&slot(
+            "/a.js",
+            mime::APPLICATION_JAVASCRIPT,
+            SlotType::Other
+        )));
+        assert!(is_asset(&slot(
+            "/a.woff2",
+            mime::FONT_WOFF2,
+            SlotType::Other
+        )));
+        assert!(is_asset(&slot("/a.svg", mime::IMAGE_SVG, SlotType::Other)));
+        assert!(!is_asset(&slot(
+            "/robots.txt",
+            mime::TEXT_PLAIN,
+            SlotType::Other
+        )));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint("/styles/main.css", 0x1234_5678_9abc_def0),
+            "/styles/main.9abcdef0.css"
+        );
+        assert_eq!(fingerprint("/v1.2/LICENSE", 1), "/v1.2/LICENSE.00000001");
+    }
+
+    #[test]
+    fn test_rewrite_css() {
+        let mut assets = Assets::default();
+        assets.insert("/icons/a.svg".into(), "/icons/a.0000000a.svg".into());
+        assets.insert("/fonts/b.woff2".into(), "/fonts/b.0000000b.woff2".into());
+        let inputs = inputs(&assets);
+        let rewrite = |css: &str| rewrite_css(css, "/styles/main.css", &inputs);
+
+        assert_eq!(
+            rewrite(r#"a{background:url("/icons/a.svg")}"#),
+            r#"a{background:url("/icons/a.0000000a.svg")}"#
+        );
+        assert_eq!(
+            rewrite("src:url('/fonts/b.woff2') format('woff2')"),
+            "src:url('/fonts/b.0000000b.woff2') format('woff2')"
+        );
+        assert_eq!(
+            rewrite("a{b:URL( /icons/a.svg ) c:url(../fonts/b.woff2#x)}"),
+            "a{b:URL( /icons/a.0000000a.svg ) c:url(/fonts/b.0000000b.woff2#x)}"
+        );
+
+        // not assets, or not paths
+        for css in [
+            "url(/icons/c.svg)",
+            "url(data:image/png;base64,AAAA)",
+            "url(https://example.com/icons/a.svg)",
+            "url(#clip)",
+            "url('/icons/a.svg",
+        ] {
+            assert_eq!(rewrite(css), css);
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        let id = FileId::new(None, VirtualPath::new("main.scss"));
+        let mut slots = Slots::default();
+        slots.insert(id, slot("/main.css", mime::TEXT_CSS, SlotType::Scss));
+
+        let v1 = route("a{}");
+        let v1_url = fingerprint("/main.css", v1.hash);
+        let mut table = RoutingTable::default();
+        table.insert("/main.css".into(), v1.clone());
+        table.insert(v1_url.clone(), v1);
+
+        let mut assets = Assets::default();
+        assert!(sync(&slots, &mut assets, &mut table));
+        assert_eq!(assets["/main.css"], v1_url);
+        assert!(!sync(&slots, &mut assets, &mut table));
+
+        // failed (error page served), keeps last fingerprint
+        table.insert("/main.css".into(), route("error"));
+        assert!(!sync(&slots, &mut assets, &mut table));
+        assert_eq!(assets["/main.css"], v1_url);
+
+        // changed, outdated route removed
+        let v2 = route("b{}");
+        let v2_url = fingerprint("/main.css", v2.hash);
+        table.insert("/main.css".into(), v2.clone());
+        table.insert(v2_url.clone(), v2);
+        assert!(sync(&slots, &mut assets, &mut table));
+        assert_eq!(assets["/main.css"], v2_url);
+        assert!(!table.contains_key(&v1_url));
+
+        // removed
+        slots.clear();
+        assert!(sync(&slots, &mut assets, &mut table));
+        assert!(assets.is_empty());
+        assert!(!table.contains_key(&v2_url));
+    }
+}
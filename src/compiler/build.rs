@@ -0,0 +1,100 @@
+//! What templates know about the build: `sys.inputs.build` and `today()`
+//!
+//! Both honor `SOURCE_DATE_EPOCH` so exports can be reproduced
+
+use std::path::Path;
+use std::process::Command;
+use time::OffsetDateTime;
+use typst::foundations::{Datetime, Dict, Value};
+
+use crate::compiler::Ctx;
+use crate::update;
+
+pub const INPUTS_KEY: &str = "build";
+
+/// `SOURCE_DATE_EPOCH` if set, otherwise now (UTC)
+pub fn now() -> OffsetDateTime {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| source_date(&epoch))
+        .unwrap_or_else(OffsetDateTime::now_utc)
+}
+
+fn source_date(epoch: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp(epoch.trim().parse().ok()?).ok()
+}
+
+/// `now()` as a Typst date, `offset` hours from UTC
+pub fn today(offset: Option<i64>) -> Option<Datetime> {
+    let offset = time::Duration::hours(offset.unwrap_or(0));
+    let date = now().checked_add(offset)?.date();
+    Some(Datetime::Date(date))
+}
+
+/// `mode` (`watch` or `prod`), `timestamp`, `commit` (`none` outside
+/// of git), `dirty` (uncommitted changes in the content root) and `version`
+pub fn inputs(ctx: &Ctx) -> Dict {
+    let now = now();
+    let timestamp = Datetime::from_ymd_hms(
+        now.year(),
+        now.month().into(),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+    );
+    let commit = git(&ctx.cfg.root, &["rev-parse", "HEAD"]);
+    let dirty = git(&ctx.cfg.root, &["status", "--porcelain", "--", "."])
+        .is_some_and(|status| !status.is_empty());
+    let mode = if ctx.watch.watch { "watch" } else { "prod" };
+
+    let mut dict = Dict::new();
+    dict.insert("mode".into(), Value::Str(mode.into()));
+    dict.insert(
+        "timestamp".into(),
+        timestamp.map_or(Value::None, Value::Datetime),
+    );
+    dict.insert(
+        "commit".into(),
+        commit.map_or(Value::None, |c| Value::Str(c.into())),
+    );
+    dict.insert("dirty".into(), Value::Bool(dirty));
+    dict.insert(
+        "version".into(),
+        Value::Str(env!("CARGO_PKG_VERSION").into()),
+    );
+    dict
+}
+
+/// Trimmed stdout of git run in `dir`, `None` if it failed
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let git = update::GIT
+        .get()
+        .map_or(Path::new("git"), |git| git.as_path());
+    let output = Command::new(git)
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn source_date_epoch() {
+        assert_eq!(
+            source_date("1700000000"),
+            Some(datetime!(2023-11-14 22:13:20 UTC))
+        );
+        assert_eq!(source_date(" 0\n"), Some(OffsetDateTime::UNIX_EPOCH));
+        assert_eq!(source_date("yesterday"), None);
+    }
+}
@@ -0,0 +1,157 @@
+//! Tracks which files each slot read while compiling, so the
+//! watcher only has to recompile the routes a change affects
+
+use crate::compiler::{fonts, query_prefix};
+use crate::indexer::{Changes, SlotType, Slots};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::sync::Mutex;
+use typst::syntax::FileId;
+
+/// The files each compiled slot read
+pub type Deps = FxHashMap<FileId, FxHashSet<FileId>>;
+
+/// Records every file a single slot's compilation reads
+#[derive(Debug, Default)]
+pub struct Tracker(Mutex<FxHashSet<FileId>>);
+
+impl Tracker {
+    pub fn access(&self, id: FileId) {
+        self.0.lock().unwrap().insert(id);
+    }
+
+    pub fn into_inner(self) -> FxHashSet<FileId> {
+        self.0.into_inner().unwrap()
+    }
+}
+
+/// Every slot which needs recompiling after `changes`:
+///  1. the changed files themselves
+///  2. slots which read a changed file (imports, `@use`, images, ..)
+///  3. pages querying a prefix that a page with changed metadata is under
+///  4. every page if an image's variants changed (`sys.inputs.images`)
+///  5. every page if a font changed (font use isn't tracked)
+pub fn affected(slots: &Slots, deps: &Deps, changes: &Changes) -> FxHashSet<FileId> {
+    let mut dirty = changes.ids.clone();
+    let fonts = fonts::changed(changes);
+
+    for (id, read) in deps {
+        if !read.is_disjoint(&changes.ids) {
+            dirty.insert(*id);
+        }
+    }
+
+    for (id, slot) in slots {
+        let SlotType::Typst(tslot) = &slot.ty else {
+            continue;
+        };
+        let queried = tslot.queries.as_ref().is_some_and(|queries| {
+            queries
+                .iter()
+                .filter_map(|(_, query)| query_prefix(query))
+                .any(|prefix| changes.meta.iter().any(|url| url.starts_with(prefix)))
+        });
+        if queried || changes.images || fonts {
+            dirty.insert(*id);
+        }
+    }
+
+    dirty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::{FileSlot, TypstSlot};
+    use mime_guess::mime;
+    use typst::foundations::{Bytes, Dict, Value};
+    use typst::syntax::{Source, VirtualPath};
+
+    fn id(path: &str) -> FileId {
+        FileId::new(None, VirtualPath::new(path))
+    }
+
+    fn page(path: &str, queries: Option<Dict>) -> (FileId, FileSlot) {
+        let slot = FileSlot {
+            url: format!("/{}", path.trim_end_matches(".typ")),
+            hidden: false,
+            mime: mime::TEXT_HTML_UTF_8,
+            file: Bytes::new(Vec::new()),
+            ty: SlotType::Typst(TypstSlot {
+                source: Source::new(id(path), String::new()),
+                page_meta: Some(Dict::new()),
+                queries,
+                css: None,
+                feed: None,
+            }),
+        };
+        (id(path), slot)
+    }
+
+    #[test]
+    fn affected_by_reads_and_queries() {
+        let blog_query = [("posts".into(), Value::Str("/blog/".into()))];
+        let slots: Slots = [
+            page("index.typ", None),
+            page("blog.typ", Some(blog_query.into_iter().collect())),
+            page("blog/post.typ", None),
+            page("about.typ", None),
+        ]
+        .into_iter()
+        .collect();
+
+        let template = id("_shared/template.typ");
+        let deps: Deps = [
+            (
+                id("index.typ"),
+                [id("index.typ"), template].into_iter().collect(),
+            ),
+            (id("about.typ"), [id("about.typ")].into_iter().collect()),
+        ]
+        .into_iter()
+        .collect();
+
+        // template change hits importers only
+        let changes = Changes {
+            ids: [template].into_iter().collect(),
+            ..Default::default()
+        };
+        let dirty = affected(&slots, &deps, &changes);
+        assert_eq!(
+            dirty,
+            [template, id("index.typ")]
+                .into_iter()
+                .collect::<FxHashSet<_>>()
+        );
+
+        // post metadata change hits the blog listing
+        let changes = Changes {
+            ids: [id("blog/post.typ")].into_iter().collect(),
+            meta: vec!["/blog/post".into()],
+            ..Default::default()
+        };
+        let dirty = affected(&slots, &deps, &changes);
+        assert_eq!(
+            dirty,
+            [id("blog/post.typ"), id("blog.typ")]
+                .into_iter()
+                .collect::<FxHashSet<_>>()
+        );
+
+        // new image variants hit every page
+        let changes = Changes {
+            ids: [id("img/a.png")].into_iter().collect(),
+            images: true,
+            ..Default::default()
+        };
+        let dirty = affected(&slots, &deps, &changes);
+        assert_eq!(dirty.len(), 5);
+
+        // so do fonts
+        let changes = Changes {
+            ids: [id("fonts/a.woff2")].into_iter().collect(),
+            ..Default::default()
+        };
+        let dirty = affected(&slots, &deps, &changes);
+        assert_eq!(dirty.len(), 5);
+    }
+}
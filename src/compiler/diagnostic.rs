@@ -0,0 +1,158 @@
+//! Compile errors in a structured form, so they can be
+//! shown in the browser (see `watcher`) and not just stderr
+
+use serde_json::{Value, json};
+use std::fmt;
+
+/// A Typst or SCSS compilation failure
+/// `rendered` holds the full (uncolored) terminal output
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub msg: &'static str,
+    pub rendered: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub hints: Vec<String>,
+    pub location: Option<Location>,
+    /// Stacktrace-like helpers (ex. `error occurred in this call`)
+    pub trace: Vec<(String, Option<Location>)>,
+}
+
+#[derive(Debug)]
+pub struct Location {
+    pub file: String,
+    /// 1-indexed
+    pub line: usize,
+    /// 1-indexed
+    pub column: usize,
+    /// The entire source line
+    pub snippet: String,
+    /// Columns of `snippet` to highlight
+    pub highlight: (usize, usize),
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.msg)
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+impl Diagnostic {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "message": self.message,
+            "hints": self.hints,
+            "location": self.location.as_ref().map(Location::to_json),
+            "trace": self.trace.iter().map(|(message, location)| json!({
+                "message": message,
+                "location": location.as_ref().map(Location::to_json),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Location {
+    /// Make a location from 0-indexed line & column
+    pub fn new(file: String, line: usize, column: usize, snippet: &str, end: usize) -> Self {
+        let snippet = snippet.trim_end_matches(['\r', '\n']).to_string();
+        let end = end
+            .max(column + 1)
+            .min(snippet.chars().count().max(column + 1));
+        Self {
+            file,
+            line: line + 1,
+            column: column + 1,
+            snippet,
+            highlight: (column, end),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "file": self.file,
+            "line": self.line,
+            "column": self.column,
+            "snippet": self.snippet,
+            "highlight": [self.highlight.0, self.highlight.1],
+        })
+    }
+}
+
+impl From<Box<grass::Error>> for Diagnostics {
+    fn from(e: Box<grass::Error>) -> Self {
+        let rendered = e.to_string();
+        let diagnostic = match e.kind() {
+            grass::ErrorKind::ParseError { message, loc, .. } => {
+                let end = match loc.end.line == loc.begin.line {
+                    true => loc.end.column,
+                    false => usize::MAX,
+                };
+                Diagnostic {
+                    message,
+                    hints: Vec::new(),
+                    location: Some(Location::new(
+                        loc.file.name().to_string(),
+                        loc.begin.line,
+                        loc.begin.column,
+                        loc.file.source_line(loc.begin.line),
+                        end,
+                    )),
+                    trace: Vec::new(),
+                }
+            }
+            _ => Diagnostic {
+                message: rendered.clone(),
+                hints: Vec::new(),
+                location: None,
+                trace: Vec::new(),
+            },
+        };
+
+        Diagnostics {
+            msg: "scss compilation failed",
+            rendered,
+            diagnostics: vec![diagnostic],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_highlight() {
+        let loc = Location::new("a.typ".into(), 0, 2, "#foo()\n", 5);
+        assert_eq!((loc.line, loc.column), (1, 3));
+        assert_eq!(loc.snippet, "#foo()");
+        assert_eq!(loc.highlight, (2, 5));
+
+        // past the end of the line
+        let loc = Location::new("a.typ".into(), 3, 2, "#foo", usize::MAX);
+        assert_eq!(loc.highlight, (2, 4));
+
+        // empty range still highlights something
+        let loc = Location::new("a.typ".into(), 3, 4, "#foo", 4);
+        assert_eq!(loc.highlight, (4, 5));
+    }
+
+    #[test]
+    fn scss_error() {
+        let err = grass::from_string("a { color: red", &grass::Options::default()).unwrap_err();
+        let diag = Diagnostics::from(err);
+
+        assert_eq!(diag.diagnostics.len(), 1);
+        let loc = diag.diagnostics[0].location.as_ref().unwrap();
+        assert_eq!(loc.line, 1);
+        assert_eq!(loc.snippet, "a { color: red");
+
+        let json = diag.diagnostics[0].to_json();
+        assert_eq!(json["location"]["line"], 1);
+    }
+}
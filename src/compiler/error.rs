@@ -0,0 +1,134 @@
+//! Routes served in place of pages which failed to compile,
+//! so one broken post doesn't take the whole site down
+
+use crate::compiler::diagnostic::Diagnostics;
+use crate::compiler::escape;
+use crate::compiler::typst::reload_script;
+use crate::web::route::Route;
+use crate::{ROUTING_TABLE, WatchArgs};
+use anyhow::{Error, Result};
+use mime_guess::mime;
+use typst::syntax::{FileId, VirtualPath};
+
+const STATUS: &str = "500 Internal Server Error";
+
+/// In watch mode show everything that went wrong, otherwise keep serving
+/// the last good version (if there is one) or `error_page` (`500.typ`)
+pub fn fallback(
+    url: &str,
+    error: &Error,
+    watch: &WatchArgs,
+    error_page: Option<&Route>,
+) -> Result<Route> {
+    if !watch.watch {
+        if let Some(route) = ROUTING_TABLE.load().get(url) {
+            return Ok(route.clone());
+        }
+        if let Some(route) = error_page {
+            return Ok(route.clone());
+        }
+    }
+
+    let body = if watch.watch {
+        details(error, watch)
+    } else {
+        page("<h1>500 Internal Server Error</h1>")
+    };
+
+    let id = FileId::new_fake(VirtualPath::new(url));
+    Route::compile_status(
+        &id,
+        body.into(),
+        &mime::TEXT_HTML_UTF_8,
+        STATUS,
+        watch.watch,
+    )
+}
+
+fn details(error: &Error, watch: &WatchArgs) -> String {
+    let mut pre = escape(&format!("{error:#}"));
+    if let Some(diag) = error.chain().find_map(|e| e.downcast_ref::<Diagnostics>()) {
+        pre.push_str("\n\n");
+        pre.push_str(&escape(&diag.rendered));
+    }
+
+    page(&format!(
+        "<h1>Build Error</h1><pre>{pre}</pre>{}",
+        reload_script(watch)
+    ))
+}
+
+fn page(body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Error</title></head>\
+         <body>{body}</body></html>"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn watch(watch: bool) -> WatchArgs {
+        WatchArgs {
+            watch,
+            watch_address: [127, 0, 0, 1].into(),
+            watch_port: 3233,
+        }
+    }
+
+    #[test]
+    fn dev_shows_details() {
+        let error = anyhow!(Diagnostics {
+            msg: "compilation failed",
+            rendered: "unknown variable: <foo>".into(),
+            diagnostics: Vec::new(),
+        })
+        .context("/blog/post.typ");
+
+        let body = details(&error, &watch(true));
+        assert!(body.contains("/blog/post.typ: compilation failed"));
+        assert!(body.contains("unknown variable: &lt;foo&gt;"));
+        assert!(body.contains("WebSocket"));
+    }
+
+    #[test]
+    fn fallback_is_500() {
+        let route = fallback("/missing-in-table", &anyhow!("oops"), &watch(false), None).unwrap();
+        assert!(route.identity.head.starts_with(b"HTTP/1.1 500"));
+        assert!(!route.identity.body.windows(4).any(|w| w == b"oops"));
+    }
+
+    #[test]
+    fn fallback_uses_error_page() {
+        let id = FileId::new_fake(VirtualPath::new("500.typ"));
+        let page = Route::compile_status(
+            &id,
+            "<p>custom</p>".into(),
+            &mime::TEXT_HTML_UTF_8,
+            STATUS,
+            true,
+        )
+        .unwrap();
+
+        let route = fallback(
+            "/missing-in-table",
+            &anyhow!("oops"),
+            &watch(false),
+            Some(&page),
+        )
+        .unwrap();
+        assert_eq!(route.identity, page.identity);
+
+        // details are more useful in development
+        let route = fallback(
+            "/missing-in-table",
+            &anyhow!("oops"),
+            &watch(true),
+            Some(&page),
+        )
+        .unwrap();
+        assert!(route.identity.body.windows(4).any(|w| w == b"oops"));
+    }
+}
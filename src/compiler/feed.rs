@@ -0,0 +1,377 @@
+//! Atom and RSS 2.0 feeds for collection pages declaring
+//! `#metadata((feed: "/blog/")) <feed>`
+//!
+//! Every dated page (`written`/`updated`) under the prefix becomes an
+//! entry. Optional keys: `author`, and `content: true` to include the
+//! compiled HTML of each page (its `<main>`)
+
+use crate::compiler::{Ctx, absolute, escape, under_prefix};
+use crate::indexer::SlotType;
+use crate::{RoutingTable, web::route::Route};
+use anyhow::{Context, Error, Result, bail};
+use mime_guess::Mime;
+use std::fmt::Write;
+use std::path::Path;
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+use typst::foundations::{Dict, Value};
+use typst::syntax::{FileId, VirtualPath};
+
+const ATOM_FILE: &str = "feed.xml";
+const RSS_FILE: &str = "rss.xml";
+
+struct Feed<'a> {
+    base_url: &'a str,
+    /// Url of the collection page
+    url: &'a str,
+    title: &'a str,
+    desc: Option<&'a str>,
+    author: Option<&'a str>,
+    entries: Vec<Entry<'a>>,
+    /// Latest update of any entry
+    updated: OffsetDateTime,
+}
+
+struct Entry<'a> {
+    url: &'a str,
+    title: &'a str,
+    desc: Option<&'a str>,
+    published: OffsetDateTime,
+    updated: OffsetDateTime,
+    /// Compiled HTML
+    content: Option<String>,
+}
+
+/// Generates an Atom (`feed.xml`) and RSS (`rss.xml`) route next to each feed page
+///
+/// Invalid feeds and entries are recorded in `ctx.report` and left out
+pub fn generate(ctx: &Ctx, routes: &RoutingTable) -> Result<Vec<(String, Route)>> {
+    let mut out = Vec::new();
+
+    for (id, slot) in ctx.slots.iter().filter(|(_, slot)| !slot.hidden) {
+        let SlotType::Typst(tslot) = &slot.ty else {
+            continue;
+        };
+        let (Some(cfg), Some(page_meta)) = (&tslot.feed, &tslot.page_meta) else {
+            continue;
+        };
+
+        let feed = match Feed::new(ctx, routes, &slot.url, page_meta, cfg) {
+            Ok(feed) => feed,
+            Err(e) => {
+                let e = e.context(format!("generating feed for {}", slot.url));
+                ctx.report.error(id.vpath().as_rootless_path(), None, e);
+                continue;
+            }
+        };
+
+        let base = slot.url.trim_end_matches('/');
+        for (file, mime, xml) in [
+            (ATOM_FILE, "application/atom+xml", feed.atom()),
+            (RSS_FILE, "application/rss+xml", feed.rss()),
+        ] {
+            let url = format!("{base}/{file}");
+            let id = FileId::new_fake(VirtualPath::new(&url));
+            let mime = mime.parse::<Mime>()?;
+            let route = Route::compile(&id, xml.into(), &mime, ctx.watch.watch)?;
+            out.push((url, route));
+        }
+    }
+
+    Ok(out)
+}
+
+impl<'a> Feed<'a> {
+    fn new(
+        ctx: &'a Ctx,
+        routes: &RoutingTable,
+        url: &'a str,
+        page_meta: &'a Dict,
+        cfg: &'a Dict,
+    ) -> Result<Self> {
+        let Some(prefix) = get_str(cfg, "feed") else {
+            bail!("`feed` must be a url prefix (ex. \"/blog/\")");
+        };
+        let content = matches!(cfg.get("content"), Ok(Value::Bool(true)));
+
+        let mut entries = Vec::new();
+        for (entry_url, meta) in under_prefix(ctx.metamap, prefix) {
+            let dates = get_date(meta, "written")
+                .and_then(|written| Ok((written, get_date(meta, "updated")?)));
+            let (written, updated) = match dates {
+                Ok(dates) => dates,
+                Err(e) => {
+                    report_entry(ctx, url, entry_url, e);
+                    continue;
+                }
+            };
+            let Some(published) = written.or(updated) else {
+                continue;
+            };
+
+            entries.push(Entry {
+                url: entry_url,
+                title: get_str(meta, "title").unwrap_or(entry_url),
+                desc: get_str(meta, "desc"),
+                published,
+                updated: updated.unwrap_or(published).max(published),
+                content: content
+                    .then(|| {
+                        routes
+                            .get(entry_url)
+                            .and_then(|route| main_html(&route.identity.body))
+                    })
+                    .flatten(),
+            });
+        }
+
+        entries.sort_by(|a, b| b.updated.cmp(&a.updated).then(a.url.cmp(b.url)));
+
+        Ok(Feed {
+            base_url: &ctx.cfg.base_url,
+            url,
+            title: get_str(page_meta, "title").unwrap_or(url),
+            desc: get_str(page_meta, "desc"),
+            author: get_str(cfg, "author"),
+            updated: entries
+                .iter()
+                .map(|entry| entry.updated)
+                .max()
+                .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+            entries,
+        })
+    }
+
+    fn atom(&self) -> String {
+        let mut xml = String::with_capacity(4096);
+        let link = absolute(self.base_url, self.url);
+        let base = self.url.trim_end_matches('/');
+
+        xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        xml.push('\n');
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push('\n');
+        writeln!(xml, "  <title>{}</title>", escape(self.title)).unwrap();
+        if let Some(desc) = self.desc {
+            writeln!(xml, "  <subtitle>{}</subtitle>", escape(desc)).unwrap();
+        }
+        writeln!(
+            xml,
+            r#"  <link href="{}" rel="self" type="application/atom+xml"/>"#,
+            absolute(self.base_url, &format!("{base}/{ATOM_FILE}"))
+        )
+        .unwrap();
+        writeln!(xml, r#"  <link href="{link}"/>"#).unwrap();
+        writeln!(xml, "  <id>{link}</id>").unwrap();
+        writeln!(xml, "  <updated>{}</updated>", rfc3339(self.updated)).unwrap();
+        if let Some(author) = self.author {
+            writeln!(xml, "  <author><name>{}</name></author>", escape(author)).unwrap();
+        }
+
+        for entry in &self.entries {
+            let link = absolute(self.base_url, entry.url);
+            writeln!(xml, "  <entry>").unwrap();
+            writeln!(xml, "    <title>{}</title>", escape(entry.title)).unwrap();
+            writeln!(xml, r#"    <link href="{link}"/>"#).unwrap();
+            writeln!(xml, "    <id>{link}</id>").unwrap();
+            writeln!(
+                xml,
+                "    <published>{}</published>",
+                rfc3339(entry.published)
+            )
+            .unwrap();
+            writeln!(xml, "    <updated>{}</updated>", rfc3339(entry.updated)).unwrap();
+            if let Some(desc) = entry.desc {
+                writeln!(xml, "    <summary>{}</summary>", escape(desc)).unwrap();
+            }
+            if let Some(content) = &entry.content {
+                writeln!(
+                    xml,
+                    r#"    <content type="html" xml:base="{link}">{}</content>"#,
+                    escape(content)
+                )
+                .unwrap();
+            }
+            writeln!(xml, "  </entry>").unwrap();
+        }
+
+        xml.push_str("</feed>");
+        xml
+    }
+
+    fn rss(&self) -> String {
+        let mut xml = String::with_capacity(4096);
+        let base = self.url.trim_end_matches('/');
+
+        xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        xml.push('\n');
+        xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">"#);
+        xml.push('\n');
+        xml.push_str("<channel>\n");
+        writeln!(xml, "  <title>{}</title>", escape(self.title)).unwrap();
+        writeln!(xml, "  <link>{}</link>", absolute(self.base_url, self.url)).unwrap();
+        writeln!(
+            xml,
+            "  <description>{}</description>",
+            escape(self.desc.unwrap_or(self.title))
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            r#"  <atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+            absolute(self.base_url, &format!("{base}/{RSS_FILE}"))
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "  <lastBuildDate>{}</lastBuildDate>",
+            rfc2822(self.updated)
+        )
+        .unwrap();
+
+        for entry in &self.entries {
+            let link = absolute(self.base_url, entry.url);
+            writeln!(xml, "  <item>").unwrap();
+            writeln!(xml, "    <title>{}</title>", escape(entry.title)).unwrap();
+            writeln!(xml, "    <link>{link}</link>").unwrap();
+            writeln!(xml, r#"    <guid isPermaLink="true">{link}</guid>"#).unwrap();
+            writeln!(xml, "    <pubDate>{}</pubDate>", rfc2822(entry.published)).unwrap();
+            if let Some(desc) = entry.content.as_deref().or(entry.desc) {
+                writeln!(xml, "    <description>{}</description>", escape(desc)).unwrap();
+            }
+            writeln!(xml, "  </item>").unwrap();
+        }
+
+        xml.push_str("</channel>\n</rss>");
+        xml
+    }
+}
+
+/// Record an entry left out of the feed at `url`
+/// against the page it came from
+fn report_entry(ctx: &Ctx, url: &str, entry_url: &str, e: Error) {
+    let path = ctx
+        .slots
+        .iter()
+        .find(|(_, slot)| slot.url == entry_url)
+        .map(|(id, _)| id.vpath().as_rootless_path())
+        .unwrap_or(Path::new(entry_url));
+    let e = e.context(format!("{entry_url}: left out of the feed for {url}"));
+    ctx.report.error(path, None, e);
+}
+
+fn get_str<'a>(dict: &'a Dict, key: &str) -> Option<&'a str> {
+    match dict.get(key) {
+        Ok(Value::Str(s)) => Some(s.as_str()),
+        _ => None,
+    }
+}
+
+/// Parse a `YYYY-MM-DD` metadata date (as midnight UTC)
+pub(super) fn get_date(dict: &Dict, key: &str) -> Result<Option<OffsetDateTime>> {
+    let Some(s) = get_str(dict, key) else {
+        return Ok(None);
+    };
+    let date = Date::parse(s, format_description!("[year]-[month]-[day]"))
+        .with_context(|| format!("`{key}: \"{s}\"` is not a YYYY-MM-DD date"))?;
+    Ok(Some(date.midnight().assume_utc()))
+}
+
+fn rfc3339(dt: OffsetDateTime) -> String {
+    dt.format(&Rfc3339).unwrap()
+}
+
+fn rfc2822(dt: OffsetDateTime) -> String {
+    dt.format(&Rfc2822).unwrap()
+}
+
+/// Pull the contents of `<main>` out of a pre-serialized HTML response
+fn main_html(response: &[u8]) -> Option<String> {
+    let html = String::from_utf8_lossy(response);
+    let start = html.find("<main")?;
+    let start = start + html[start..].find('>')? + 1;
+    let end = start + html[start..].rfind("</main>")?;
+    Some(html[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry<'a>(url: &'a str, date: &str, content: Option<&str>) -> Entry<'a> {
+        let dt = get_date(
+            &[("d".into(), Value::Str(date.into()))]
+                .into_iter()
+                .collect(),
+            "d",
+        )
+        .unwrap()
+        .unwrap();
+        Entry {
+            url,
+            title: "A & B",
+            desc: Some("desc"),
+            published: dt,
+            updated: dt,
+            content: content.map(str::to_string),
+        }
+    }
+
+    fn feed<'a>(entries: Vec<Entry<'a>>) -> Feed<'a> {
+        Feed {
+            base_url: "https://liamsnow.com",
+            url: "/blog",
+            title: "Blog",
+            desc: None,
+            author: Some("Me"),
+            updated: entries[0].updated,
+            entries,
+        }
+    }
+
+    #[test]
+    fn dates() {
+        let dict = [
+            ("ok".into(), Value::Str("2025-03-14".into())),
+            ("bad".into(), Value::Str("March 14th".into())),
+        ]
+        .into_iter()
+        .collect();
+
+        let dt = get_date(&dict, "ok").unwrap().unwrap();
+        assert_eq!(rfc3339(dt), "2025-03-14T00:00:00Z");
+        assert_eq!(rfc2822(dt), "Fri, 14 Mar 2025 00:00:00 +0000");
+        assert!(get_date(&dict, "missing").unwrap().is_none());
+        assert!(get_date(&dict, "bad").is_err());
+    }
+
+    #[test]
+    fn atom() {
+        let xml = feed(vec![entry("/blog/post", "2025-03-14", Some("<p>hi</p>"))]).atom();
+
+        assert!(xml.contains(r#"<link href="https://liamsnow.com/blog/feed.xml" rel="self""#));
+        assert!(xml.contains("<id>https://liamsnow.com/blog/post</id>"));
+        assert!(xml.contains("<title>A &amp; B</title>"));
+        assert!(xml.contains("<updated>2025-03-14T00:00:00Z</updated>"));
+        assert!(xml.contains("&lt;p&gt;hi&lt;/p&gt;</content>"));
+        assert!(xml.contains("<author><name>Me</name></author>"));
+    }
+
+    #[test]
+    fn rss() {
+        let xml = feed(vec![entry("/blog/post", "2025-03-14", None)]).rss();
+
+        assert!(xml.contains("<link>https://liamsnow.com/blog</link>"));
+        assert!(xml.contains("<pubDate>Fri, 14 Mar 2025 00:00:00 +0000</pubDate>"));
+        assert!(xml.contains("<description>desc</description>"));
+        assert!(xml.contains(r#"href="https://liamsnow.com/blog/rss.xml""#));
+    }
+
+    #[test]
+    fn test_main_html() {
+        let res = b"HTTP/1.1 200 OK\r\n\r\n<html><body><nav>x</nav><main class=\"a\"><p>hi</p></main></body></html>";
+        assert_eq!(main_html(res).as_deref(), Some("<p>hi</p>"));
+        assert_eq!(main_html(b"<html></html>"), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! The fonts Typst can use: every font file in the content tree,
+//! then optionally Typst's embedded fonts and the system's
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use typst::foundations::Bytes;
+use typst::syntax::FileId;
+use typst::text::{Font, FontBook, FontInfo};
+use typst::utils::LazyHash;
+
+use crate::BuildArgs;
+use crate::compiler::woff2;
+use crate::indexer::{Changes, Slots};
+use crate::report::Report;
+
+const EXTENSIONS: [&str; 5] = ["ttf", "otf", "ttc", "otc", "woff2"];
+
+/// Built once per build and shared by every page's world
+pub struct Fonts {
+    book: LazyHash<FontBook>,
+    fonts: Vec<FontSlot>,
+}
+
+enum FontSlot {
+    Loaded(Font),
+    /// A system font, only read if a page uses it
+    System {
+        path: PathBuf,
+        index: u32,
+        font: OnceLock<Option<Font>>,
+    },
+}
+
+impl Fonts {
+    /// Content fonts come first (sorted by path) so they
+    /// win over embedded or system fonts of the same family
+    pub fn load(slots: &Slots, cfg: &BuildArgs, report: &Report) -> Self {
+        let mut book = FontBook::new();
+        let mut fonts = Vec::new();
+
+        let mut files = slots
+            .iter()
+            .filter(|(id, _)| is_font(id))
+            .map(|(id, slot)| (id.vpath().as_rootless_path(), &slot.file))
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(path, _)| *path);
+        for (path, data) in files {
+            match parse(data) {
+                Ok(parsed) => {
+                    for font in parsed {
+                        book.push(font.info().clone());
+                        fonts.push(FontSlot::Loaded(font));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("warning: font {}: {e:#}", path.display());
+                    report.warning();
+                }
+            }
+        }
+
+        if cfg.embedded_fonts {
+            for data in typst_assets::fonts() {
+                for font in Font::iter(Bytes::new(data)) {
+                    book.push(font.info().clone());
+                    fonts.push(FontSlot::Loaded(font));
+                }
+            }
+        }
+
+        if cfg.system_fonts {
+            let mut db = fontdb::Database::new();
+            db.load_system_fonts();
+            for face in db.faces() {
+                let fontdb::Source::File(path) = &face.source else {
+                    continue;
+                };
+                let info = db.with_face_data(face.id, FontInfo::new).flatten();
+                if let Some(info) = info {
+                    book.push(info);
+                    fonts.push(FontSlot::System {
+                        path: path.clone(),
+                        index: face.index,
+                        font: OnceLock::new(),
+                    });
+                }
+            }
+        }
+
+        Self {
+            book: LazyHash::new(book),
+            fonts,
+        }
+    }
+
+    pub fn book(&self) -> &LazyHash<FontBook> {
+        &self.book
+    }
+
+    pub fn font(&self, index: usize) -> Option<Font> {
+        match self.fonts.get(index)? {
+            FontSlot::Loaded(font) => Some(font.clone()),
+            FontSlot::System { path, index, font } => font
+                .get_or_init(|| {
+                    let data = std::fs::read(path).ok()?;
+                    Font::new(Bytes::new(data), *index)
+                })
+                .clone(),
+        }
+    }
+}
+
+/// If `changes` touched a font, so `Fonts` needs reloading
+pub fn changed(changes: &Changes) -> bool {
+    changes.ids.iter().any(is_font)
+}
+
+fn is_font(id: &FileId) -> bool {
+    is_font_path(id.vpath().as_rootless_path())
+}
+
+fn is_font_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Every font in a font file or collection, decoding WOFF2
+fn parse(data: &Bytes) -> anyhow::Result<Vec<Font>> {
+    let data = match woff2::is_woff2(data) {
+        true => Bytes::new(woff2::decode(data)?),
+        false => data.clone(),
+    };
+    let fonts = Font::iter(data).collect::<Vec<_>>();
+    anyhow::ensure!(!fonts.is_empty(), "no fonts in file");
+    Ok(fonts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::{FileSlot, SlotType};
+    use typst::syntax::VirtualPath;
+    use typst::text::FontVariant;
+
+    #[test]
+    fn font_paths() {
+        assert!(is_font_path(Path::new("fonts/a.woff2")));
+        assert!(is_font_path(Path::new("fonts/a.TTF")));
+        assert!(!is_font_path(Path::new("fonts/a.woff")));
+        assert!(!is_font_path(Path::new("fonts")));
+    }
+
+    #[test]
+    fn content_fonts() {
+        let slots = std::fs::read_dir("content/fonts")
+            .unwrap()
+            .map(|entry| {
+                let path = Path::new("fonts").join(entry.unwrap().file_name());
+                let slot = FileSlot {
+                    url: format!("/{}", path.display()),
+                    hidden: false,
+                    mime: mime_guess::from_path(&path).first_or_octet_stream(),
+                    file: Bytes::new(std::fs::read(Path::new("content").join(&path)).unwrap()),
+                    ty: SlotType::Other,
+                };
+                (FileId::new(None, VirtualPath::new(path)), slot)
+            })
+            .collect::<Slots>();
+        let report = Report::default();
+        let cfg = BuildArgs {
+            root: "content".into(),
+            base_url: String::new(),
+            system_fonts: false,
+            embedded_fonts: false,
+            package_path: None,
+            package_cache_path: None,
+        };
+        let fonts = Fonts::load(&slots, &cfg, &report);
+        assert_eq!(report.num_warnings(), 0);
+
+        let index = fonts
+            .book()
+            .select("space grotesk", FontVariant::default())
+            .expect("Space Grotesk is in content/fonts");
+        let font = fonts.font(index).unwrap();
+        assert_eq!(font.info().family, "Space Grotesk");
+        assert!(fonts.font(fonts.fonts.len()).is_none());
+    }
+}
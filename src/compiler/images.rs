@@ -0,0 +1,301 @@
+//! Encodes the resized variants of each image (see `indexer::ImageSlot`)
+//! and describes them to templates as `sys.inputs.images`
+
+use crate::compiler::{Assets, Ctx};
+use crate::indexer::{ImageSlot, SlotType, Slots};
+use crate::web::route::Route;
+use anyhow::{Context, Result, bail};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rustc_hash::FxHashMap;
+use std::io::Cursor;
+use typst::foundations::{Array, Dict, Value};
+use typst::syntax::{FileId, VirtualPath};
+
+pub const INPUTS_KEY: &str = "images";
+
+const AVIF_QUALITY: u8 = 70;
+/// 1 (slowest, smallest) to 10
+const AVIF_SPEED: u8 = 6;
+/// For `fast` (watch mode) builds, same variants but bigger files
+const AVIF_SPEED_FAST: u8 = 10;
+const WEBP_QUALITY: f32 = 80.0;
+const JPEG_QUALITY: u8 = 80;
+
+/// Routes for every variant of `image`
+pub fn compile(ctx: &Ctx, file: &[u8], image: &ImageSlot) -> Result<Vec<(String, Route)>> {
+    let img = decode(file)?;
+
+    let mut widths = image.variants.iter().map(|v| v.width).collect::<Vec<_>>();
+    widths.sort_unstable();
+    widths.dedup();
+
+    let resized = widths
+        .into_par_iter()
+        .map(|w| {
+            let img = match w == img.width() {
+                true => img.clone(),
+                false => {
+                    let h = image.variants.iter().find(|v| v.width == w).unwrap().height;
+                    img.resize_exact(w, h, FilterType::Lanczos3)
+                }
+            };
+            (w, img)
+        })
+        .collect::<FxHashMap<_, _>>();
+
+    image
+        .variants
+        .par_iter()
+        .map(|variant| {
+            let bytes = encode(&resized[&variant.width], variant.format, ctx.watch.watch)
+                .with_context(|| format!("encoding {}", variant.url))?;
+            let id = FileId::new_fake(VirtualPath::new(&variant.url));
+            let mime = variant.format.to_mime_type().parse()?;
+            let route = Route::compile_immutable(&id, bytes, &mime, ctx.watch.watch)?;
+            Ok((variant.url.clone(), route))
+        })
+        .collect()
+}
+
+/// Decodes to 8-bit RGB(A), applying EXIF orientation
+fn decode(file: &[u8]) -> Result<DynamicImage> {
+    let mut decoder = ImageReader::new(Cursor::new(file))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+
+    Ok(match img.has_alpha() {
+        true => DynamicImage::ImageRgba8(img.into_rgba8()),
+        false => DynamicImage::ImageRgb8(img.into_rgb8()),
+    })
+}
+
+/// Lossy AVIF, WebP and JPEG, lossless PNG
+///
+/// `fast` trades AVIF size for encode time, which is otherwise
+/// far longer than the rest
+fn encode(img: &DynamicImage, format: ImageFormat, fast: bool) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match format {
+        ImageFormat::Avif => {
+            let speed = if fast { AVIF_SPEED_FAST } else { AVIF_SPEED };
+            let encoder = AvifEncoder::new_with_speed_quality(&mut buf, speed, AVIF_QUALITY)
+                // already parallel over variants
+                .with_num_threads(Some(1));
+            img.write_with_encoder(encoder)?
+        }
+        // through libwebp since `image` can only encode lossless WebP
+        ImageFormat::WebP => {
+            let (width, height) = (img.width(), img.height());
+            let encoder = match img {
+                DynamicImage::ImageRgba8(rgba) => webp::Encoder::from_rgba(rgba, width, height),
+                DynamicImage::ImageRgb8(rgb) => webp::Encoder::from_rgb(rgb, width, height),
+                _ => bail!("expected 8-bit RGB(A)"),
+            };
+            buf.extend_from_slice(&encoder.encode(WEBP_QUALITY));
+        }
+        ImageFormat::Jpeg => {
+            img.write_with_encoder(JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY))?
+        }
+        ImageFormat::Png => img.write_with_encoder(PngEncoder::new(&mut buf))?,
+        format => bail!("cannot encode {format:?}"),
+    }
+    Ok(buf)
+}
+
+/// Every visible image's url to its size and variants:
+/// ```typst
+/// (
+///   width: 4000,
+///   height: 3000,
+///   // fallback, original (fingerprinted) included
+///   srcset: "/a-480w.<hash>.jpg 480w, .., /a.<hash>.jpg 4000w",
+///   sources: (
+///     (type: "image/avif", srcset: "/a-480w.<hash>.avif 480w, .."),
+///     (type: "image/webp", srcset: "/a-480w.<hash>.webp 480w, .."),
+///   ),
+/// )
+/// ```
+pub fn inputs(slots: &Slots, assets: &Assets) -> Dict {
+    let mut images = slots
+        .values()
+        .filter(|slot| !slot.hidden)
+        .filter_map(|slot| match &slot.ty {
+            SlotType::Image(image) => Some((&slot.url, image)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    images.sort_by_key(|(url, _)| *url);
+
+    images
+        .into_iter()
+        .map(|(url, image)| {
+            let mut srcsets: Vec<(ImageFormat, String)> = Vec::new();
+            for variant in &image.variants {
+                let entry = format!("{} {}w", variant.url, variant.width);
+                match srcsets.iter_mut().find(|(f, _)| *f == variant.format) {
+                    Some((_, srcset)) => {
+                        srcset.push_str(", ");
+                        srcset.push_str(&entry);
+                    }
+                    None => srcsets.push((variant.format, entry)),
+                }
+            }
+
+            // the fallback format is the only one without the original width
+            let original = assets.get(url.as_str()).unwrap_or(url);
+            let original = format!("{original} {}w", image.width);
+            let fallback = match srcsets.pop() {
+                Some((ImageFormat::Jpeg | ImageFormat::Png, srcset)) => {
+                    format!("{srcset}, {original}")
+                }
+                other => {
+                    srcsets.extend(other);
+                    original
+                }
+            };
+
+            let sources = srcsets
+                .into_iter()
+                .map(|(format, srcset)| {
+                    let source: Dict = [
+                        ("type".into(), Value::Str(format.to_mime_type().into())),
+                        ("srcset".into(), Value::Str(srcset.into())),
+                    ]
+                    .into_iter()
+                    .collect();
+                    Value::Dict(source)
+                })
+                .collect::<Array>();
+
+            let info: Dict = [
+                ("width".into(), Value::Int(image.width.into())),
+                ("height".into(), Value::Int(image.height.into())),
+                ("srcset".into(), Value::Str(fallback.into())),
+                ("sources".into(), Value::Array(sources)),
+            ]
+            .into_iter()
+            .collect();
+
+            (url.as_str().into(), Value::Dict(info))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::Variant;
+    use image::{RgbImage, RgbaImage};
+
+    #[test]
+    fn roundtrip() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 32, |x, y| {
+            image::Rgb([x as u8 * 4, y as u8 * 8, 128])
+        }));
+
+        for format in [ImageFormat::WebP, ImageFormat::Jpeg, ImageFormat::Png] {
+            let bytes = encode(&img, format, false).unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap(), format);
+            let decoded = decode(&bytes).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (64, 32));
+        }
+
+        assert!(encode(&img, ImageFormat::Gif, false).is_err());
+    }
+
+    #[test]
+    fn lossy() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 32, |x, y| {
+            image::Rgba([x as u8 * 4, y as u8 * 8, 128, 255 - x as u8])
+        }));
+
+        // `VP8 ` chunk (lossy) rather than `VP8L`
+        let webp = encode(&img, ImageFormat::WebP, false).unwrap();
+        assert_eq!(&webp[12..16], b"VP8X");
+        assert!(webp.windows(4).any(|chunk| chunk == b"VP8 "));
+        assert!(!webp.windows(4).any(|chunk| chunk == b"VP8L"));
+
+        // decoding AVIF needs dav1d, so just check the container
+        for fast in [false, true] {
+            let avif = encode(&img, ImageFormat::Avif, fast).unwrap();
+            assert_eq!(image::guess_format(&avif).unwrap(), ImageFormat::Avif);
+        }
+    }
+
+    #[test]
+    fn srcsets() {
+        let variant = |url: &str, width, format| Variant {
+            url: url.into(),
+            width,
+            height: width / 2,
+            format,
+        };
+        let mut slots = Slots::default();
+        slots.insert(
+            FileId::new(None, VirtualPath::new("a.jpg")),
+            crate::indexer::FileSlot {
+                url: "/a.jpg".into(),
+                hidden: false,
+                mime: mime_guess::mime::IMAGE_JPEG,
+                file: typst::foundations::Bytes::new(vec![]),
+                ty: SlotType::Image(ImageSlot {
+                    width: 1000,
+                    height: 500,
+                    variants: vec![
+                        variant("/a-480w.avif", 480, ImageFormat::Avif),
+                        variant("/a-480w.webp", 480, ImageFormat::WebP),
+                        variant("/a-1000w.webp", 1000, ImageFormat::WebP),
+                        variant("/a-480w.jpg", 480, ImageFormat::Jpeg),
+                    ],
+                }),
+            },
+        );
+
+        let assets = [("/a.jpg".into(), "/a.0000000a.jpg".into())]
+            .into_iter()
+            .collect();
+        let inputs = inputs(&slots, &assets);
+        let Ok(Value::Dict(info)) = inputs.get("/a.jpg") else {
+            panic!("missing /a.jpg in {inputs:?}");
+        };
+        assert_eq!(info.get("width").unwrap(), &Value::Int(1000));
+        assert_eq!(
+            info.get("srcset").unwrap(),
+            &Value::Str("/a-480w.jpg 480w, /a.0000000a.jpg 1000w".into())
+        );
+        let Ok(Value::Array(sources)) = info.get("sources") else {
+            panic!("missing sources");
+        };
+        let types = |sources: &typst::foundations::Array| {
+            sources
+                .iter()
+                .map(|s| match s {
+                    Value::Dict(d) => d.get("type").unwrap().clone(),
+                    _ => panic!("expected dict"),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            types(sources),
+            [
+                Value::Str("image/avif".into()),
+                Value::Str("image/webp".into())
+            ]
+        );
+        let Value::Dict(webp) = &sources.as_slice()[1] else {
+            panic!("expected dict");
+        };
+        assert_eq!(
+            webp.get("srcset").unwrap(),
+            &Value::Str("/a-480w.webp 480w, /a-1000w.webp 1000w".into())
+        );
+    }
+}
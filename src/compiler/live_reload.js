@@ -0,0 +1,56 @@
+const ws = new WebSocket(url);
+ws.onmessage = (e) => typeof e.data === "string" ? showErrors(JSON.parse(e.data)) : location.reload();
+ws.onclose = () => setTimeout(() => location.reload(), 1000);
+
+function el(tag, text, style) {
+    const e = document.createElement(tag);
+    if (text) e.textContent = text;
+    if (style) e.style.cssText = style;
+    return e;
+}
+
+function loc(l) {
+    return `${l.file}:${l.line}:${l.column}`;
+}
+
+function snippet(l) {
+    const pre = el("pre", null, "background:#222;padding:.5rem;overflow:auto");
+    const chars = [...l.snippet];
+    const [a, b] = l.highlight;
+    pre.append(
+        el("div", loc(l), "color:#aaa"),
+        `${l.line} │ ${chars.slice(0, a).join("")}`,
+        el("mark", chars.slice(a, b).join(""), "background:#f55;color:#000"),
+        chars.slice(b).join(""),
+    );
+    return pre;
+}
+
+// replaces any previous overlay, cleared by reloading after the next good build
+function showErrors(failures) {
+    document.getElementById("build-errors")?.remove();
+
+    const overlay = el("div", null,
+        "position:fixed;inset:0;z-index:99999;overflow:auto;padding:2rem;" +
+        "background:rgba(0,0,0,.92);color:#eee;font:14px/1.4 monospace;text-align:left");
+    overlay.id = "build-errors";
+
+    const close = el("button", "×", "float:right;font-size:1.5rem;cursor:pointer");
+    close.onclick = () => overlay.remove();
+    overlay.append(close, el("h2", `Build failed (${failures.length})`, "color:#f55"));
+
+    for (const f of failures) {
+        overlay.append(el("h3", f.error));
+        for (const d of f.diagnostics) {
+            overlay.append(el("p", d.message, "color:#f88;font-weight:bold"));
+            if (d.location) overlay.append(snippet(d.location));
+            for (const h of d.hints) overlay.append(el("p", `hint: ${h}`, "color:#8cf"));
+            for (const t of d.trace) {
+                overlay.append(el("p", t.message, "color:#aaa"));
+                if (t.location) overlay.append(snippet(t.location));
+            }
+        }
+    }
+
+    document.body.append(overlay);
+}
@@ -1,62 +1,285 @@
-use crate::compiler::scss::GrassSlotsFs;
+pub use crate::compiler::assets::Assets;
+use crate::compiler::deps::{Deps, Tracker};
+use crate::compiler::diagnostic::Diagnostics;
+pub use crate::compiler::fonts::Fonts;
+pub use crate::compiler::packages::Packages;
+pub use crate::compiler::redirects::Redirects;
+use crate::compiler::scss::{GrassSlotsFs, ScssLogger};
 use crate::compiler::typst::LiamsWorld;
-use crate::indexer::{MetaMap, SlotType, Slots, TypstSlot};
-use crate::web::route::Route;
-use crate::{RoutingTable, WatchArgs};
-use ::typst::foundations::{Dict, Value};
+use crate::indexer::{FileSlot, MetaMap, SlotType, Slots, TypstSlot};
+use crate::report::Report;
+use crate::web::route::{self, Route};
+use crate::{BuildArgs, RoutingTable, WatchArgs};
+use ::typst::foundations::{Dict, Value, ops};
 use ::typst::syntax::{FileId, VirtualPath};
-use anyhow::{Context, Result, anyhow, bail};
+use anyhow::{Context, Result, bail};
+use mime_guess::mime;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rustc_hash::FxHashSet;
+use std::cmp::Ordering;
 use std::ops::Bound;
-use std::path::Path;
-
+use typst_html::HtmlDocument;
+
+mod assets;
+mod build;
+pub mod deps;
+pub mod diagnostic;
+mod error;
+mod feed;
+pub mod fonts;
+mod images;
+mod packages;
+mod redirects;
 mod scss;
 mod sitemap;
 mod typst;
+mod woff2;
+
+/// Everything shared between the compilation of each slot
+#[derive(Clone, Copy)]
+pub struct Ctx<'a> {
+    pub slots: &'a Slots,
+    pub metamap: &'a MetaMap,
+    pub cfg: &'a BuildArgs,
+    pub watch: &'a WatchArgs,
+    pub report: &'a Report,
+    pub fonts: &'a Fonts,
+    pub packages: &'a Packages,
+}
+
+/// Compiles every visible slot into a route
+///
+/// Slots which fail (here or in the indexer) are recorded in `report`
+/// and served as an error page or their last good version
+pub fn run(ctx: &Ctx) -> Result<(RoutingTable, Deps, Assets, Redirects)> {
+    let mut routing_table = RoutingTable::default();
+    let mut deps = Deps::default();
+    let mut assets = Assets::default();
+    let mut redirects = Redirects::default();
+    let ids = ctx.slots.keys().copied().collect();
+    update(
+        ctx,
+        &ids,
+        &mut routing_table,
+        &mut deps,
+        &mut assets,
+        &mut redirects,
+    )?;
+    Ok((routing_table, deps, assets, redirects))
+}
+
+/// Compiles the visible slots in `ids`, patching their routes into
+/// `routing_table`, what they read into `deps`, and fingerprints into `assets`.
+/// Redirects and sitemaps are always regenerated (see `redirects::update`)
+///
+/// Assets are compiled first, stylesheets last since their `url()`s are
+/// fingerprinted, and every stylesheet or page recompiled if any asset
+/// they could link to changed fingerprint, so they always link to the
+/// latest version
+pub fn update(
+    ctx: &Ctx,
+    ids: &FxHashSet<FileId>,
+    routing_table: &mut RoutingTable,
+    deps: &mut Deps,
+    assets: &mut Assets,
+    redirects: &mut Redirects,
+) -> Result<()> {
+    let is_page = |slot: &FileSlot| matches!(slot.ty, SlotType::Typst(_));
+    let mut pages = Vec::new();
+    let mut stylesheets = Vec::new();
+    let mut others = Vec::new();
+    for id in ids {
+        match ctx.slots.get(id) {
+            None => {
+                deps.remove(id);
+            }
+            Some(slot) if slot.hidden => {}
+            Some(slot) if is_page(slot) => pages.push((*id, slot)),
+            Some(slot) if assets::is_stylesheet(slot) => stylesheets.push((*id, slot)),
+            Some(slot) => others.push((*id, slot)),
+        }
+    }
+
+    compile_all(ctx, &others, &Dict::new(), routing_table, deps)?;
+
+    if assets::sync(ctx.slots, assets, routing_table) {
+        stylesheets = visible(ctx.slots, assets::is_stylesheet);
+        pages = visible(ctx.slots, is_page);
+    }
+
+    let fingerprints = assets_inputs(assets);
+    compile_all(ctx, &stylesheets, &fingerprints, routing_table, deps)?;
+
+    if assets::sync(ctx.slots, assets, routing_table) {
+        pages = visible(ctx.slots, is_page);
+    }
+
+    let mut shared = assets_inputs(assets);
+    shared.insert(build::INPUTS_KEY.into(), Value::Dict(build::inputs(ctx)));
+    let images = images::inputs(ctx.slots, assets);
+    if !images.is_empty() {
+        shared.insert(images::INPUTS_KEY.into(), Value::Dict(images));
+    }
+
+    compile_all(ctx, &pages, &shared, routing_table, deps)?;
 
-pub fn run(slots: Slots, metamap: MetaMap, root: &Path, watch: &WatchArgs) -> Result<RoutingTable> {
-    let mut routing_table = slots
+    routing_table.extend(feed::generate(ctx, routing_table)?);
+    sitemap::update(ctx, routing_table)?;
+    redirects::update(ctx, routing_table, redirects);
+
+    Ok(())
+}
+
+/// Every visible slot matching `filter`
+fn visible(slots: &Slots, filter: impl Fn(&FileSlot) -> bool) -> Vec<(FileId, &FileSlot)> {
+    slots
+        .iter()
+        .filter(|(_, slot)| !slot.hidden && filter(slot))
+        .map(|(id, slot)| (*id, slot))
+        .collect()
+}
+
+/// `sys.inputs.assets`, also used to rewrite stylesheets' `url()`s
+fn assets_inputs(assets: &Assets) -> Dict {
+    let mut inputs = Dict::new();
+    if !assets.is_empty() {
+        inputs.insert(
+            assets::INPUTS_KEY.into(),
+            Value::Dict(assets::inputs(assets)),
+        );
+    }
+    inputs
+}
+
+/// Compiles `slots` in parallel, serving failures
+/// (including any from indexing) through `error::fallback`
+fn compile_all(
+    ctx: &Ctx,
+    slots: &[(FileId, &FileSlot)],
+    shared: &Dict,
+    routing_table: &mut RoutingTable,
+    deps: &mut Deps,
+) -> Result<()> {
+    let results = slots
         .par_iter()
-        .filter(|(_, slot)| !slot.hidden)
         .map(|(id, slot)| {
-            let content = match &slot.ty {
-                SlotType::Typst(tslot) => compile_typst(id, tslot, &slots, &metamap, root, watch),
-                SlotType::Scss => compile_scss(id, &slots),
-                SlotType::Other => Ok(slot.file.to_vec()),
+            let tracker = Tracker::default();
+            let routes = compile_slot(ctx, id, slot, shared, &tracker);
+            (*id, slot, routes, tracker.into_inner())
+        })
+        .collect::<Vec<_>>();
+
+    for (id, slot, routes, read) in results {
+        deps.insert(id, read);
+        match routes {
+            Ok(routes) => routing_table.extend(routes),
+            Err(e) => {
+                let path = id.vpath().as_rootless_path();
+                ctx.report
+                    .error(path, Some(&slot.url), e.context(format!("{id:?}")));
             }
-            .with_context(|| format!("{id:?}"))?;
+        }
+    }
 
-            let route = Route::compile(id, content, &slot.mime, watch.watch)?;
-            Ok((slot.url.clone(), route))
-        })
-        .collect::<Result<RoutingTable>>()?;
+    let error_page = routing_table.get(route::ERROR_PAGE).cloned();
+    for failure in ctx.report.failures().iter() {
+        if let Some(url) = &failure.url {
+            let page = error_page.as_ref().filter(|_| url != route::ERROR_PAGE);
+            let route = error::fallback(url, &failure.error, ctx.watch, page)?;
+            routing_table.insert(url.clone(), route);
+        }
+    }
+
+    Ok(())
+}
 
-    let (url, route) = sitemap::generate(&routing_table, watch)?;
-    routing_table.insert(url, route);
+/// The slot's route (and any others it generates,
+/// like image variants or its fingerprinted url)
+///
+/// `shared` are inputs given to every Typst page, its
+/// `assets` also fingerprinting stylesheets' `url()`s
+fn compile_slot(
+    ctx: &Ctx,
+    id: &FileId,
+    slot: &FileSlot,
+    shared: &Dict,
+    tracker: &Tracker,
+) -> Result<Vec<(String, Route)>> {
+    let mut routes = Vec::new();
+    let fast = ctx.watch.watch;
+
+    let content = match &slot.ty {
+        SlotType::Typst(tslot) => {
+            compile_typst(ctx, id, &slot.url, tslot, shared, tracker, &mut routes)?
+        }
+        SlotType::Scss => compile_scss(ctx, id, shared, tracker)?,
+        SlotType::Image(image) => {
+            tracker.access(*id);
+            routes = images::compile(ctx, &slot.file, image)?;
+            slot.file.to_vec()
+        }
+        SlotType::Other if assets::is_stylesheet(slot) => {
+            tracker.access(*id);
+            let css = String::from_utf8_lossy(&slot.file);
+            assets::rewrite_css(&css, &slot.url, &fingerprints(shared)).into_bytes()
+        }
+        SlotType::Other => {
+            tracker.access(*id);
+            slot.file.to_vec()
+        }
+    };
 
-    Ok(routing_table)
+    let route = match (&slot.ty, route::status_page(&slot.url)) {
+        (SlotType::Typst(_), Some(status)) => {
+            Route::compile_status(id, content, &slot.mime, status, fast)?
+        }
+        _ => Route::compile(id, content, &slot.mime, fast)?,
+    };
+
+    if assets::is_asset(slot) {
+        let immutable = route.immutable()?;
+        routes.push((assets::fingerprint(&slot.url, route.hash), immutable));
+    }
+
+    routes.push((slot.url.clone(), route));
+    Ok(routes)
+}
+
+/// The url → fingerprinted url part of `shared`
+fn fingerprints(shared: &Dict) -> Dict {
+    match shared.get(assets::INPUTS_KEY) {
+        Ok(Value::Dict(assets)) => assets.clone(),
+        _ => Dict::new(),
+    }
 }
 
-fn compile_scss(id: &FileId, slots: &Slots) -> Result<Vec<u8>> {
-    let fs = GrassSlotsFs(slots);
+fn compile_scss(ctx: &Ctx, id: &FileId, shared: &Dict, tracker: &Tracker) -> Result<Vec<u8>> {
+    let fs = GrassSlotsFs {
+        slots: ctx.slots,
+        tracker,
+    };
+    let logger = ScssLogger(ctx.report);
     let opts = grass::Options::default()
         .style(grass::OutputStyle::Compressed)
         .input_syntax(grass::InputSyntax::Scss)
-        .fs(&fs);
+        .fs(&fs)
+        .logger(&logger);
     let path = id.vpath().as_rooted_path();
-    let css = grass::from_path(path, &opts).map_err(|e| anyhow!("{e}"))?;
-    Ok(css.into())
+    let css = grass::from_path(path, &opts).map_err(Diagnostics::from)?;
+    let base = ctx.slots.get(id).map_or("/", |slot| &slot.url);
+    Ok(assets::rewrite_css(&css, base, &fingerprints(shared)).into_bytes())
 }
 
 fn compile_typst(
+    ctx: &Ctx,
     id: &FileId,
+    url: &str,
     tslot: &TypstSlot,
-    slots: &Slots,
-    metamap: &MetaMap,
-    root: &Path,
-    watch: &WatchArgs,
+    shared: &Dict,
+    tracker: &Tracker,
+    routes: &mut Vec<(String, Route)>,
 ) -> Result<Vec<u8>> {
-    let mut inputs = Dict::new();
+    let mut inputs = shared.clone();
 
     if let Some(page_meta) = &tslot.page_meta {
         inputs.insert("page".into(), Value::Dict(page_meta.clone()));
@@ -64,21 +287,41 @@ fn compile_typst(
 
     if let Some(queries) = &tslot.queries {
         for (name, query) in queries {
-            inputs.insert(name.clone(), eval_query(query, metamap)?);
+            let result = eval_query(query, ctx.metamap, url)
+                .with_context(|| format!("evaluating query `{name}`"))?;
+            inputs.insert(name.clone(), result);
         }
     }
 
     if let Some(css_path) = &tslot.css {
         let vp = VirtualPath::new(css_path);
         let id = FileId::new(None, vp);
-        let bytes = compile_scss(&id, slots)?;
+        let bytes = compile_scss(ctx, &id, shared, tracker).context("compiling page css")?;
         let text = String::from_utf8_lossy(&bytes);
         inputs.insert("css".into(), Value::Str(text.into()));
     }
 
-    let mut world = LiamsWorld::new(*id, slots, inputs, root, watch);
-    let doc = world.compile()?;
-    let html = world.html(&doc)?;
+    let mut world = LiamsWorld::new(*id, ctx, inputs, tracker);
+    let doc = world.compile::<HtmlDocument>()?;
+    let html = world.html(doc)?;
+
+    let wants_pdf = tslot
+        .page_meta
+        .as_ref()
+        .is_some_and(|meta| matches!(meta.get("pdf"), Ok(Value::Bool(true))));
+    if wants_pdf {
+        // a broken PDF leaves the page itself up
+        match world.pdf() {
+            Ok(pdf) => {
+                let route = Route::compile(id, pdf, &mime::APPLICATION_PDF, ctx.watch.watch)?;
+                routes.push((pdf_url(url), route));
+            }
+            Err(e) => {
+                let path = id.vpath().as_rootless_path();
+                ctx.report.error(path, None, e.context("compiling pdf"));
+            }
+        }
+    }
 
     let cfg = minify_html::Cfg {
         keep_html_and_head_opening_tags: true,
@@ -91,30 +334,172 @@ fn compile_typst(
     Ok(minify_html::minify(&html.into_bytes(), &cfg))
 }
 
-/// Evaluate a query `/projects/` into an array of the metadata
-/// of each page where its url is prefixed `/projects/`
-fn eval_query(query: &Value, metamap: &MetaMap) -> Result<Value> {
-    let Value::Str(query) = query else {
-        bail!("`{query:?}` is not a valid query. Must be a string.");
-    };
+/// Where a page's PDF is served, `/resume` -> `/resume.pdf`
+fn pdf_url(url: &str) -> String {
+    match url {
+        "/" => "/index.pdf".into(),
+        url => format!("{url}.pdf"),
+    }
+}
+
+/// Escape text for HTML/XML
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
 
-    let mut end = query.clone().to_string();
-    if let Some(last) = end.as_bytes().last().copied() {
-        end.pop();
-        end.push((last + 1) as char);
+/// `url` (ex. `/blog`) on the site hosted at `base_url`
+fn absolute(base_url: &str, url: &str) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    if url == "/" {
+        base_url.to_string()
+    } else {
+        format!("{base_url}{url}")
+    }
+}
+
+/// A `<query>`, either a url prefix `"/blog/"` or a dict:
+/// ```typst
+/// (
+///   prefix: "/blog/",        // default: every page
+///   where: (homepage: true), // fields each page must equal
+///   sort: "-written",        // field to sort by, `-` for descending
+///   limit: 5,
+///   exclude-self: true,      // leave out the querying page
+/// )
+/// ```
+struct Query<'a> {
+    prefix: &'a str,
+    filter: Option<&'a Dict>,
+    /// Field and if descending
+    sort: Option<(&'a str, bool)>,
+    limit: Option<usize>,
+    exclude_self: bool,
+}
+
+impl<'a> Query<'a> {
+    fn parse(query: &'a Value) -> Result<Self> {
+        let mut out = Query {
+            prefix: "",
+            filter: None,
+            sort: None,
+            limit: None,
+            exclude_self: false,
+        };
+
+        let dict = match query {
+            Value::Str(prefix) => {
+                out.prefix = prefix;
+                return Ok(out);
+            }
+            Value::Dict(dict) => dict,
+            _ => bail!("`{query:?}` is not a valid query. Must be a string or dict."),
+        };
+
+        for (key, value) in dict {
+            match (key.as_str(), value) {
+                ("prefix", Value::Str(prefix)) => out.prefix = prefix,
+                ("where", Value::Dict(filter)) => out.filter = Some(filter),
+                ("sort", Value::Str(field)) => {
+                    out.sort = Some(match field.strip_prefix('-') {
+                        Some(field) => (field, true),
+                        None => (field, false),
+                    })
+                }
+                ("limit", Value::Int(limit)) => {
+                    out.limit = Some(usize::try_from(*limit).context("`limit` is negative")?)
+                }
+                ("exclude-self", Value::Bool(exclude)) => out.exclude_self = *exclude,
+                (key @ ("prefix" | "where" | "sort" | "limit" | "exclude-self"), value) => {
+                    bail!("query `{key}` has invalid value `{value:?}`")
+                }
+                (key, _) => bail!("unknown query key `{key}`"),
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// The url prefix a query selects pages from
+fn query_prefix(query: &Value) -> Option<&str> {
+    Query::parse(query).ok().map(|query| query.prefix)
+}
+
+/// Evaluate a query (see `Query`) made by the page at `url` into
+/// an array of the metadata of each page it selects
+fn eval_query(query: &Value, metamap: &MetaMap, url: &str) -> Result<Value> {
+    let query = Query::parse(query)?;
+
+    let mut pages = under_prefix(metamap, query.prefix)
+        .filter(|(page_url, _)| !(query.exclude_self && *page_url == url))
+        .filter(|(_, meta)| {
+            query.filter.is_none_or(|filter| {
+                filter
+                    .iter()
+                    .all(|(key, value)| meta.get(key).is_ok_and(|v| v == value))
+            })
+        })
+        .map(|(_, meta)| meta)
+        .collect::<Vec<_>>();
+
+    if let Some((field, descending)) = query.sort {
+        let mut error = None;
+        // pages missing the field are smallest
+        pages.sort_by(|a, b| {
+            let ord = match (a.get(field).ok(), b.get(field).ok()) {
+                (Some(a), Some(b)) => ops::compare(a, b).unwrap_or_else(|e| {
+                    error.get_or_insert(e);
+                    Ordering::Equal
+                }),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            };
+            if descending { ord.reverse() } else { ord }
+        });
+        if let Some(e) = error {
+            bail!("sorting by `{field}`: {e}");
+        }
+    }
+
+    if let Some(limit) = query.limit {
+        pages.truncate(limit);
     }
 
     Ok(Value::Array(
-        metamap
-            .range::<str, _>((
-                Bound::Included(query.as_str()),
-                Bound::Excluded(end.as_str()),
-            ))
-            .map(|(_, meta)| Value::Dict(meta.clone()))
+        pages
+            .into_iter()
+            .map(|meta| Value::Dict(meta.clone()))
             .collect(),
     ))
 }
 
+/// The metadata of every page whose url starts with `prefix`
+fn under_prefix<'a>(
+    metamap: &'a MetaMap,
+    prefix: &str,
+) -> impl Iterator<Item = (&'a String, &'a Dict)> {
+    let mut end = prefix.to_string();
+    let end = match end.as_bytes().last().copied() {
+        Some(last) => {
+            end.pop();
+            end.push((last + 1) as char);
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    };
+
+    metamap.range::<String, _>((Bound::Included(prefix.to_string()), end))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -143,6 +528,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_pdf_url() {
+        assert_eq!(pdf_url("/"), "/index.pdf");
+        assert_eq!(pdf_url("/resume"), "/resume.pdf");
+    }
+
+    #[test]
+    fn test_absolute() {
+        assert_eq!(absolute("https://a.com", "/"), "https://a.com");
+        assert_eq!(absolute("https://a.com/", "/blog"), "https://a.com/blog");
+    }
+
     #[test]
     fn query_matches_prefix() {
         let meta = make_meta(&[
@@ -151,7 +556,7 @@ mod test {
             ("/blog/post1", &[("title", Value::Str("Post".into()))]),
         ]);
 
-        let result = eval_query(&Value::Str("/projects/".into()), &meta).unwrap();
+        let result = eval_query(&Value::Str("/projects/".into()), &meta, "/").unwrap();
         let items = unwrap_array(result);
 
         assert_eq!(items.len(), 2);
@@ -163,7 +568,7 @@ mod test {
     fn query_no_matches() {
         let meta = make_meta(&[("/blog/post1", &[("title", Value::Str("Post".into()))])]);
 
-        let result = eval_query(&Value::Str("/projects/".into()), &meta).unwrap();
+        let result = eval_query(&Value::Str("/projects/".into()), &meta, "/").unwrap();
         let items = unwrap_array(result);
 
         assert!(items.is_empty());
@@ -173,7 +578,7 @@ mod test {
     fn query_empty_metamap() {
         let meta = MetaMap::new();
 
-        let result = eval_query(&Value::Str("/anything/".into()), &meta).unwrap();
+        let result = eval_query(&Value::Str("/anything/".into()), &meta, "/").unwrap();
         let items = unwrap_array(result);
 
         assert!(items.is_empty());
@@ -186,7 +591,7 @@ mod test {
             ("/projectsX/dog", &[("title", Value::Str("Dog".into()))]),
         ]);
 
-        let result = eval_query(&Value::Str("/projects/".into()), &meta).unwrap();
+        let result = eval_query(&Value::Str("/projects/".into()), &meta, "/").unwrap();
         let items = unwrap_array(result);
 
         assert_eq!(items.len(), 1);
@@ -197,8 +602,127 @@ mod test {
     fn query_non_string() {
         let meta = MetaMap::new();
 
-        assert!(eval_query(&Value::Int(42), &meta).is_err());
-        assert!(eval_query(&Value::Bool(true), &meta).is_err());
-        assert!(eval_query(&Value::None, &meta).is_err());
+        assert!(eval_query(&Value::Int(42), &meta, "/").is_err());
+        assert!(eval_query(&Value::Bool(true), &meta, "/").is_err());
+        assert!(eval_query(&Value::None, &meta, "/").is_err());
+    }
+
+    fn query(fields: &[(&str, Value)]) -> Value {
+        Value::Dict(fields.iter().cloned().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    fn titles(items: &[Dict]) -> Vec<&str> {
+        items
+            .iter()
+            .map(|d| match d.get("title").unwrap() {
+                Value::Str(s) => s.as_str(),
+                other => panic!("expected Str, got {other:?}"),
+            })
+            .collect()
+    }
+
+    fn blog() -> MetaMap {
+        let post = |title: &str, written: Option<&str>, homepage: bool| {
+            let mut fields = vec![
+                ("title", Value::Str(title.into())),
+                ("homepage", Value::Bool(homepage)),
+            ];
+            if let Some(written) = written {
+                fields.push(("written", Value::Str(written.into())));
+            }
+            fields
+        };
+        make_meta(&[
+            ("/blog/a", &post("A", Some("2024-05-01"), true)),
+            ("/blog/b", &post("B", Some("2025-01-01"), false)),
+            ("/blog/c", &post("C", None, true)),
+            ("/blog/d", &post("D", Some("2023-02-03"), true)),
+            ("/projects/e", &post("E", Some("2026-01-01"), true)),
+        ])
+    }
+
+    #[test]
+    fn query_dict_prefix() {
+        let q = query(&[("prefix", Value::Str("/blog/".into()))]);
+        let items = unwrap_array(eval_query(&q, &blog(), "/").unwrap());
+        assert_eq!(titles(&items), ["A", "B", "C", "D"]);
+
+        let items = unwrap_array(eval_query(&query(&[]), &blog(), "/").unwrap());
+        assert_eq!(items.len(), 5);
+    }
+
+    #[test]
+    fn query_where() {
+        let q = query(&[
+            ("prefix", Value::Str("/blog/".into())),
+            ("where", query(&[("homepage", Value::Bool(true))])),
+        ]);
+        let items = unwrap_array(eval_query(&q, &blog(), "/").unwrap());
+        assert_eq!(titles(&items), ["A", "C", "D"]);
+    }
+
+    #[test]
+    fn query_sort_limit() {
+        let q = |sort: &str| {
+            query(&[
+                ("prefix", Value::Str("/blog/".into())),
+                ("sort", Value::Str(sort.into())),
+            ])
+        };
+
+        let items = unwrap_array(eval_query(&q("written"), &blog(), "/").unwrap());
+        assert_eq!(titles(&items), ["C", "D", "A", "B"]);
+
+        let items = unwrap_array(eval_query(&q("-written"), &blog(), "/").unwrap());
+        assert_eq!(titles(&items), ["B", "A", "D", "C"]);
+
+        let Value::Dict(mut limited) = q("-written") else {
+            unreachable!()
+        };
+        limited.insert("limit".into(), Value::Int(2));
+        let items = unwrap_array(eval_query(&Value::Dict(limited), &blog(), "/").unwrap());
+        assert_eq!(titles(&items), ["B", "A"]);
+    }
+
+    #[test]
+    fn query_exclude_self() {
+        let q = query(&[
+            ("prefix", Value::Str("/blog/".into())),
+            ("exclude-self", Value::Bool(true)),
+        ]);
+        let items = unwrap_array(eval_query(&q, &blog(), "/blog/b").unwrap());
+        assert_eq!(titles(&items), ["A", "C", "D"]);
+    }
+
+    #[test]
+    fn query_invalid() {
+        let meta = blog();
+        let bad = [
+            query(&[("prefx", Value::Str("/blog/".into()))]),
+            query(&[("prefix", Value::Int(1))]),
+            query(&[("limit", Value::Int(-1))]),
+            query(&[("sort", Value::Bool(true))]),
+        ];
+        for q in &bad {
+            assert!(eval_query(q, &meta, "/").is_err(), "{q:?}");
+        }
+    }
+
+    #[test]
+    fn query_sort_mixed_types() {
+        let meta = make_meta(&[
+            ("/a", &[("n", Value::Int(1))]),
+            ("/b", &[("n", Value::Str("x".into()))]),
+        ]);
+        let q = query(&[("sort", Value::Str("n".into()))]);
+        assert!(eval_query(&q, &meta, "/").is_err());
+    }
+
+    #[test]
+    fn query_prefix_of_dict() {
+        let q = query(&[("prefix", Value::Str("/blog/".into()))]);
+        assert_eq!(query_prefix(&q), Some("/blog/"));
+        assert_eq!(query_prefix(&query(&[])), Some(""));
+        assert_eq!(query_prefix(&Value::Int(1)), None);
     }
 }
@@ -0,0 +1,202 @@
+//! `@namespace/name:version` imports, resolved from local package
+//! directories in Typst's layout (`<dir>/<namespace>/<name>/<version>/`)
+//!
+//! Packages vendored in the content tree (`content/@preview/name:1.0.0/`)
+//! are indexed like any other file and take precedence
+
+use rustc_hash::FxHashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use typst::diag::{FileError, FileResult, PackageError, PackageResult};
+use typst::foundations::Bytes;
+use typst::syntax::package::{PackageManifest, PackageSpec};
+use typst::syntax::{FileId, Source, VirtualPath};
+
+use crate::BuildArgs;
+
+const MANIFEST: &str = "typst.toml";
+
+/// Built per build, caching package directories and parsed sources
+pub struct Packages {
+    /// Searched in order
+    dirs: Vec<PathBuf>,
+    roots: Mutex<FxHashMap<PackageSpec, PackageResult<PathBuf>>>,
+    sources: Mutex<FxHashMap<FileId, FileResult<Source>>>,
+}
+
+impl Packages {
+    pub fn new(cfg: &BuildArgs) -> Self {
+        let dirs = [
+            cfg.package_path.clone().or_else(|| {
+                default_dir(
+                    "XDG_DATA_HOME",
+                    ".local/share",
+                    "Library/Application Support",
+                )
+            }),
+            cfg.package_cache_path
+                .clone()
+                .or_else(|| default_dir("XDG_CACHE_HOME", ".cache", "Library/Caches")),
+        ];
+        Self {
+            dirs: dirs.into_iter().flatten().collect(),
+            roots: Mutex::default(),
+            sources: Mutex::default(),
+        }
+    }
+
+    pub fn file(&self, id: FileId) -> FileResult<Bytes> {
+        let spec = id.package().ok_or(FileError::AccessDenied)?;
+        let root = self.root(spec)?;
+        let path = id.vpath().resolve(&root).ok_or(FileError::AccessDenied)?;
+        fs::read(&path)
+            .map(Bytes::new)
+            .map_err(|e| FileError::from_io(e, &path))
+    }
+
+    pub fn source(&self, id: FileId) -> FileResult<Source> {
+        if let Some(source) = self.sources.lock().unwrap().get(&id) {
+            return source.clone();
+        }
+        let source = self.file(id).and_then(|bytes| {
+            let text = bytes.as_str()?;
+            Ok(Source::new(id, text.into()))
+        });
+        self.sources.lock().unwrap().insert(id, source.clone());
+        source
+    }
+
+    /// The package's directory, once its manifest checks out
+    fn root(&self, spec: &PackageSpec) -> PackageResult<PathBuf> {
+        if let Some(root) = self.roots.lock().unwrap().get(spec) {
+            return root.clone();
+        }
+        let root = self.find(spec).and_then(|root| {
+            validate(spec, &root)?;
+            Ok(root)
+        });
+        self.roots
+            .lock()
+            .unwrap()
+            .insert(spec.clone(), root.clone());
+        root
+    }
+
+    fn find(&self, spec: &PackageSpec) -> PackageResult<PathBuf> {
+        let subdir = Path::new(spec.namespace.as_str()).join(spec.name.as_str());
+        let version = spec.version.to_string();
+        for dir in &self.dirs {
+            let root = dir.join(&subdir).join(&version);
+            if root.is_dir() {
+                return Ok(root);
+            }
+        }
+
+        let latest = self
+            .dirs
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir.join(&subdir)).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .max();
+        match latest {
+            Some(latest) => Err(PackageError::VersionNotFound(spec.clone(), latest)),
+            None => Err(PackageError::NotFound(spec.clone())),
+        }
+    }
+}
+
+/// Parses `root`'s manifest, checking it's for `spec` and its entrypoint exists
+fn validate(spec: &PackageSpec, root: &Path) -> PackageResult<()> {
+    let invalid = |msg: String| PackageError::Other(Some(msg.into()));
+    let text = fs::read_to_string(root.join(MANIFEST))
+        .map_err(|e| invalid(format!("reading {MANIFEST}: {e}")))?;
+    let manifest: PackageManifest = toml::from_str(&text)
+        .map_err(|e| invalid(format!("parsing {MANIFEST}: {}", e.message())))?;
+    manifest.validate(spec).map_err(|e| invalid(e.into()))?;
+
+    let entrypoint = VirtualPath::new(manifest.package.entrypoint.as_str());
+    if !entrypoint.resolve(root).is_some_and(|path| path.is_file()) {
+        let entrypoint = manifest.package.entrypoint;
+        return Err(invalid(format!("entrypoint `{entrypoint}` is missing")));
+    }
+    Ok(())
+}
+
+/// Where Typst itself keeps packages: `<data or cache dir>/typst/packages`
+fn default_dir(xdg: &str, linux: &str, macos: &str) -> Option<PathBuf> {
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(target_os = "macos") {
+        home()?.join(macos)
+    } else {
+        match std::env::var_os(xdg) {
+            Some(dir) => PathBuf::from(dir),
+            None => home()?.join(linux),
+        }
+    };
+    Some(base.join("typst/packages"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use typst::syntax::package::PackageVersion;
+
+    fn spec(s: &str) -> PackageSpec {
+        PackageSpec::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = std::env::temp_dir().join(format!("liamsnow-packages-{}", std::process::id()));
+        let root = dir.join("local/pkg/0.1.0");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join(MANIFEST),
+            "[package]\nname = \"pkg\"\nversion = \"0.1.0\"\nentrypoint = \"src/lib.typ\"\n",
+        )
+        .unwrap();
+        fs::write(root.join("src/lib.typ"), "#let x = 1").unwrap();
+        let broken = dir.join("local/broken/1.0.0");
+        fs::create_dir_all(&broken).unwrap();
+        fs::write(
+            broken.join(MANIFEST),
+            "[package]\nname = \"broken\"\nversion = \"1.0.0\"\nentrypoint = \"lib.typ\"\n",
+        )
+        .unwrap();
+
+        let packages = Packages {
+            dirs: vec![dir.clone()],
+            roots: Mutex::default(),
+            sources: Mutex::default(),
+        };
+        let id = |s, path| FileId::new(Some(spec(s)), VirtualPath::new(path));
+
+        let source = packages
+            .source(id("@local/pkg:0.1.0", "src/lib.typ"))
+            .unwrap();
+        assert_eq!(source.text(), "#let x = 1");
+        assert!(matches!(
+            packages.file(id("@local/pkg:0.1.0", "missing.typ")),
+            Err(FileError::NotFound(_))
+        ));
+        assert!(matches!(
+            packages.file(id("@local/pkg:0.2.0", "src/lib.typ")),
+            Err(FileError::Package(PackageError::VersionNotFound(_, v)))
+                if v == PackageVersion::from_str("0.1.0").unwrap()
+        ));
+        assert!(matches!(
+            packages.file(id("@local/other:0.1.0", "lib.typ")),
+            Err(FileError::Package(PackageError::NotFound(_)))
+        ));
+        assert!(matches!(
+            packages.file(id("@local/broken:1.0.0", "typst.toml")),
+            Err(FileError::Package(PackageError::Other(Some(msg))))
+                if msg.contains("entrypoint `lib.typ`")
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+//! Redirects from `aliases: ("/old/path",)` in `<page>` metadata and the
+//! `_redirects` file at the root of the content directory:
+//! ```text
+//! # source         target                  status (default 301)
+//! /old/post        /blog/post
+//! /talk            https://example.com     302
+//! /deleted-post                            410
+//! ```
+
+use crate::RoutingTable;
+use crate::compiler::Ctx;
+use crate::indexer::SlotType;
+use crate::web::route::{GONE_PAGE, Route};
+use anyhow::{Error, Result, bail};
+use rustc_hash::FxHashSet;
+use std::path::Path;
+use typst::foundations::{Dict, Value};
+use typst::syntax::{FileId, VirtualPath};
+
+const REDIRECTS_PATH: &str = "_redirects";
+const ALIASES_KEY: &str = "aliases";
+
+/// Urls currently routed as redirects
+pub type Redirects = FxHashSet<String>;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Redirect {
+    source: String,
+    /// None for `410 Gone`
+    target: Option<String>,
+    status: &'static str,
+}
+
+/// Replaces the redirects in `routing_table` with those currently declared
+///
+/// Invalid redirects, ones whose source is already a route, and ones
+/// to missing pages or other redirects are recorded in `ctx.report`
+/// and left out
+pub fn update(ctx: &Ctx, routing_table: &mut RoutingTable, redirects: &mut Redirects) {
+    for url in redirects.drain() {
+        routing_table.remove(&url);
+    }
+
+    let mut declared = Vec::new();
+
+    let mut pages = ctx
+        .slots
+        .iter()
+        .filter(|(_, slot)| !slot.hidden)
+        .filter_map(|(id, slot)| match &slot.ty {
+            SlotType::Typst(tslot) => Some((id, &slot.url, tslot.page_meta.as_ref()?)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    pages.sort_by_key(|(_, url, _)| *url);
+
+    for (id, url, meta) in pages {
+        let path = id.vpath().as_rootless_path();
+        match aliases(meta, url) {
+            Ok(aliases) => declared.extend(aliases.into_iter().map(|r| (path, r))),
+            Err(e) => ctx.report.error(path, None, e.context(format!("{id:?}"))),
+        }
+    }
+
+    let id = FileId::new(None, VirtualPath::new(REDIRECTS_PATH));
+    if let Some(slot) = ctx.slots.get(&id) {
+        let path = Path::new(REDIRECTS_PATH);
+        for (i, redirect) in parse(&String::from_utf8_lossy(&slot.file)) {
+            match redirect {
+                Ok(redirect) => declared.push((path, redirect)),
+                Err(e) => ctx.report.error(
+                    path,
+                    None,
+                    e.context(format!("{REDIRECTS_PATH} line {}", i + 1)),
+                ),
+            }
+        }
+    }
+
+    for (path, e) in insert(declared, routing_table, redirects) {
+        ctx.report.error(path, None, e);
+    }
+}
+
+/// Routes every valid redirect in `declared`, returning why the rest aren't
+///
+/// Each is checked against every declared source, so whether a chain or
+/// loop is caught doesn't depend on the order they're declared in
+fn insert<'a>(
+    declared: Vec<(&'a Path, Redirect)>,
+    routing_table: &mut RoutingTable,
+    redirects: &mut Redirects,
+) -> Vec<(&'a Path, Error)> {
+    let sources = declared
+        .iter()
+        .map(|(_, redirect)| redirect.source.clone())
+        .collect::<Redirects>();
+    let gone_page = routing_table.get(GONE_PAGE).cloned();
+    let mut errors = Vec::new();
+
+    for (path, redirect) in declared {
+        let route = check(&redirect, routing_table, &sources, redirects).and_then(|()| {
+            match (&redirect.target, &gone_page) {
+                (None, Some(page)) => Ok(page.clone()),
+                (target, _) => Route::empty(redirect.status, target.as_deref()),
+            }
+        });
+        match route {
+            Ok(route) => {
+                routing_table.insert(redirect.source.clone(), route);
+                redirects.insert(redirect.source);
+            }
+            Err(e) => {
+                let e = e.context(format!(
+                    "{}: redirect from `{}`",
+                    path.display(),
+                    redirect.source
+                ));
+                errors.push((path, e));
+            }
+        }
+    }
+
+    errors
+}
+
+/// A page's `aliases`, redirecting permanently to its `url`
+fn aliases(meta: &Dict, url: &str) -> Result<Vec<Redirect>> {
+    let Ok(value) = meta.get(ALIASES_KEY) else {
+        return Ok(Vec::new());
+    };
+    let Value::Array(aliases) = value else {
+        bail!("`{ALIASES_KEY}` must be an array of urls");
+    };
+
+    aliases
+        .iter()
+        .map(|alias| match alias {
+            Value::Str(alias) => Ok(Redirect {
+                source: alias.to_string(),
+                target: Some(url.to_string()),
+                status: "301 Moved Permanently",
+            }),
+            _ => bail!("`{ALIASES_KEY}` must be an array of urls"),
+        })
+        .collect()
+}
+
+/// Each redirect in a `_redirects` file with its line index
+fn parse(text: &str) -> Vec<(usize, Result<Redirect>)> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| (i, parse_line(line)))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<Redirect> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let (source, target, status) = match fields[..] {
+        [source, "410"] => (source, None, "410"),
+        [source, target] => (source, Some(target), "301"),
+        [source, target, status] => (source, Some(target), status),
+        _ => bail!("expected `source target [status]`"),
+    };
+
+    let status = match (status, target) {
+        ("301", Some(_)) => "301 Moved Permanently",
+        ("302", Some(_)) => "302 Found",
+        ("307", Some(_)) => "307 Temporary Redirect",
+        ("308", Some(_)) => "308 Permanent Redirect",
+        ("410", None) => "410 Gone",
+        (status, _) => bail!("unsupported status `{status}` (301, 302, 307, 308 or 410)"),
+    };
+
+    Ok(Redirect {
+        source: source.to_string(),
+        target: target.map(str::to_string),
+        status,
+    })
+}
+
+/// Sources must be new paths and local targets must be existing
+/// routes, not one of the declared `sources` (chains and loops)
+fn check(
+    redirect: &Redirect,
+    routing_table: &RoutingTable,
+    sources: &Redirects,
+    seen: &Redirects,
+) -> Result<()> {
+    let source = &redirect.source;
+    if !source.starts_with('/') || !is_header_safe(source) {
+        bail!("source must be a path");
+    }
+    if seen.contains(source) {
+        bail!("declared more than once");
+    }
+    if routing_table.contains_key(source) {
+        bail!("`{source}` is already a route");
+    }
+
+    let Some(target) = &redirect.target else {
+        return Ok(());
+    };
+    if !is_header_safe(target) {
+        bail!("target `{target}` is not a valid url");
+    }
+    if target.starts_with("http://") || target.starts_with("https://") {
+        return Ok(());
+    }
+    if !target.starts_with('/') || target.starts_with("//") {
+        bail!("target `{target}` must be a path or http(s) url");
+    }
+
+    let path = target.split(['?', '#']).next().unwrap_or(target);
+    if sources.contains(path) {
+        bail!("target `{target}` is a redirect too, point at where it goes instead");
+    }
+    if !routing_table.contains_key(path) {
+        bail!("target `{target}` doesn't exist");
+    }
+
+    Ok(())
+}
+
+/// Written as-is into `Location`, so no whitespace or non-ASCII
+fn is_header_safe(url: &str) -> bool {
+    url.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mime_guess::mime;
+
+    fn redirect(source: &str, target: Option<&str>, status: &'static str) -> Redirect {
+        Redirect {
+            source: source.into(),
+            target: target.map(Into::into),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# comment\n\n/a  /b\n/c https://example.com 302\n  /d 410\n/e\n/f /g 303\n";
+        let parsed = parse(text);
+
+        let lines = parsed.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(lines, [2, 3, 4, 5, 6]);
+        assert_eq!(
+            parsed[0].1.as_ref().unwrap(),
+            &redirect("/a", Some("/b"), "301 Moved Permanently")
+        );
+        assert_eq!(
+            parsed[1].1.as_ref().unwrap(),
+            &redirect("/c", Some("https://example.com"), "302 Found")
+        );
+        assert_eq!(
+            parsed[2].1.as_ref().unwrap(),
+            &redirect("/d", None, "410 Gone")
+        );
+        assert!(parsed[3].1.is_err());
+        assert!(parsed[4].1.is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut meta = Dict::new();
+        assert!(aliases(&meta, "/new").unwrap().is_empty());
+
+        meta.insert(
+            ALIASES_KEY.into(),
+            Value::Array([Value::Str("/old".into())].into_iter().collect()),
+        );
+        assert_eq!(
+            aliases(&meta, "/new").unwrap(),
+            [redirect("/old", Some("/new"), "301 Moved Permanently")]
+        );
+
+        meta.insert(ALIASES_KEY.into(), Value::Str("/old".into()));
+        assert!(aliases(&meta, "/new").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let id = FileId::new_fake(VirtualPath::new("page"));
+        let route = Route::compile(&id, b"page".to_vec(), &mime::TEXT_HTML, true).unwrap();
+        let mut table = RoutingTable::default();
+        table.insert("/page".into(), route);
+        let mut seen = Redirects::default();
+        let sources = ["/old".into(), "/other".into()].into_iter().collect();
+        let valid = |source, target, seen: &Redirects| {
+            check(&redirect(source, target, "301"), &table, &sources, seen).is_ok()
+        };
+
+        assert!(valid("/old", Some("/page"), &seen));
+        assert!(valid("/old", Some("/page#top"), &seen));
+        assert!(valid("/old", Some("https://example.com/x"), &seen));
+        assert!(valid("/old", None, &seen));
+
+        // collisions
+        assert!(!valid("/page", Some("/page"), &seen));
+        seen.insert("/old".into());
+        assert!(!valid("/old", Some("/page"), &seen));
+
+        // missing or invalid targets
+        assert!(!valid("/a", Some("/missing"), &seen));
+        assert!(!valid("/a", Some("/other"), &seen));
+        assert!(!valid("/a", Some("//evil.com"), &seen));
+        assert!(!valid("/a", Some("page"), &seen));
+        assert!(!valid("/a b", Some("/page"), &seen));
+        assert!(!valid("a", Some("/page"), &seen));
+    }
+
+    #[test]
+    fn chains_whatever_the_order() {
+        let id = FileId::new_fake(VirtualPath::new("page"));
+        let route = Route::compile(&id, b"page".to_vec(), &mime::TEXT_HTML, true).unwrap();
+        let path = Path::new(REDIRECTS_PATH);
+        let declared = vec![
+            redirect("/a", Some("/b"), "301 Moved Permanently"),
+            redirect("/b", Some("/page"), "301 Moved Permanently"),
+            redirect("/x", Some("/y"), "301 Moved Permanently"),
+            redirect("/y", Some("/x"), "301 Moved Permanently"),
+        ];
+
+        for declared in [declared.clone(), declared.into_iter().rev().collect()] {
+            let mut table = RoutingTable::default();
+            table.insert("/page".into(), route.clone());
+            let mut redirects = Redirects::default();
+            let declared = declared.into_iter().map(|r| (path, r)).collect();
+
+            let errors = insert(declared, &mut table, &mut redirects);
+            assert_eq!(redirects, Redirects::from_iter(["/b".to_string()]));
+            assert_eq!(errors.len(), 3);
+            assert!(table.contains_key("/b") && !table.contains_key("/a"));
+        }
+    }
+}
@@ -1,11 +1,19 @@
+use crate::compiler::deps::Tracker;
 use crate::indexer::{FileSlot, Slots};
+use crate::report::Report;
+use codemap::SpanLoc;
+use grass::{Logger, StdLogger};
 use std::io::{self, ErrorKind};
 use std::path::Path;
 use typst::syntax::{FileId, VirtualPath};
 
 /// Make `Slots` VFS for Grass
 #[derive(Debug)]
-pub struct GrassSlotsFs<'a>(pub &'a Slots);
+pub struct GrassSlotsFs<'a> {
+    pub slots: &'a Slots,
+    /// Records every file looked up (ex. through `@use`)
+    pub tracker: &'a Tracker,
+}
 
 impl<'a> grass::Fs for GrassSlotsFs<'a> {
     fn is_dir(&self, _: &Path) -> bool {
@@ -28,6 +36,22 @@ impl<'a> GrassSlotsFs<'a> {
     fn slot(&self, path: &Path) -> Option<&FileSlot> {
         let vp = VirtualPath::new(path);
         let id = FileId::new(None, vp);
-        self.0.get(&id)
+        self.tracker.access(id);
+        self.slots.get(&id)
+    }
+}
+
+/// Prints `@warn` and `@debug` like normal, counting warnings in the `Report`
+#[derive(Debug)]
+pub struct ScssLogger<'a>(pub &'a Report);
+
+impl<'a> Logger for ScssLogger<'a> {
+    fn debug(&self, location: SpanLoc, message: &str) {
+        StdLogger.debug(location, message);
+    }
+
+    fn warn(&self, location: SpanLoc, message: &str) {
+        StdLogger.warn(location, message);
+        self.0.warning();
     }
 }
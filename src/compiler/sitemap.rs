@@ -1,40 +1,209 @@
-use crate::{RoutingTable, WatchArgs, web::route::Route};
+use crate::RoutingTable;
+use crate::compiler::feed::get_date;
+use crate::compiler::{Ctx, absolute};
+use crate::indexer::SlotType;
+use crate::web::route::{Route, status_page};
 use anyhow::Result;
 use mime_guess::mime;
 use std::fmt::Write;
+use typst::foundations::{Dict, Value};
 use typst::syntax::{FileId, VirtualPath};
 
-const SITEMAP_PATH: &str = "sitemap.xml";
-const BASE_URL: &str = "https://liamsnow.com";
+const SITEMAP_PATH: &str = "/sitemap.xml";
+/// Most urls allowed in one sitemap, past which
+/// `/sitemap.xml` becomes an index of `/sitemap-{n}.xml`
+const MAX_URLS: usize = 50_000;
 
-// TODO this can be run after indexing (might be more useful?)
-pub fn generate(routes: &RoutingTable, watch: &WatchArgs) -> Result<(String, Route)> {
-    let mut xml = String::with_capacity(2048);
+struct Entry<'a> {
+    url: &'a str,
+    /// `YYYY-MM-DD`
+    lastmod: Option<String>,
+}
+
+/// Replaces the sitemap(s) in `routing_table`, dropping any
+/// `/sitemap-{n}.xml` parts left over from a previous build
+pub fn update(ctx: &Ctx, routing_table: &mut RoutingTable) -> Result<()> {
+    let sitemaps = generate(ctx, routing_table)?;
+    replace(routing_table, sitemaps);
+    Ok(())
+}
+
+fn replace(routing_table: &mut RoutingTable, sitemaps: Vec<(String, Route)>) {
+    for i in 0.. {
+        if routing_table.remove(&part_path(i)).is_none() {
+            break;
+        }
+    }
+    routing_table.extend(sitemaps);
+}
+
+/// Generates a sitemap of every visible Typst page with a route, except
+/// status pages and those with `sitemap: false` or `noindex: true` in their
+/// `<page>` metadata
+fn generate(ctx: &Ctx, routes: &RoutingTable) -> Result<Vec<(String, Route)>> {
+    let mut entries = ctx
+        .slots
+        .values()
+        .filter(|slot| !slot.hidden && routes.contains_key(&slot.url))
+        .filter(|slot| status_page(&slot.url).is_none())
+        .filter_map(|slot| match &slot.ty {
+            SlotType::Typst(tslot) => Some((&slot.url, tslot.page_meta.as_ref()?)),
+            _ => None,
+        })
+        .filter(|(_, meta)| include(meta))
+        .map(|(url, meta)| Entry {
+            url,
+            lastmod: lastmod(meta),
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| a.url.cmp(b.url));
+
+    let base_url = &ctx.cfg.base_url;
+    let mut xmls = Vec::new();
+    if entries.len() <= MAX_URLS {
+        xmls.push((SITEMAP_PATH.to_string(), urlset(base_url, &entries)));
+    } else {
+        let chunks = entries.chunks(MAX_URLS).collect::<Vec<_>>();
+        xmls.push((SITEMAP_PATH.to_string(), index(base_url, chunks.len())));
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            xmls.push((part_path(i), urlset(base_url, chunk)));
+        }
+    }
+
+    xmls.into_iter()
+        .map(|(path, xml)| {
+            let id = FileId::new_fake(VirtualPath::new(&path));
+            let route = Route::compile(&id, xml.into(), &mime::TEXT_XML, ctx.watch.watch)?;
+            Ok((path, route))
+        })
+        .collect()
+}
+
+fn include(meta: &Dict) -> bool {
+    !matches!(meta.get("sitemap"), Ok(Value::Bool(false)))
+        && !matches!(meta.get("noindex"), Ok(Value::Bool(true)))
+}
+
+/// `updated` falling back to `written`
+fn lastmod(meta: &Dict) -> Option<String> {
+    let date = match get_date(meta, "updated") {
+        Ok(Some(date)) => date,
+        _ => get_date(meta, "written").ok()??,
+    };
+    Some(date.date().to_string())
+}
+
+fn part_path(i: usize) -> String {
+    format!("/sitemap-{}.xml", i + 1)
+}
+
+fn urlset(base_url: &str, entries: &[Entry]) -> String {
+    let mut xml = String::with_capacity(128 * entries.len() + 128);
     xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
     xml.push('\n');
     xml.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
     xml.push('\n');
 
-    for path in routes.keys() {
-        if path.contains('.') {
-            // skip files
-            continue;
-        }
-
-        let loc = if path == "/" {
-            BASE_URL.to_string()
-        } else {
-            format!("{}{}", BASE_URL, path)
-        };
+    for entry in entries {
         writeln!(xml, "  <url>").unwrap();
-        writeln!(xml, "    <loc>{loc}</loc>").unwrap();
+        writeln!(xml, "    <loc>{}</loc>", absolute(base_url, entry.url)).unwrap();
+        if let Some(lastmod) = &entry.lastmod {
+            writeln!(xml, "    <lastmod>{lastmod}</lastmod>").unwrap();
+        }
         writeln!(xml, "  </url>").unwrap();
     }
 
     xml.push_str("</urlset>");
+    xml
+}
 
-    let vp = VirtualPath::new("sitemap.xml");
-    let id = FileId::new_fake(vp);
-    let route = Route::compile(&id, xml.into(), &mime::TEXT_XML, watch.watch)?;
-    Ok((SITEMAP_PATH.to_string(), route))
+fn index(base_url: &str, parts: usize) -> String {
+    let mut xml = String::with_capacity(128 * parts + 128);
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push('\n');
+    xml.push_str(r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+    xml.push('\n');
+
+    for i in 0..parts {
+        writeln!(xml, "  <sitemap>").unwrap();
+        writeln!(xml, "    <loc>{}</loc>", absolute(base_url, &part_path(i))).unwrap();
+        writeln!(xml, "  </sitemap>").unwrap();
+    }
+
+    xml.push_str("</sitemapindex>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(fields: &[(&str, Value)]) -> Dict {
+        fields.iter().cloned().map(|(k, v)| (k.into(), v)).collect()
+    }
+
+    #[test]
+    fn test_include() {
+        assert!(include(&meta(&[])));
+        assert!(include(&meta(&[("sitemap", Value::Bool(true))])));
+        assert!(!include(&meta(&[("sitemap", Value::Bool(false))])));
+        assert!(!include(&meta(&[("noindex", Value::Bool(true))])));
+    }
+
+    #[test]
+    fn test_lastmod() {
+        let written = || ("written", Value::Str("2024-01-02".into()));
+        let updated = ("updated", Value::Str("2025-03-04".into()));
+
+        assert_eq!(lastmod(&meta(&[])), None);
+        assert_eq!(lastmod(&meta(&[written()])).unwrap(), "2024-01-02");
+        assert_eq!(lastmod(&meta(&[written(), updated])).unwrap(), "2025-03-04");
+    }
+
+    #[test]
+    fn test_urlset() {
+        let entries = [
+            Entry {
+                url: "/",
+                lastmod: None,
+            },
+            Entry {
+                url: "/blog",
+                lastmod: Some("2025-03-04".into()),
+            },
+        ];
+        let xml = urlset("https://example.com", &entries);
+
+        assert!(xml.contains("<loc>https://example.com</loc>\n  </url>"));
+        assert!(
+            xml.contains("<loc>https://example.com/blog</loc>\n    <lastmod>2025-03-04</lastmod>")
+        );
+    }
+
+    #[test]
+    fn test_replace() {
+        let route = || Route::empty("200 OK", None).unwrap();
+        let mut routing_table = RoutingTable::default();
+        for path in [SITEMAP_PATH.to_string(), part_path(0), part_path(1)] {
+            routing_table.insert(path, route());
+        }
+        routing_table.insert("/blog".into(), route());
+
+        replace(&mut routing_table, vec![(SITEMAP_PATH.into(), route())]);
+
+        let mut urls = routing_table.keys().collect::<Vec<_>>();
+        urls.sort();
+        assert_eq!(urls, ["/blog", SITEMAP_PATH]);
+    }
+
+    #[test]
+    fn test_index() {
+        let xml = index("https://example.com", 2);
+
+        assert!(xml.contains("<sitemapindex"));
+        assert!(xml.contains("<loc>https://example.com/sitemap-1.xml</loc>"));
+        assert!(xml.contains("<loc>https://example.com/sitemap-2.xml</loc>"));
+        assert!(!xml.contains("sitemap-3"));
+    }
 }
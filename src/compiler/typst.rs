@@ -5,9 +5,9 @@
 //! The program will need all files read at some point, so it async reads
 //! all files in the index, then all are shared here
 
-use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::diagnostic::{self as codespan, Label};
 use codespan_reporting::term;
-use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use codespan_reporting::term::termcolor::{ColorChoice, NoColor, StandardStream, WriteColor};
 use rustc_hash::FxHashMap;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
@@ -15,20 +15,30 @@ use typst::comemo::Track;
 use typst::diag::{
     FileError, FileResult, HintedStrResult, Severity, SourceDiagnostic, Warned, bail,
 };
-use typst::ecow::{EcoString, eco_format};
+use typst::ecow::{EcoString, EcoVec, eco_format};
 use typst::engine::Sink;
-use typst::foundations::{Bytes, Datetime, Dict, LocatableSelector, Scope, Value};
+use typst::foundations::{
+    Bytes, Content, Datetime, Dict, LocatableSelector, NativeElement, Recipe, Scope, StyleChain,
+    Styles, Transformation, Value, select_where,
+};
+use typst::introspection::Tag;
+use typst::layout::{Abs, BoxElem, Frame, FrameItem, PagedDocument};
+use typst::math::EquationElem;
 use typst::syntax::{FileId, Lines, Source, Span, SyntaxMode};
 use typst::text::{Font, FontBook};
 use typst::utils::LazyHash;
-use typst::{Feature, Library, LibraryExt, World, WorldExt};
+use typst::{Document, Feature, Library, LibraryExt, World, WorldExt};
 use typst_eval::eval_string;
-use typst_html::HtmlDocument;
+use typst_html::{FrameElem, HtmlAttr, HtmlDocument, HtmlElement, HtmlFrame, HtmlNode, HtmlTag};
+use typst_pdf::PdfOptions;
 
 use crate::WatchArgs;
-use crate::indexer::{FileSlot, SlotType, Slots};
+use crate::compiler::deps::Tracker;
+use crate::compiler::diagnostic::{Diagnostic, Diagnostics, Location};
+use crate::compiler::{Ctx, Fonts, Packages, build};
+use crate::indexer::{FileSlot, SlotType};
+use crate::report::Report;
 
-static BOOK: LazyLock<LazyHash<FontBook>> = LazyLock::new(|| LazyHash::new(FontBook::default()));
 static WORKDIR: LazyLock<PathBuf> = LazyLock::new(|| std::env::current_dir().unwrap());
 
 pub struct LiamsWorld<'a> {
@@ -42,33 +52,31 @@ pub struct LiamsWorld<'a> {
     slots: &'a FxHashMap<FileId, FileSlot>,
     root: &'a Path,
     watch: &'a WatchArgs,
+    report: &'a Report,
+    /// Records every file read
+    tracker: &'a Tracker,
+    fonts: &'a Fonts,
+    packages: &'a Packages,
 }
 
 impl<'a> LiamsWorld<'a> {
-    pub fn new(
-        main: FileId,
-        slots: &'a Slots,
-        inputs: Dict,
-        root: &'a Path,
-        watch: &'a WatchArgs,
-    ) -> Self {
+    pub fn new(main: FileId, ctx: &Ctx<'a>, inputs: Dict, tracker: &'a Tracker) -> Self {
         Self {
             main,
-            library: LazyHash::new(
-                Library::builder()
-                    .with_features([Feature::Html].into_iter().collect())
-                    .with_inputs(inputs)
-                    .build(),
-            ),
-            slots,
-            root,
-            watch,
+            library: LazyHash::new(library(inputs)),
+            slots: ctx.slots,
+            root: &ctx.cfg.root,
+            watch: ctx.watch,
+            report: ctx.report,
+            tracker,
+            fonts: ctx.fonts,
+            packages: ctx.packages,
         }
     }
 
-    /// Compile the document
-    pub fn compile(&mut self) -> anyhow::Result<HtmlDocument> {
-        let Warned { output, warnings } = typst::compile::<HtmlDocument>(self);
+    /// Compile the document (`HtmlDocument`, or `PagedDocument` through `pdf`)
+    pub fn compile<D: Document>(&mut self) -> anyhow::Result<D> {
+        let Warned { output, warnings } = typst::compile::<D>(self);
 
         match output {
             Ok(doc) => {
@@ -77,44 +85,99 @@ impl<'a> LiamsWorld<'a> {
             }
             Err(errors) => {
                 self.print_diagnostics(&errors, &warnings)?;
-                anyhow::bail!("compilation failed")
+                Err(self.failed("compilation failed", &errors))
             }
         }
     }
 
     /// Generate HTML output
-    pub fn html(&self, doc: &HtmlDocument) -> anyhow::Result<String> {
-        let mut html = match typst_html::html(doc) {
+    pub fn html(&self, mut doc: HtmlDocument) -> anyhow::Result<String> {
+        self.wrap_frames(&mut doc.root.children, &mut None);
+        let mut html = match typst_html::html(&doc) {
             Ok(c) => c,
             Err(errors) => {
                 self.print_diagnostics(&errors, &[])?;
-                anyhow::bail!("html output failed")
+                return Err(self.failed("html output failed", &errors));
             }
         };
 
         if self.watch.watch {
-            html = html.replacen(
-                "</head>",
-                &format!(
-                    r#"
-            <script>
-                (function() {{
-                    const ws = new WebSocket(`ws://{}:{}`);
-                    ws.onmessage = () => location.reload();
-                    ws.onclose = () => setTimeout(() => location.reload(), 1000);
-                }})();
-            </script>
-            </head>
-            "#,
-                    self.watch.watch_address, self.watch.watch_port
-                ),
-                1,
-            );
+            html = html.replacen("</head>", &(reload_script(self.watch) + "</head>"), 1);
         }
 
         Ok(html)
     }
 
+    /// Compile the document to pages and generate PDF output, without
+    /// `library`'s HTML only show rules
+    pub fn pdf(&mut self) -> anyhow::Result<Vec<u8>> {
+        let mut paged = Library::clone(&self.library);
+        paged.styles = Styles::new();
+        let html = std::mem::replace(&mut self.library, LazyHash::new(paged));
+        let doc = self.compile::<PagedDocument>();
+        self.library = html;
+
+        typst_pdf::pdf(&doc?, &PdfOptions::default()).or_else(|errors| {
+            self.print_diagnostics(&errors, &[])?;
+            Err(self.failed("pdf output failed", &errors))
+        })
+    }
+
+    /// Wraps each frame (`html.frame` and equations, see `library`) in an
+    /// element with alt text, placing inline ones on the text's baseline
+    fn wrap_frames(&self, children: &mut EcoVec<HtmlNode>, eq: &mut Option<Content>) {
+        for child in children.make_mut() {
+            match child {
+                HtmlNode::Tag(Tag::Start(elem, _)) if elem.is::<EquationElem>() => {
+                    *eq = Some(elem.clone());
+                }
+                // shown as something other than a frame
+                HtmlNode::Tag(Tag::End(loc, ..))
+                    if eq.as_ref().and_then(|eq| eq.location()) == Some(*loc) =>
+                {
+                    *eq = None;
+                }
+                HtmlNode::Element(elem) => self.wrap_frames(&mut elem.children, eq),
+                HtmlNode::Frame(frame) => {
+                    let wrapper = self.frame_wrapper(frame, eq.take());
+                    let frame = std::mem::replace(child, HtmlNode::Element(wrapper));
+                    if let HtmlNode::Element(wrapper) = child {
+                        wrapper.children.push(frame);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn frame_wrapper(&self, frame: &HtmlFrame, eq: Option<Content>) -> HtmlElement {
+        let eq = eq.as_ref().and_then(|eq| eq.to_packed::<EquationElem>());
+        let (block, class, role, alt) = match eq {
+            Some(eq) => {
+                let alt = self.range(eq.span()).and_then(|range| {
+                    let source = self.source(eq.span().id()?).ok()?;
+                    Some(math_alt(source.text().get(range)?))
+                });
+                (eq.block.get(StyleChain::default()), "math", "math", alt)
+            }
+            None => (false, "frame", "img", Some(frame_text(&frame.inner))),
+        };
+
+        let tag = HtmlTag::constant(if block { "div" } else { "span" });
+        let mut elem = HtmlElement::new(tag)
+            .with_attr(HtmlAttr::constant("class"), class)
+            .with_attr(HtmlAttr::constant("role"), role);
+        if let Some(alt) = alt.filter(|alt| !alt.is_empty()) {
+            elem = elem.with_attr(HtmlAttr::constant("aria-label"), alt);
+        }
+        if !block {
+            let depth = (frame.inner.height() - baseline(&frame.inner)) / frame.text_size;
+            let style = eco_format!("vertical-align: {:.3}em", -depth);
+            elem = elem.with_attr(HtmlAttr::constant("style"), style);
+        }
+        elem
+    }
+
     #[allow(unused)]
     /// Query the document
     /// Must be called after `.compile`
@@ -163,13 +226,92 @@ impl<'a> LiamsWorld<'a> {
     }
 }
 
+/// The standard library with HTML support, `inputs` as `sys.inputs` and
+/// `show math.equation: html.frame` so equations are rendered as SVGs
+/// (boxed when inline to stay in their paragraph). The show rules are
+/// its only styles, which `LiamsWorld::pdf` leaves out
+fn library(inputs: Dict) -> Library {
+    let mut library = Library::builder()
+        .with_features([Feature::Html].into_iter().collect())
+        .with_inputs(inputs)
+        .build();
+    let frame = Recipe::new(
+        Some(EquationElem::ELEM.select()),
+        Transformation::Func(FrameElem::ELEM.into()),
+        Span::detached(),
+    );
+    let inline = Recipe::new(
+        Some(select_where!(EquationElem, block => false)),
+        Transformation::Func(BoxElem::ELEM.into()),
+        Span::detached(),
+    );
+    library.styles.apply_one(inline.into());
+    library.styles.apply_one(frame.into());
+    library
+}
+
+/// The baseline of `frame` or else its first line's, which
+/// `layout_frame` leaves on a nested frame
+fn baseline(frame: &Frame) -> Abs {
+    if frame.has_baseline() {
+        return frame.baseline();
+    }
+    frame
+        .items()
+        .find_map(|(pos, item)| match item {
+            FrameItem::Group(group) => Some(pos.y + baseline(&group.frame)),
+            FrameItem::Text(_) => Some(pos.y),
+            _ => None,
+        })
+        .unwrap_or(frame.height())
+}
+
+/// Alt text of an equation from its source, `$ a^2 $` -> `a^2`
+fn math_alt(source: &str) -> String {
+    let inner = source.trim().trim_start_matches('$').trim_end_matches('$');
+    inner.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Every run of text in `frame`, separated by spaces
+fn frame_text(frame: &Frame) -> String {
+    let mut runs = Vec::new();
+    collect_text(frame, &mut runs);
+    runs.join(" ")
+}
+
+fn collect_text(frame: &Frame, runs: &mut Vec<String>) {
+    for (_, item) in frame.items() {
+        match item {
+            FrameItem::Group(group) => collect_text(&group.frame, runs),
+            FrameItem::Text(text) => runs.push(text.text.trim().to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// A file missing from the content directory, by its path in there
+fn not_found(id: FileId) -> FileError {
+    FileError::NotFound(id.vpath().as_rooted_path().into())
+}
+
+/// Reloads the page when the watcher rebuilds, or
+/// shows an overlay of the diagnostics if it failed
+pub fn reload_script(watch: &WatchArgs) -> String {
+    format!(
+        "<script>(function(url) {{\n{}}})(\"ws://{}:{}\");</script>",
+        include_str!("live_reload.js"),
+        watch.watch_address,
+        watch.watch_port
+    )
+}
+
 impl<'a> World for LiamsWorld<'a> {
     fn library(&self) -> &LazyHash<Library> {
         &self.library
     }
 
     fn book(&self) -> &LazyHash<FontBook> {
-        &BOOK
+        self.fonts.book()
     }
 
     fn main(&self) -> FileId {
@@ -177,32 +319,32 @@ impl<'a> World for LiamsWorld<'a> {
     }
 
     fn source(&self, id: FileId) -> FileResult<Source> {
-        let slot = self.slots.get(&id).ok_or(FileError::AccessDenied)?;
-
-        match &slot.ty {
-            SlotType::Typst(tslot) => Ok(tslot.source.clone()),
-            _ => Err(FileError::NotSource),
+        self.tracker.access(id);
+        match self.slots.get(&id) {
+            Some(slot) => match &slot.ty {
+                SlotType::Typst(tslot) => Ok(tslot.source.clone()),
+                _ => Err(FileError::NotSource),
+            },
+            None if id.package().is_some() => self.packages.source(id),
+            None => Err(not_found(id)),
         }
     }
 
     fn file(&self, id: FileId) -> FileResult<Bytes> {
+        self.tracker.access(id);
         match self.slots.get(&id) {
             Some(slot) => Ok(slot.file.clone()),
-            None => {
-                let pack = id.package().unwrap();
-                let vp = id.vpath();
-                println!("failed to get {id:?}, pack={pack:?}, vp={vp:?}");
-                Err(FileError::AccessDenied)
-            }
+            None if id.package().is_some() => self.packages.file(id),
+            None => Err(not_found(id)),
         }
     }
 
-    fn font(&self, _: usize) -> Option<Font> {
-        None
+    fn font(&self, index: usize) -> Option<Font> {
+        self.fonts.font(index)
     }
 
-    fn today(&self, _: Option<i64>) -> Option<Datetime> {
-        None
+    fn today(&self, offset: Option<i64>) -> Option<Datetime> {
+        build::today(offset)
     }
 }
 
@@ -213,20 +355,77 @@ impl<'a> LiamsWorld<'a> {
         warnings: &[SourceDiagnostic],
     ) -> Result<(), codespan_reporting::files::Error> {
         let writer = StandardStream::stderr(ColorChoice::Auto);
+
+        for _ in warnings.iter().filter(|d| !is_ignored(d)) {
+            self.report.warning();
+        }
+
+        self.emit_diagnostics(&mut writer.lock(), errors, warnings)
+    }
+
+    /// Make an error holding the (uncolored) rendered
+    /// and structured diagnostics
+    fn failed(&self, msg: &'static str, errors: &[SourceDiagnostic]) -> anyhow::Error {
+        let mut writer = NoColor::new(Vec::new());
+        let rendered = match self.emit_diagnostics(&mut writer, errors, &[]) {
+            Ok(()) => String::from_utf8_lossy(&writer.into_inner()).into(),
+            Err(e) => e.to_string(),
+        };
+
+        let diagnostics = errors
+            .iter()
+            .filter(|d| !is_ignored(d))
+            .map(|d| Diagnostic {
+                message: d.message.to_string(),
+                hints: d.hints.iter().map(|h| h.to_string()).collect(),
+                location: self.location(d.span),
+                trace: d
+                    .trace
+                    .iter()
+                    .map(|point| (point.v.to_string(), self.location(point.span)))
+                    .collect(),
+            })
+            .collect();
+
+        anyhow::Error::new(Diagnostics {
+            msg,
+            rendered,
+            diagnostics,
+        })
+    }
+
+    fn location(&self, span: Span) -> Option<Location> {
+        let id = span.id()?;
+        let range = self.range(span)?;
+        let lines = self.lookup(id).ok()?;
+
+        let (line, column) = lines.byte_to_line_column(range.start)?;
+        let snippet = lines.text().get(lines.line_to_range(line)?)?;
+        let end = match lines.byte_to_line_column(range.end) {
+            Some((end_line, end_column)) if end_line == line => end_column,
+            _ => usize::MAX,
+        };
+
+        let file = codespan_reporting::files::Files::name(self, id).ok()?;
+        Some(Location::new(file, line, column, snippet, end))
+    }
+
+    fn emit_diagnostics(
+        &self,
+        writer: &mut dyn WriteColor,
+        errors: &[SourceDiagnostic],
+        warnings: &[SourceDiagnostic],
+    ) -> Result<(), codespan_reporting::files::Error> {
         let config = codespan_reporting::term::Config::default();
 
         for diagnostic in warnings.iter().chain(errors) {
-            if diagnostic
-                .message
-                .contains("html export is under active development")
-            {
-                // dammit bruh
+            if is_ignored(diagnostic) {
                 continue;
             }
 
             let diag = match diagnostic.severity {
-                Severity::Error => Diagnostic::error(),
-                Severity::Warning => Diagnostic::warning(),
+                Severity::Error => codespan::Diagnostic::error(),
+                Severity::Warning => codespan::Diagnostic::warning(),
             }
             .with_message(diagnostic.message.clone())
             .with_notes(
@@ -238,16 +437,16 @@ impl<'a> LiamsWorld<'a> {
             )
             .with_labels(self.label(diagnostic.span).into_iter().collect());
 
-            term::emit(&mut writer.lock(), &config, self, &diag)?;
+            term::emit(writer, &config, self, &diag)?;
 
             // Stacktrace-like helper diagnostics.
             for point in &diagnostic.trace {
                 let message = point.v.to_string();
-                let help = Diagnostic::help()
+                let help = codespan::Diagnostic::help()
                     .with_message(message)
                     .with_labels(self.label(point.span).into_iter().collect());
 
-                term::emit(&mut writer.lock(), &config, self, &help)?;
+                term::emit(writer, &config, self, &help)?;
             }
         }
         Ok(())
@@ -268,6 +467,13 @@ impl<'a> LiamsWorld<'a> {
     }
 }
 
+fn is_ignored(diagnostic: &SourceDiagnostic) -> bool {
+    // dammit bruh
+    diagnostic
+        .message
+        .contains("html export is under active development")
+}
+
 type CodespanResult<T> = Result<T, CodespanError>;
 type CodespanError = codespan_reporting::files::Error;
 
@@ -328,3 +534,122 @@ impl<'a, 'b> codespan_reporting::files::Files<'a> for LiamsWorld<'b> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BuildArgs;
+    use crate::indexer::{MetaMap, Slots, TypstSlot};
+    use typst::syntax::VirtualPath;
+
+    /// Runs `f` on a world for `text` as a page, with Typst's embedded fonts
+    fn with_world<T>(text: &str, f: impl FnOnce(&mut LiamsWorld) -> T) -> T {
+        let id = FileId::new(None, VirtualPath::new("page.typ"));
+        let slot = FileSlot {
+            url: "/page".into(),
+            hidden: false,
+            mime: mime_guess::mime::TEXT_HTML,
+            file: Bytes::new(text.as_bytes().to_vec()),
+            ty: SlotType::Typst(TypstSlot {
+                source: Source::new(id, text.into()),
+                page_meta: None,
+                queries: None,
+                css: None,
+                feed: None,
+            }),
+        };
+        let slots = [(id, slot)].into_iter().collect::<Slots>();
+        let cfg = BuildArgs {
+            root: "content".into(),
+            base_url: String::new(),
+            system_fonts: false,
+            embedded_fonts: true,
+            package_path: None,
+            package_cache_path: None,
+        };
+        let watch = WatchArgs {
+            watch: false,
+            watch_address: [127, 0, 0, 1].into(),
+            watch_port: 0,
+        };
+        let report = Report::default();
+        let fonts = Fonts::load(&slots, &cfg, &report);
+        let ctx = Ctx {
+            slots: &slots,
+            metamap: &MetaMap::new(),
+            cfg: &cfg,
+            watch: &watch,
+            report: &report,
+            fonts: &fonts,
+            packages: &Packages::new(&cfg),
+        };
+
+        let tracker = Tracker::default();
+        f(&mut LiamsWorld::new(id, &ctx, Dict::new(), &tracker))
+    }
+
+    /// Compiles `text` as a page to HTML
+    fn html(text: &str) -> String {
+        with_world(text, |world| {
+            let doc = world.compile::<HtmlDocument>().unwrap();
+            world.html(doc).unwrap()
+        })
+    }
+
+    /// The opening tag of the wrapper with `class`
+    fn wrapper<'a>(html: &'a str, class: &str) -> &'a str {
+        let start = html.find(&format!(r#"class="{class}""#)).unwrap();
+        let start = html[..start].rfind('<').unwrap();
+        &html[start..start + html[start..].find('>').unwrap() + 1]
+    }
+
+    #[test]
+    fn equations() {
+        let html = html("Inline $a^2$ and\n\n$ x = sum_(i=1)^n i $");
+
+        let inline = wrapper(&html, "math");
+        assert!(inline.starts_with("<span "), "{inline}");
+        assert!(inline.contains(r#"role="math""#), "{inline}");
+        assert!(inline.contains(r#"aria-label="a^2""#), "{inline}");
+        assert!(inline.contains(r#"style="vertical-align: "#), "{inline}");
+
+        let block = wrapper(&html[html.find("</span>").unwrap()..], "math");
+        assert!(block.starts_with("<div "), "{block}");
+        assert!(block.contains(r#"role="math""#), "{block}");
+        assert!(
+            block.contains(r#"aria-label="x = sum_(i=1)^n i""#),
+            "{block}"
+        );
+        assert!(!block.contains("vertical-align"), "{block}");
+    }
+
+    #[test]
+    fn equation_not_a_frame() {
+        let html = html("#show math.equation: it => [eq]\n$a^2$ then #html.frame[hello]");
+
+        let frame = wrapper(&html, "frame");
+        assert!(frame.contains(r#"role="img""#), "{frame}");
+        assert!(frame.contains(r#"aria-label="hello""#), "{frame}");
+        assert!(!html.contains(r#"aria-label="a^2""#), "{html}");
+    }
+
+    #[test]
+    fn pdf_equations() {
+        let text = "Inline $a^2$ and\n\n$ x = sum_(i=1)^n i $";
+        let html = with_world(text, |world| {
+            let pdf = world.pdf().unwrap();
+            assert!(pdf.starts_with(b"%PDF-"));
+
+            // the show rules are back for HTML
+            let doc = world.compile::<HtmlDocument>().unwrap();
+            world.html(doc).unwrap()
+        });
+        assert!(html.contains(r#"role="math""#), "{html}");
+    }
+
+    #[test]
+    fn equation_alt() {
+        assert_eq!(math_alt("$a^2 + b^2$"), "a^2 + b^2");
+        assert_eq!(math_alt("$ sum_(i=1)^n\n    i $"), "sum_(i=1)^n i");
+    }
+}
@@ -0,0 +1,601 @@
+//! Decodes [WOFF2](https://www.w3.org/TR/WOFF2/) back into the TrueType/OpenType
+//! font it compresses (Typst can only read the latter), undoing the glyf/loca
+//! and hmtx transforms
+
+use anyhow::{Context, Result, bail, ensure};
+
+const SIGNATURE: &[u8; 4] = b"wOF2";
+const COLLECTION: u32 = u32::from_be_bytes(*b"ttcf");
+const HEADER_SIZE: usize = 48;
+
+const GLYF: [u8; 4] = *b"glyf";
+const LOCA: [u8; 4] = *b"loca";
+const HMTX: [u8; 4] = *b"hmtx";
+const HEAD: [u8; 4] = *b"head";
+
+/// Tags indexed by the low 6 bits of a table directory entry's flags
+#[rustfmt::skip]
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post",
+    b"cvt ", b"fpgm", b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT",
+    b"EBLC", b"gasp", b"hdmx", b"kern", b"LTSH", b"PCLT", b"VDMX", b"vhea",
+    b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC", b"JSTF", b"MATH",
+    b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar",
+    b"gvar", b"hsty", b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop",
+    b"trak", b"Zapf", b"Silf", b"Glat", b"Gloc", b"Feat", b"Sill",
+];
+
+// simple glyph flags
+const ON_CURVE: u8 = 0x01;
+const X_SHORT: u8 = 0x02;
+const Y_SHORT: u8 = 0x04;
+const X_SAME: u8 = 0x10;
+const Y_SAME: u8 = 0x20;
+const OVERLAP_SIMPLE: u8 = 0x40;
+
+// composite glyph flags
+const ARG_WORDS: u16 = 0x0001;
+const HAVE_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const HAVE_XY_SCALE: u16 = 0x0040;
+const HAVE_2X2: u16 = 0x0080;
+const HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+pub fn is_woff2(data: &[u8]) -> bool {
+    data.starts_with(SIGNATURE)
+}
+
+/// The sfnt (`.ttf`/`.otf`) font inside WOFF2 `data`
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut r = Reader::new(data);
+    ensure!(r.bytes(4)? == SIGNATURE, "not a WOFF2 file");
+    let flavor = r.u32()?;
+    ensure!(
+        flavor != COLLECTION,
+        "WOFF2 font collections aren't supported"
+    );
+    r.skip(4)?; // length
+    let num_tables = r.u16()?;
+    r.skip(2 + 4)?; // reserved, totalSfntSize
+    let compressed_size = r.u32()? as usize;
+    r.pos = HEADER_SIZE;
+
+    struct Entry {
+        tag: [u8; 4],
+        transformed: bool,
+        length: usize,
+    }
+    let mut entries = Vec::with_capacity(num_tables as usize);
+    for _ in 0..num_tables {
+        let flags = r.u8()?;
+        let tag = match flags & 0x3f {
+            63 => r.bytes(4)?.try_into()?,
+            i => *KNOWN_TAGS[i as usize],
+        };
+        let version = flags >> 6;
+        // glyf & loca are transformed by default, everything else is opt-in
+        let transformed = match tag {
+            GLYF | LOCA => version == 0,
+            _ => version != 0,
+        };
+        let orig_length = r.base128()? as usize;
+        let length = match transformed {
+            true => r.base128()? as usize,
+            false => orig_length,
+        };
+        entries.push(Entry {
+            tag,
+            transformed,
+            length,
+        });
+    }
+
+    let compressed = r.bytes(compressed_size)?;
+    let mut stream = Vec::new();
+    brotli::BrotliDecompress(&mut &compressed[..], &mut stream)
+        .context("decompressing font tables")?;
+
+    let mut tables = Vec::with_capacity(entries.len());
+    let mut offset = 0;
+    for entry in &entries {
+        let table = stream
+            .get(offset..offset + entry.length)
+            .context("font table out of bounds")?;
+        offset += entry.length;
+        tables.push((entry.tag, entry.transformed, table));
+    }
+
+    let find = |tag| tables.iter().find(|(t, ..)| *t == tag);
+    let mut out = Vec::with_capacity(tables.len());
+    let mut x_mins = None;
+    if let Some((_, true, glyf)) = find(GLYF) {
+        ensure!(
+            matches!(find(LOCA), Some((_, true, []))),
+            "transformed glyf without transformed loca"
+        );
+        let glyphs = reconstruct_glyf(glyf).context("reconstructing glyf")?;
+        out.push((GLYF, glyphs.glyf));
+        out.push((LOCA, glyphs.loca));
+        x_mins = Some(glyphs.x_mins);
+    }
+    for &(tag, transformed, table) in &tables {
+        match (tag, transformed) {
+            (GLYF | LOCA, true) => {}
+            (HMTX, true) => {
+                let x_mins = x_mins.as_deref().context("transformed hmtx without glyf")?;
+                let num_hmetrics = table_u16(&tables, b"hhea", 34)?;
+                let hmtx =
+                    reconstruct_hmtx(table, num_hmetrics, x_mins).context("reconstructing hmtx")?;
+                out.push((tag, hmtx));
+            }
+            (_, true) => bail!("unknown transform of {}", String::from_utf8_lossy(&tag)),
+            (_, false) => out.push((tag, table.to_vec())),
+        }
+    }
+
+    Ok(assemble(flavor, out))
+}
+
+/// Big endian u16 at `offset` of the untransformed table `tag`
+fn table_u16(tables: &[([u8; 4], bool, &[u8])], tag: &[u8; 4], offset: usize) -> Result<usize> {
+    let (.., table) = tables
+        .iter()
+        .find(|(t, ..)| t == tag)
+        .with_context(|| format!("missing {} table", String::from_utf8_lossy(tag)))?;
+    let mut r = Reader::new(table);
+    r.pos = offset;
+    Ok(r.u16()? as usize)
+}
+
+struct Glyphs {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    /// Each glyph's xMin, for rebuilding hmtx
+    x_mins: Vec<i16>,
+}
+
+/// Reconstructs glyf & loca from the transformed glyf table (WOFF2 §5.1)
+fn reconstruct_glyf(data: &[u8]) -> Result<Glyphs> {
+    let mut r = Reader::new(data);
+    r.skip(2)?; // reserved
+    let options = r.u16()?;
+    let num_glyphs = r.u16()? as usize;
+    let index_format = r.u16()?;
+    let mut sizes = [0; 7];
+    for size in &mut sizes {
+        *size = r.u32()? as usize;
+    }
+    let mut streams = [(); 7].map(|_| Reader::new(&[]));
+    for (stream, size) in streams.iter_mut().zip(sizes) {
+        *stream = Reader::new(r.bytes(size)?);
+    }
+    let [
+        n_contours,
+        n_points,
+        flags,
+        glyphs,
+        composites,
+        bboxes,
+        instructions,
+    ] = &mut streams;
+    let overlaps = match options & 1 {
+        0 => None,
+        _ => Some(r.bytes(num_glyphs.div_ceil(8))?),
+    };
+    let has_bbox = bboxes.bytes(num_glyphs.div_ceil(32) * 4)?;
+    let bit = |bits: &[u8], i: usize| bits[i >> 3] & (0x80 >> (i & 7)) != 0;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+    for i in 0..num_glyphs {
+        offsets.push(glyf.len());
+        let contours = n_contours.i16()?;
+        let bbox = match bit(has_bbox, i) {
+            true => Some(bboxes.bytes(8)?),
+            false => None,
+        };
+
+        match contours {
+            0 => {
+                ensure!(bbox.is_none(), "empty glyph {i} has a bbox");
+                x_mins.push(0);
+            }
+            -1 => {
+                let bbox = bbox.with_context(|| format!("composite glyph {i} has no bbox"))?;
+                let start = composites.pos;
+                let mut has_instructions = false;
+                loop {
+                    let flags = composites.u16()?;
+                    let mut len = 2 + if flags & ARG_WORDS != 0 { 4 } else { 2 };
+                    if flags & HAVE_SCALE != 0 {
+                        len += 2;
+                    } else if flags & HAVE_XY_SCALE != 0 {
+                        len += 4;
+                    } else if flags & HAVE_2X2 != 0 {
+                        len += 8;
+                    }
+                    composites.skip(len)?;
+                    has_instructions |= flags & HAVE_INSTRUCTIONS != 0;
+                    if flags & MORE_COMPONENTS == 0 {
+                        break;
+                    }
+                }
+
+                glyf.extend((-1i16).to_be_bytes());
+                glyf.extend(bbox);
+                glyf.extend(&composites.data[start..composites.pos]);
+                if has_instructions {
+                    let len = glyphs.u255()?;
+                    glyf.extend((len as u16).to_be_bytes());
+                    glyf.extend(instructions.bytes(len)?);
+                }
+                x_mins.push(i16::from_be_bytes([bbox[0], bbox[1]]));
+            }
+            contours if contours > 0 => {
+                let mut end_points = Vec::with_capacity(contours as usize);
+                let mut total = 0;
+                for _ in 0..contours {
+                    total += n_points.u255()?;
+                    ensure!(
+                        total > 0 && total <= 0x10000,
+                        "glyph {i} has bad point counts"
+                    );
+                    end_points.push((total - 1) as u16);
+                }
+
+                let mut points = Vec::with_capacity(total);
+                let (mut x, mut y) = (0i32, 0i32);
+                for &flag in flags.bytes(total)? {
+                    let (dx, dy) = triplet(flag & 0x7f, glyphs)?;
+                    x += dx;
+                    y += dy;
+                    points.push((x, y, flag & 0x80 == 0));
+                }
+                let instruction_len = glyphs.u255()?;
+
+                let (x_min, bbox) = match bbox {
+                    Some(bbox) => (i16::from_be_bytes([bbox[0], bbox[1]]), bbox.to_vec()),
+                    None => {
+                        let xs = points.iter().map(|p| p.0);
+                        let ys = points.iter().map(|p| p.1);
+                        let bbox = [xs.clone().min(), ys.clone().min(), xs.max(), ys.max()]
+                            .map(|v| v.unwrap_or(0) as i16);
+                        (bbox[0], bbox.iter().flat_map(|v| v.to_be_bytes()).collect())
+                    }
+                };
+
+                glyf.extend(contours.to_be_bytes());
+                glyf.extend(bbox);
+                for end in end_points {
+                    glyf.extend(end.to_be_bytes());
+                }
+                glyf.extend((instruction_len as u16).to_be_bytes());
+                glyf.extend(instructions.bytes(instruction_len)?);
+                let overlap = overlaps.is_some_and(|bits| bit(bits, i));
+                encode_points(&points, overlap, &mut glyf);
+                x_mins.push(x_min);
+            }
+            _ => bail!("glyph {i} has {contours} contours"),
+        }
+
+        glyf.resize(glyf.len().next_multiple_of(4), 0);
+    }
+    offsets.push(glyf.len());
+
+    let loca = match index_format {
+        0 => offsets
+            .iter()
+            .flat_map(|&offset| ((offset / 2) as u16).to_be_bytes())
+            .collect(),
+        _ => offsets
+            .iter()
+            .flat_map(|&offset| (offset as u32).to_be_bytes())
+            .collect(),
+    };
+    ensure!(
+        index_format != 0 || glyf.len() / 2 <= u16::MAX as usize,
+        "glyf too large for short loca offsets"
+    );
+
+    Ok(Glyphs { glyf, loca, x_mins })
+}
+
+/// The (dx, dy) of a point from its flag's low 7 bits and the glyph stream
+/// (WOFF2 §5.2, table of triplet encodings)
+fn triplet(flag: u8, glyphs: &mut Reader) -> Result<(i32, i32)> {
+    let sign = |value: i32, bit: u8| if flag & bit != 0 { value } else { -value };
+    let flag32 = flag as i32;
+    Ok(match flag {
+        0..10 => {
+            let b = glyphs.u8()? as i32;
+            (0, sign(((flag32 & 14) << 7) + b, 1))
+        }
+        10..20 => {
+            let b = glyphs.u8()? as i32;
+            (sign((((flag32 - 10) & 14) << 7) + b, 1), 0)
+        }
+        20..84 => {
+            let b = glyphs.u8()? as i32;
+            let f = flag32 - 20;
+            (
+                sign(1 + (f & 0x30) + (b >> 4), 1),
+                sign(1 + ((f & 0x0c) << 2) + (b & 0x0f), 2),
+            )
+        }
+        84..120 => {
+            let b = glyphs.bytes(2)?;
+            let f = flag32 - 84;
+            (
+                sign(1 + ((f / 12) << 8) + b[0] as i32, 1),
+                sign(1 + (((f % 12) >> 2) << 8) + b[1] as i32, 2),
+            )
+        }
+        120..124 => {
+            let b = glyphs
+                .bytes(3)?
+                .iter()
+                .map(|&b| b as i32)
+                .collect::<Vec<_>>();
+            (
+                sign((b[0] << 4) + (b[1] >> 4), 1),
+                sign(((b[1] & 0x0f) << 8) + b[2], 2),
+            )
+        }
+        124..128 => {
+            let b = glyphs
+                .bytes(4)?
+                .iter()
+                .map(|&b| b as i32)
+                .collect::<Vec<_>>();
+            (sign((b[0] << 8) + b[1], 1), sign((b[2] << 8) + b[3], 2))
+        }
+        128.. => unreachable!("flag is 7 bits"),
+    })
+}
+
+/// Appends the flags and x/y coordinate arrays of a simple glyph
+fn encode_points(points: &[(i32, i32, bool)], overlap: bool, out: &mut Vec<u8>) {
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let (mut last_x, mut last_y) = (0, 0);
+    for (i, &(x, y, on_curve)) in points.iter().enumerate() {
+        let mut flag = if on_curve { ON_CURVE } else { 0 };
+        if i == 0 && overlap {
+            flag |= OVERLAP_SIMPLE;
+        }
+        flag |= coordinate(x - last_x, X_SHORT, X_SAME, &mut xs);
+        flag |= coordinate(y - last_y, Y_SHORT, Y_SAME, &mut ys);
+        flags.push(flag);
+        (last_x, last_y) = (x, y);
+    }
+    out.extend(flags);
+    out.extend(xs);
+    out.extend(ys);
+}
+
+/// Writes the delta `d` as compactly as possible, returning its flag bits
+fn coordinate(d: i32, short: u8, same: u8, out: &mut Vec<u8>) -> u8 {
+    if d == 0 {
+        same
+    } else if d.abs() < 256 {
+        out.push(d.unsigned_abs() as u8);
+        short | if d > 0 { same } else { 0 }
+    } else {
+        out.extend((d as i16).to_be_bytes());
+        0
+    }
+}
+
+/// Reconstructs hmtx from its transformed version (WOFF2 §5.4), where
+/// left side bearings equal to the glyph's xMin may have been dropped
+fn reconstruct_hmtx(data: &[u8], num_hmetrics: usize, x_mins: &[i16]) -> Result<Vec<u8>> {
+    let num_glyphs = x_mins.len();
+    ensure!(
+        (1..=num_glyphs).contains(&num_hmetrics),
+        "bad numberOfHMetrics"
+    );
+    let mut r = Reader::new(data);
+    let flags = r.u8()?;
+    let mut advances = Vec::with_capacity(num_hmetrics);
+    for _ in 0..num_hmetrics {
+        advances.push(r.u16()?);
+    }
+    let mut lsbs = Vec::with_capacity(num_glyphs);
+    for (i, &x_min) in x_mins.iter().enumerate() {
+        let dropped = match i < num_hmetrics {
+            true => flags & 1 != 0,
+            false => flags & 2 != 0,
+        };
+        lsbs.push(if dropped { x_min } else { r.i16()? });
+    }
+
+    let mut out = Vec::with_capacity(num_hmetrics * 2 + num_glyphs * 2);
+    for (i, lsb) in lsbs.iter().enumerate() {
+        if let Some(advance) = advances.get(i) {
+            out.extend(advance.to_be_bytes());
+        }
+        out.extend(lsb.to_be_bytes());
+    }
+    Ok(out)
+}
+
+/// Builds an sfnt with the table directory sorted by tag
+/// and checksums (plus `head.checkSumAdjustment`) filled in
+fn assemble(flavor: u32, mut tables: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    tables.sort_by_key(|(tag, _)| *tag);
+    let num_tables = tables.len() as u16;
+    let entry_selector = num_tables.max(1).ilog2() as u16;
+    let search_range: u16 = (1 << entry_selector) * 16;
+
+    let mut out = Vec::new();
+    out.extend(flavor.to_be_bytes());
+    out.extend(num_tables.to_be_bytes());
+    out.extend(search_range.to_be_bytes());
+    out.extend(entry_selector.to_be_bytes());
+    out.extend((num_tables * 16 - search_range).to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head = None;
+    for (tag, table) in &mut tables {
+        if *tag == HEAD && table.len() >= 12 {
+            table[8..12].fill(0);
+            head = Some(offset + 8);
+        }
+        out.extend(*tag);
+        out.extend(checksum(table).to_be_bytes());
+        out.extend((offset as u32).to_be_bytes());
+        out.extend((table.len() as u32).to_be_bytes());
+        offset += table.len().next_multiple_of(4);
+    }
+    for (_, table) in &tables {
+        out.extend(table);
+        out.resize(out.len().next_multiple_of(4), 0);
+    }
+
+    if let Some(head) = head {
+        let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&out));
+        out[head..head + 4].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    out
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(n))
+            .context("unexpected end of font data")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    /// `UIntBase128`: big endian 7 bits per byte, high bit set to continue
+    fn base128(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = self.u8()?;
+            ensure!(!(i == 0 && byte == 0x80), "UIntBase128 has leading zeros");
+            ensure!(value >> 25 == 0, "UIntBase128 overflows");
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("UIntBase128 longer than 5 bytes")
+    }
+
+    /// `255UInt16`
+    fn u255(&mut self) -> Result<usize> {
+        Ok(match self.u8()? {
+            253 => self.u16()? as usize,
+            254 => self.u8()? as usize + 253 * 2,
+            255 => self.u8()? as usize + 253,
+            code => code as usize,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use typst::foundations::Bytes;
+    use typst::text::Font;
+
+    #[test]
+    fn read_base128() {
+        assert_eq!(Reader::new(&[0x3f]).base128().unwrap(), 63);
+        assert_eq!(Reader::new(&[0x81, 0x00]).base128().unwrap(), 128);
+        assert_eq!(
+            Reader::new(&[0x8f, 0xff, 0xff, 0xff, 0x7f])
+                .base128()
+                .unwrap(),
+            u32::MAX
+        );
+        assert!(Reader::new(&[0x80, 0x01]).base128().is_err());
+        assert!(
+            Reader::new(&[0x90, 0x80, 0x80, 0x80, 0x00])
+                .base128()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn read_u255() {
+        assert_eq!(Reader::new(&[252]).u255().unwrap(), 252);
+        assert_eq!(Reader::new(&[255, 0]).u255().unwrap(), 253);
+        assert_eq!(Reader::new(&[254, 0]).u255().unwrap(), 506);
+        assert_eq!(Reader::new(&[253, 0x01, 0x2c]).u255().unwrap(), 300);
+    }
+
+    #[test]
+    fn decode_content_fonts() {
+        let mut decoded = 0;
+        for entry in fs::read_dir("content/fonts").unwrap() {
+            let path = entry.unwrap().path();
+            let data = fs::read(&path).unwrap();
+            if !is_woff2(&data) {
+                continue;
+            }
+
+            let sfnt = decode(&data).unwrap_or_else(|e| panic!("{path:?}: {e:?}"));
+            assert_eq!(checksum(&sfnt), 0xB1B0AFBA, "{path:?}");
+            let font = Font::new(Bytes::new(sfnt), 0).expect("parses");
+            let face = font.ttf();
+            let global = face.global_bounding_box();
+            for c in "aQg&0é".chars() {
+                let glyph = face.glyph_index(c).expect("has glyph");
+                let bbox = face.glyph_bounding_box(glyph).expect("outlines");
+                assert!(bbox.x_min >= global.x_min && bbox.y_max <= global.y_max);
+            }
+            decoded += 1;
+        }
+        assert!(decoded > 0);
+    }
+
+    #[test]
+    fn reject_garbage() {
+        assert!(decode(b"wOF2").is_err());
+        assert!(decode(b"OTTO\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Writes the compiled `RoutingTable` to a directory so the
+//! site can be hosted by a CDN or nginx without the `web` server
+
+use crate::compiler::{Ctx, Fonts, Packages};
+use crate::report::Report;
+use crate::web::route::{self, Encoding, Route};
+use crate::{BuildArgs, ExportArgs, RoutingTable, WatchArgs, compiler, indexer};
+use anyhow::{Context, Result, bail};
+use httparse::{EMPTY_HEADER, Response, Status};
+use serde_json::{Map, Value, json};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+const MANIFEST_PATH: &str = "manifest.json";
+
+pub fn run(cfg: &BuildArgs, watch: &WatchArgs, args: ExportArgs) -> Result<()> {
+    let start = Instant::now();
+
+    println!("Starting Export");
+
+    let report = Report::default();
+
+    println!("Indexing...");
+    let (slots, metamap) = indexer::run(&cfg.root, &report)?;
+    let fonts = Fonts::load(&slots, cfg, &report);
+
+    println!("Compiling...");
+    let ctx = Ctx {
+        slots: &slots,
+        metamap: &metamap,
+        cfg,
+        watch,
+        report: &report,
+        fonts: &fonts,
+        packages: &Packages::new(cfg),
+    };
+    let (routing_table, ..) = compiler::run(&ctx)?;
+
+    report.finish(false)?;
+
+    println!("Writing to {}...", args.out.display());
+    let staging = sibling(&args.out, "new")?;
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let manifest = write_routes(&routing_table, &staging)?;
+    fs::write(
+        staging.join(MANIFEST_PATH),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    replace_dir(&staging, &args.out)?;
+
+    println!(
+        "Exported {} routes in {:?}",
+        routing_table.len(),
+        Instant::now() - start
+    );
+
+    Ok(())
+}
+
+/// Writes the body of every route (and its compressed siblings) under `out`,
+/// returning a manifest of each route's status, file, ETag and Cache-Control
+///
+/// Routes without a body (redirects and bare `410 Gone`s) get no file,
+/// only their status and `Location` in the manifest
+fn write_routes(routing_table: &RoutingTable, out: &Path) -> Result<Map<String, Value>> {
+    let mut urls = routing_table.keys().collect::<Vec<_>>();
+    urls.sort();
+
+    let mut manifest = Map::with_capacity(urls.len());
+
+    for url in urls {
+        let entry = write_route(url, &routing_table[url], out).with_context(|| url.clone())?;
+        manifest.insert(url.clone(), entry);
+    }
+
+    Ok(manifest)
+}
+
+fn write_route(url: &str, route: &Route, out: &Path) -> Result<Value> {
+    let identity = Parts::parse(&route.identity)?;
+    if identity.status != 200 && identity.body.is_empty() {
+        return Ok(json!({
+            "status": identity.status,
+            "location": identity.location,
+        }));
+    }
+
+    let rel = file_path(url, identity.content_type.starts_with("text/html"));
+    write_file(&out.join(&rel), identity.body)?;
+
+    let sibling = |encoding: Encoding, ext: &str| -> Result<Option<PathBuf>> {
+        let response = route.response(encoding);
+        if Arc::ptr_eq(&response.body, &route.identity.body) {
+            return Ok(None);
+        }
+        let parts = Parts::parse(response)?;
+        let mut rel = rel.clone().into_os_string();
+        rel.push(ext);
+        let rel = PathBuf::from(rel);
+        write_file(&out.join(&rel), parts.body)?;
+        Ok(Some(rel))
+    };
+    let brotli = sibling(Encoding::Brotli, ".br")?;
+    let zstd = sibling(Encoding::Zstd, ".zst")?;
+    let gzip = sibling(Encoding::Gzip, ".gz")?;
+
+    Ok(json!({
+        "status": identity.status,
+        "file": rel,
+        "brotli": brotli,
+        "zstd": zstd,
+        "gzip": gzip,
+        "content_type": identity.content_type,
+        "etag": String::from_utf8_lossy(&route.etag),
+        "cache_control": identity.cache_control,
+    }))
+}
+
+/// `out` with `.{suffix}` added to its name, so it lands on the same filesystem
+fn sibling(out: &Path, suffix: &str) -> Result<PathBuf> {
+    let Some(name) = out.file_name() else {
+        bail!("can't export to {}, name a directory", out.display());
+    };
+    let mut name = name.to_os_string();
+    name.push(".");
+    name.push(suffix);
+    Ok(out.with_file_name(name))
+}
+
+/// Swaps the freshly written `new` in for `out`, so files from earlier
+/// exports (deleted or renamed pages) don't linger
+fn replace_dir(new: &Path, out: &Path) -> Result<()> {
+    let old = sibling(out, "old")?;
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    if out.exists() {
+        fs::rename(out, &old).with_context(|| format!("moving {} aside", out.display()))?;
+    }
+    fs::rename(new, out).with_context(|| format!("moving export to {}", out.display()))?;
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("writing {}", path.display()))
+}
+
+/// The parts of a pre-serialized response we care about
+struct Parts<'a> {
+    status: u16,
+    location: Option<&'a str>,
+    content_type: &'a str,
+    cache_control: Option<&'a str>,
+    body: &'a [u8],
+}
+
+impl<'a> Parts<'a> {
+    fn parse(response: &'a route::Response) -> Result<Self> {
+        let mut headers = [EMPTY_HEADER; 16];
+        let mut resp = Response::new(&mut headers);
+
+        let Ok(Status::Complete(_)) = resp.parse(&response.head) else {
+            bail!("route contains an invalid HTTP response");
+        };
+
+        let header = |name: &str| {
+            resp.headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .and_then(|h| str::from_utf8(h.value).ok())
+        };
+
+        Ok(Parts {
+            status: resp.code.unwrap_or_default(),
+            location: header("location"),
+            content_type: header("content-type").unwrap_or_default(),
+            cache_control: header("cache-control"),
+            body: &response.body,
+        })
+    }
+}
+
+/// `/`               → `index.html`
+/// `/blog`           → `blog/index.html`
+/// `/styles/a.css`   → `styles/a.css`
+/// `/demo.html`      → `demo.html`
+/// `sitemap.xml`     → `sitemap.xml`
+fn file_path(url: &str, html: bool) -> PathBuf {
+    let rel = url.trim_matches('/');
+
+    if !html || rel.ends_with(".html") {
+        PathBuf::from(rel)
+    } else if rel.is_empty() {
+        PathBuf::from("index.html")
+    } else {
+        Path::new(rel).join("index.html")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mime_guess::mime;
+    use typst::syntax::{FileId, VirtualPath};
+
+    #[test]
+    fn test_file_path() {
+        assert_eq!(file_path("/", true), Path::new("index.html"));
+        assert_eq!(file_path("/blog", true), Path::new("blog/index.html"));
+        assert_eq!(
+            file_path("/blog/igloo/v1", true),
+            Path::new("blog/igloo/v1/index.html")
+        );
+        assert_eq!(file_path("/demo.html", true), Path::new("demo.html"));
+        assert_eq!(file_path("/styles/a.css", false), Path::new("styles/a.css"));
+        assert_eq!(file_path("sitemap.xml", false), Path::new("sitemap.xml"));
+    }
+
+    #[test]
+    fn parts_from_route() {
+        let id = FileId::new(None, VirtualPath::new("t.html"));
+        let body = "<html><body>".repeat(200).into_bytes();
+        let route = Route::compile(&id, body.clone(), &mime::TEXT_HTML, false).unwrap();
+
+        let identity = Parts::parse(&route.identity).unwrap();
+        assert_eq!(identity.status, 200);
+        assert_eq!(identity.content_type, "text/html");
+        assert_eq!(identity.cache_control, None);
+        assert_eq!(identity.body, body);
+
+        let brotli = Parts::parse(&route.brotli).unwrap();
+        assert!(brotli.body.len() < body.len());
+    }
+
+    #[test]
+    fn redirects_have_no_file() {
+        let out = std::env::temp_dir().join(format!("export-test-{}", std::process::id()));
+        let route = Route::empty("301 Moved Permanently", Some("/blog/post")).unwrap();
+
+        let entry = write_route("/old", &route, &out).unwrap();
+        assert_eq!(entry, json!({ "status": 301, "location": "/blog/post" }));
+        assert!(!out.join("old").exists());
+
+        let route = Route::empty("410 Gone", None).unwrap();
+        let entry = write_route("/gone", &route, &out).unwrap();
+        assert_eq!(entry, json!({ "status": 410, "location": null }));
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn stale_files_removed() {
+        let dir = std::env::temp_dir().join(format!("export-stale-{}", std::process::id()));
+        let out = dir.join("out");
+        write_file(&out.join("old-page/index.html"), b"old").unwrap();
+
+        let new = sibling(&out, "new").unwrap();
+        assert_eq!(new, dir.join("out.new"));
+        write_file(&new.join("index.html"), b"new").unwrap();
+        replace_dir(&new, &out).unwrap();
+
+        assert_eq!(fs::read(out.join("index.html")).unwrap(), b"new");
+        assert!(!out.join("old-page").exists());
+        assert!(!new.exists() && !dir.join("out.old").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
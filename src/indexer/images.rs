@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use image::metadata::Orientation;
+use image::{ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Widths (px) images are resized to, if they are wider
+/// (the largest also caps the size of re-encoded originals)
+pub const WIDTHS: [u32; 3] = [480, 960, 1920];
+
+#[derive(Debug)]
+pub struct ImageSlot {
+    /// Intrinsic size (after EXIF orientation)
+    pub width: u32,
+    pub height: u32,
+    /// Resized and re-encoded copies, each served at its own url
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// `/img/photo-480w.<hash>.avif`
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: ImageFormat,
+}
+
+impl ImageSlot {
+    /// Reads the header of the image at `url` and plans its variants:
+    ///  - AVIF and WebP at every width (including the original if not too large)
+    ///  - JPEG (or PNG with transparency) at every smaller width
+    ///    as a fallback, the original being the largest
+    pub fn new(file: &[u8], url: &str) -> Result<Self> {
+        let mut decoder = ImageReader::new(Cursor::new(file))
+            .with_guessed_format()?
+            .into_decoder()
+            .context("reading image header")?;
+        let alpha = decoder.color_type().has_alpha();
+        let (mut width, mut height) = decoder.dimensions();
+        if matches!(
+            decoder.orientation()?,
+            Orientation::Rotate90
+                | Orientation::Rotate270
+                | Orientation::Rotate90FlipH
+                | Orientation::Rotate270FlipH
+        ) {
+            (width, height) = (height, width);
+        }
+
+        // variant urls change with the source so they can be cached forever
+        let hash = xxh3_64(file);
+        let stem = url.rsplit_once('.').map_or(url, |(stem, _)| stem);
+
+        let mut widths = WIDTHS
+            .into_iter()
+            .filter(|w| *w < width)
+            .collect::<Vec<_>>();
+        let resized = widths.len();
+        if width <= WIDTHS[WIDTHS.len() - 1] {
+            widths.push(width);
+        }
+
+        let fallback = match alpha {
+            true => ImageFormat::Png,
+            false => ImageFormat::Jpeg,
+        };
+        let formats = [
+            (ImageFormat::Avif, widths.len()),
+            (ImageFormat::WebP, widths.len()),
+            (fallback, resized),
+        ];
+
+        let mut variants = Vec::new();
+        for (format, count) in formats {
+            for &w in &widths[..count] {
+                let h = (height as u64 * w as u64 / width as u64).max(1) as u32;
+                let ext = format.extensions_str()[0];
+                variants.push(Variant {
+                    url: format!("{stem}-{w}w.{:08x}.{ext}", hash as u32),
+                    width: w,
+                    height: h,
+                    format,
+                });
+            }
+        }
+
+        Ok(ImageSlot {
+            width,
+            height,
+            variants,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbaImage};
+
+    fn png(width: u32, height: u32, alpha: bool) -> Vec<u8> {
+        let mut img = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+        if !alpha {
+            img = DynamicImage::ImageRgb8(img.to_rgb8());
+        }
+        let mut buf = Cursor::new(Vec::new());
+        img.write_to(&mut buf, ImageFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    fn plan(slot: &ImageSlot) -> Vec<(ImageFormat, u32)> {
+        slot.variants.iter().map(|v| (v.format, v.width)).collect()
+    }
+
+    #[test]
+    fn variants() {
+        let slot = ImageSlot::new(&png(1000, 500, false), "/img/a.png").unwrap();
+        assert_eq!((slot.width, slot.height), (1000, 500));
+        assert_eq!(
+            plan(&slot),
+            [
+                (ImageFormat::Avif, 480),
+                (ImageFormat::Avif, 960),
+                (ImageFormat::Avif, 1000),
+                (ImageFormat::WebP, 480),
+                (ImageFormat::WebP, 960),
+                (ImageFormat::WebP, 1000),
+                (ImageFormat::Jpeg, 480),
+                (ImageFormat::Jpeg, 960),
+            ]
+        );
+        assert_eq!(slot.variants[0].height, 240);
+        assert!(slot.variants[0].url.starts_with("/img/a-480w."));
+        assert!(slot.variants[0].url.ends_with(".avif"));
+        assert!(slot.variants[3].url.ends_with(".webp"));
+        assert!(slot.variants[7].url.ends_with(".jpg"));
+
+        let slot = ImageSlot::new(&png(4000, 100, false), "/img/big.png").unwrap();
+        assert_eq!(slot.variants.iter().map(|v| v.width).max(), Some(1920));
+    }
+
+    #[test]
+    fn small_with_alpha() {
+        let slot = ImageSlot::new(&png(100, 80, true), "/b.png").unwrap();
+        assert_eq!(
+            plan(&slot),
+            [(ImageFormat::Avif, 100), (ImageFormat::WebP, 100)]
+        );
+
+        let slot = ImageSlot::new(&png(600, 80, true), "/b.png").unwrap();
+        assert_eq!(slot.variants.last().unwrap().format, ImageFormat::Png);
+    }
+
+    #[test]
+    fn url_changes_with_content() {
+        let a = ImageSlot::new(&png(100, 80, false), "/c.png").unwrap();
+        let b = ImageSlot::new(&png(100, 81, false), "/c.png").unwrap();
+        assert_ne!(a.variants[0].url, b.variants[0].url);
+    }
+}
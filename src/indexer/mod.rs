@@ -1,8 +1,9 @@
-use crate::indexer::meta::{CSS_KEY, PAGE_KEY, QUERY_KEY};
+use crate::indexer::meta::{CSS_KEY, FEED_KEY, PAGE_KEY, QUERY_KEY};
+use crate::report::Report;
 use anyhow::{Context, Result, anyhow, bail};
 use mime_guess::{Mime, mime};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use rustc_hash::{FxBuildHasher, FxHashMap};
+use rustc_hash::{FxBuildHasher, FxHashMap, FxHashSet};
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -11,8 +12,11 @@ use typst::foundations::{Bytes, Dict, Value};
 use typst::syntax::package::PackageSpec;
 use typst::syntax::{FileId, Source, VirtualPath};
 
+mod images;
 mod meta;
 
+pub use images::{ImageSlot, Variant};
+
 #[derive(Debug)]
 pub struct FileSlot {
     pub url: String,
@@ -26,6 +30,7 @@ pub struct FileSlot {
 pub enum SlotType {
     Typst(TypstSlot),
     Scss,
+    Image(ImageSlot),
     Other,
 }
 
@@ -36,6 +41,8 @@ pub struct TypstSlot {
     pub page_meta: Option<Dict>,
     pub queries: Option<Dict>,
     pub css: Option<String>,
+    /// `#metadata((feed: "/blog/")) <feed>`
+    pub feed: Option<Dict>,
 }
 
 struct WalkEntry {
@@ -49,12 +56,14 @@ pub type MetaMap = BTreeMap<String, Dict>;
 /// Indexes root directory
 ///  1. recursively walk the directory, finding all files
 ///  2. read each file + grab metadata from typst files
-pub fn run(root: &Path) -> Result<(Slots, MetaMap)> {
+///
+/// Files which fail to read or parse are left out and recorded in `report`
+pub fn run(root: &Path, report: &Report) -> Result<(Slots, MetaMap)> {
     println!("  Walking...");
     let entries = walk(root)?;
 
     println!("  Reading...");
-    let (slots, metamap) = read_and_parse(entries)?;
+    let (slots, metamap) = read_and_parse(entries, report);
 
     Ok((slots, metamap))
 }
@@ -90,20 +99,23 @@ fn walk(root: &Path) -> Result<Vec<WalkEntry>> {
     Ok(entries)
 }
 
-fn read_and_parse(entries: Vec<WalkEntry>) -> Result<(Slots, MetaMap)> {
+fn read_and_parse(entries: Vec<WalkEntry>, report: &Report) -> (Slots, MetaMap) {
     let results = entries
         .into_par_iter()
-        .map(|entry| {
-            let rootless_str = entry
-                .rootless
-                .to_str()
-                .with_context(|| format!("`{:?}`'s path is not valid UTF-8", entry.path))?;
-
-            let id = make_id(&entry.rootless, rootless_str)?;
-            let slot = FileSlot::new(id, entry.path, rootless_str)?;
-            Ok((id, slot))
+        .filter_map(|entry| match read_entry(&entry) {
+            Ok(res) => Some(res),
+            Err(e) => {
+                let url = entry
+                    .rootless
+                    .to_str()
+                    .filter(|s| !is_hidden(s))
+                    .map(make_url);
+                let e = e.context(format!("{:?}", entry.rootless));
+                report.error(&entry.rootless, url.as_deref(), e);
+                None
+            }
         })
-        .collect::<Result<Vec<(FileId, FileSlot)>>>()?;
+        .collect::<Vec<(FileId, FileSlot)>>();
 
     let mut slots = HashMap::with_capacity_and_hasher(results.len(), FxBuildHasher);
     let mut metamap = BTreeMap::new();
@@ -117,12 +129,136 @@ fn read_and_parse(entries: Vec<WalkEntry>) -> Result<(Slots, MetaMap)> {
         slots.insert(id, slot);
     }
 
-    Ok((slots, metamap))
+    (slots, metamap)
+}
+
+/// Re-reads files (relative to `root`) which were created,
+/// modified, or removed, updating `slots` and `metamap` in place
+///
+/// Returns None if any path is (or was) a directory, which
+/// requires a full `run` since it can hold any number of files
+pub fn update(
+    root: &Path,
+    slots: &mut Slots,
+    metamap: &mut MetaMap,
+    paths: &[PathBuf],
+    report: &Report,
+) -> Result<Option<Changes>> {
+    let mut ids = Vec::with_capacity(paths.len());
+    for rootless in paths {
+        let rootless_str = rootless
+            .to_str()
+            .with_context(|| format!("`{rootless:?}`'s path is not valid UTF-8"))?;
+        let id = make_id(rootless, rootless_str)?;
+
+        let path = root.join(rootless);
+        if path.is_dir() || (!path.exists() && !slots.contains_key(&id)) {
+            return Ok(None);
+        }
+
+        ids.push((id, rootless));
+    }
+
+    let mut changes = Changes::default();
+
+    for (id, rootless) in ids {
+        changes.ids.insert(id);
+
+        let old = slots.remove(&id);
+        let old_meta = old.as_ref().and_then(|slot| {
+            metamap
+                .remove(&slot.url)
+                .map(|meta| (slot.url.clone(), meta))
+        });
+
+        let entry = WalkEntry {
+            path: root.join(rootless),
+            rootless: rootless.to_path_buf(),
+        };
+
+        let new = if entry.path.is_file() {
+            read_and_parse(vec![entry], report).0.remove(&id)
+        } else {
+            None
+        };
+
+        let new_meta = new.as_ref().and_then(|slot| match &slot.ty {
+            SlotType::Typst(tslot) => tslot.page_meta.clone().map(|meta| (slot.url.clone(), meta)),
+            _ => None,
+        });
+
+        let old_variants = old.as_ref().map_or(&[][..], variants);
+        let new_variants = new.as_ref().map_or(&[][..], variants);
+        if old_variants != new_variants {
+            changes.images = true;
+            changes.removed.extend(
+                old_variants
+                    .iter()
+                    .filter(|v| !new_variants.contains(v))
+                    .map(|v| v.url.clone()),
+            );
+        }
+
+        if old_meta != new_meta {
+            changes
+                .meta
+                .extend(old_meta.into_iter().map(|(url, _)| url));
+            changes
+                .meta
+                .extend(new_meta.iter().map(|(url, _)| url.clone()));
+        }
+
+        if let Some((url, meta)) = new_meta {
+            metamap.insert(url, meta);
+        }
+
+        match new {
+            Some(slot) => {
+                slots.insert(id, slot);
+            }
+            None => changes
+                .removed
+                .extend(old.filter(|slot| !slot.hidden).map(|slot| slot.url)),
+        }
+    }
+
+    Ok(Some(changes))
+}
+
+/// What `update` changed
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Every file which was re-read (or removed)
+    pub ids: FxHashSet<FileId>,
+    /// Urls of pages whose metadata changed (or were added/removed)
+    pub meta: Vec<String>,
+    /// Urls of routes which no longer exist
+    pub removed: Vec<String>,
+    /// If any image variant was added or removed
+    pub images: bool,
+}
+
+fn variants(slot: &FileSlot) -> &[Variant] {
+    match &slot.ty {
+        SlotType::Image(image) if !slot.hidden => &image.variants,
+        _ => &[],
+    }
+}
+
+fn read_entry(entry: &WalkEntry) -> Result<(FileId, FileSlot)> {
+    let rootless_str = entry
+        .rootless
+        .to_str()
+        .with_context(|| format!("`{:?}`'s path is not valid UTF-8", entry.path))?;
+
+    let id = make_id(&entry.rootless, rootless_str)?;
+    let slot = FileSlot::new(id, &entry.path, rootless_str)?;
+    Ok((id, slot))
 }
 
 impl FileSlot {
-    fn new(id: FileId, path: PathBuf, rootless_str: &str) -> Result<Self> {
-        let file = fs::read(&path)?;
+    fn new(id: FileId, path: &Path, rootless_str: &str) -> Result<Self> {
+        let file = fs::read(path)?;
 
         let url = make_url(rootless_str);
         let hidden = is_hidden(rootless_str);
@@ -142,6 +278,7 @@ impl FileSlot {
         let ty = match ext {
             "typ" => SlotType::Typst(TypstSlot::new(id, &file, hidden, &url)?),
             "scss" => SlotType::Scss,
+            "jpg" | "jpeg" | "png" | "webp" => SlotType::Image(ImageSlot::new(&file, &url)?),
             _ => SlotType::Other,
         };
 
@@ -166,6 +303,7 @@ impl TypstSlot {
                 page_meta: None,
                 queries: None,
                 css: None,
+                feed: None,
             });
         }
 
@@ -188,6 +326,7 @@ impl TypstSlot {
                     _ => None,
                 })
             }),
+            feed: all_meta.remove(FEED_KEY),
         })
     }
 }
@@ -197,7 +336,7 @@ impl TypstSlot {
 /// `cat/dog.typ`    → `/cat/dog`
 /// `cat/robots.txt` → `/cat/robots.txt`
 /// `style.scss`     → `/style.css`
-fn make_url(rel: &str) -> String {
+pub fn make_url(rel: &str) -> String {
     if let Some(stem) = rel.strip_suffix(".typ") {
         if stem == "index" {
             "/".to_string()
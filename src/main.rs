@@ -1,3 +1,8 @@
+use crate::compiler::deps::Deps;
+use crate::compiler::{Assets, Ctx, Fonts, Packages, Redirects};
+use crate::indexer::{MetaMap, Slots};
+use crate::report::Report;
+use crate::web::log::LogFormat;
 use crate::web::route::Route;
 use ::typst::comemo;
 use anyhow::Result;
@@ -5,13 +10,16 @@ use arc_swap::ArcSwap;
 use clap::Parser;
 use rustc_hash::FxHashMap;
 use std::net::IpAddr;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::{Arc, LazyLock};
 use std::thread::available_parallelism;
 use std::time::Instant;
 
 mod compiler;
+mod export;
 mod indexer;
+mod metrics;
+mod report;
 mod update;
 mod watcher;
 mod web;
@@ -19,9 +27,8 @@ mod web;
 #[derive(clap::Parser, Debug)]
 #[command(name = "liamsnow-com")]
 pub struct Args {
-    /// Path to content directory
-    #[arg(short, long, env = "CONTENT_DIR", default_value = "./content")]
-    pub root: PathBuf,
+    #[command(flatten)]
+    pub build: BuildArgs,
 
     #[command(flatten)]
     pub web: WebArgs,
@@ -35,6 +42,61 @@ pub struct Args {
     /// Number of threads to use. Defaults to number of cores.
     #[arg(short, long, env = "NUM_THREADS")]
     pub threads: Option<usize>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Defaults to building then serving the site
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Build the site and write every route to a directory
+    Export(ExportArgs),
+    /// Build the site without serving, reporting every error
+    Check(CheckArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// Directory to write the exported site to, replaced on every export
+    #[arg(short, long, env = "EXPORT_DIR", default_value = "./dist")]
+    pub out: PathBuf,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CheckArgs {
+    /// Fail if there are any warnings
+    #[arg(long, env = "DENY_WARNINGS")]
+    pub deny_warnings: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BuildArgs {
+    /// Path to content directory
+    #[arg(short, long, env = "CONTENT_DIR", default_value = "./content")]
+    pub root: PathBuf,
+
+    /// Absolute url the site is hosted at (for sitemaps and feeds)
+    #[arg(long, env = "BASE_URL", default_value = "https://liamsnow.com")]
+    pub base_url: String,
+
+    /// Let Typst use the system's fonts (after those in the content directory)
+    #[arg(long, env = "SYSTEM_FONTS")]
+    pub system_fonts: bool,
+
+    /// Let Typst use its embedded fonts (Libertinus, New Computer Modern, ..)
+    #[arg(long, env = "EMBEDDED_FONTS")]
+    pub embedded_fonts: bool,
+
+    /// Local Typst packages (`<namespace>/<name>/<version>/`),
+    /// defaults to Typst's (`~/.local/share/typst/packages`)
+    #[arg(long, env = "TYPST_PACKAGE_PATH")]
+    pub package_path: Option<PathBuf>,
+
+    /// Downloaded Typst packages, searched after `--package-path`,
+    /// defaults to Typst's (`~/.cache/typst/packages`)
+    #[arg(long, env = "TYPST_PACKAGE_CACHE_PATH")]
+    pub package_cache_path: Option<PathBuf>,
 }
 
 #[derive(clap::Args, Debug, Clone)]
@@ -46,6 +108,42 @@ pub struct WebArgs {
     /// Port number (1-65535)
     #[arg(short, long, env = "PORT", default_value_t = 3232)]
     pub port: u16,
+
+    /// Connections held open at once, beyond which new ones get a 503
+    #[arg(long, env = "MAX_CONNECTIONS", default_value_t = 1000)]
+    pub max_connections: usize,
+
+    /// TLS certificate chain (PEM) to serve HTTPS with, reloaded when it changes
+    #[arg(long, env = "TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// TLS private key (PEM), reloaded when it changes
+    #[arg(long, env = "TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Port for plain HTTP which redirects to HTTPS (ex. 80). Disabled if unset
+    #[arg(long, env = "HTTP_REDIRECT_PORT", requires = "tls_cert")]
+    pub http_redirect_port: Option<u16>,
+
+    /// File to write the access log to (`-` for stdout). Disabled if unset
+    #[arg(long, env = "ACCESS_LOG")]
+    pub access_log: Option<PathBuf>,
+
+    /// Access log format
+    #[arg(long, env = "ACCESS_LOG_FORMAT", value_enum, default_value_t = LogFormat::Combined)]
+    pub access_log_format: LogFormat,
+
+    /// Size (bytes) at which the access log file is rotated to `<file>.1`
+    #[arg(long, env = "ACCESS_LOG_MAX_SIZE", default_value_t = 64 * 1024 * 1024)]
+    pub access_log_max_size: u64,
+
+    /// Prometheus metrics hostname or IP address to bind to
+    #[arg(long, env = "METRICS_ADDRESS", default_value = "127.0.0.1")]
+    pub metrics_address: IpAddr,
+
+    /// Prometheus metrics port number (1-65535). Disabled if unset
+    #[arg(long, env = "METRICS_PORT")]
+    pub metrics_port: Option<u16>,
 }
 
 #[derive(clap::Args, Debug, Clone)]
@@ -84,20 +182,32 @@ pub static ROUTING_TABLE: LazyLock<ArcSwap<RoutingTable>> =
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    update::set_cfg(args.update)?;
 
     // use all threads for building
     rayon::ThreadPoolBuilder::new().build_global()?;
 
-    build(&args.root, &args.watch)?;
+    if let Some(command) = args.command {
+        let watch = WatchArgs {
+            watch: false,
+            ..args.watch
+        };
+        return match command {
+            Command::Export(export_args) => export::run(&args.build, &watch, export_args),
+            Command::Check(check_args) => check(&args.build, &watch, check_args),
+        };
+    }
+
+    update::set_cfg(args.update)?;
+
+    let (report, site) = build(&args.build, &args.watch)?;
 
     let mut num_threads = args
         .threads
         .unwrap_or(available_parallelism().map(|n| n.get()).unwrap());
 
     if args.watch.watch {
-        num_threads -= 3;
-        if let Err(e) = watcher::run(args.root, args.watch) {
+        num_threads = num_threads.saturating_sub(3).max(1);
+        if let Err(e) = watcher::run(args.build, args.watch, site, &report) {
             eprintln!("Watcher error: {e}");
         }
     }
@@ -105,22 +215,166 @@ fn main() -> Result<()> {
     web::run(args.web, num_threads)
 }
 
-fn build(root: &Path, watch: &WatchArgs) -> Result<()> {
+/// Everything kept between builds for incremental rebuilds
+pub struct Site {
+    slots: Slots,
+    metamap: MetaMap,
+    deps: Deps,
+    assets: Assets,
+    redirects: Redirects,
+    fonts: Fonts,
+    /// Files which failed last build, always re-read
+    broken: Vec<PathBuf>,
+}
+
+/// Builds and stores the routing table, returning the
+/// report of pages which failed (and are served as error pages)
+fn build(cfg: &BuildArgs, watch: &WatchArgs) -> Result<(Report, Site)> {
     let start = Instant::now();
 
     println!("Starting Build");
 
+    let report = Report::default();
+
     println!("Indexing...");
-    let (slots, metamap) = indexer::run(root)?;
+    let (slots, metamap) = indexer::run(&cfg.root, &report)?;
+    let fonts = Fonts::load(&slots, cfg, &report);
 
     println!("Compiling...");
-    let routing_table = compiler::run(slots, metamap, root, watch)?;
+    let ctx = Ctx {
+        slots: &slots,
+        metamap: &metamap,
+        cfg,
+        watch,
+        report: &report,
+        fonts: &fonts,
+        packages: &Packages::new(cfg),
+    };
+    let (routing_table, deps, assets, redirects) = compiler::run(&ctx)?;
+
+    ROUTING_TABLE.store(Arc::new(routing_table));
+
+    let elapsed = Instant::now() - start;
+    metrics::build(elapsed, report.failures().is_empty());
+    println!("Build done in {elapsed:?}");
+    report.print();
+
+    comemo::evict(10);
+
+    let broken = report.failures().iter().map(|f| f.path.clone()).collect();
+    let site = Site {
+        slots,
+        metamap,
+        deps,
+        assets,
+        redirects,
+        fonts,
+        broken,
+    };
+    Ok((report, site))
+}
+
+/// Re-indexes `paths` (relative to `cfg.root`) and recompiles only the
+/// routes they affect, patching the routing table in place
+fn rebuild(
+    cfg: &BuildArgs,
+    watch: &WatchArgs,
+    site: &mut Site,
+    paths: Vec<PathBuf>,
+) -> Result<Report> {
+    let start = Instant::now();
+
+    println!("Starting Rebuild");
+
+    let report = Report::default();
+    let mut paths = paths;
+    paths.extend(site.broken.iter().cloned());
+    paths.sort();
+    paths.dedup();
+
+    let Some(changes) = indexer::update(
+        &cfg.root,
+        &mut site.slots,
+        &mut site.metamap,
+        &paths,
+        &report,
+    )?
+    else {
+        let (report, new_site) = build(cfg, watch)?;
+        *site = new_site;
+        return Ok(report);
+    };
+
+    if compiler::fonts::changed(&changes) {
+        site.fonts = Fonts::load(&site.slots, cfg, &report);
+    }
+    let dirty = compiler::deps::affected(&site.slots, &site.deps, &changes);
+
+    println!("Recompiling {} file(s)...", dirty.len());
+    let mut routing_table = RoutingTable::clone(&ROUTING_TABLE.load());
+    for url in &changes.removed {
+        routing_table.remove(url);
+    }
+
+    let ctx = Ctx {
+        slots: &site.slots,
+        metamap: &site.metamap,
+        cfg,
+        watch,
+        report: &report,
+        fonts: &site.fonts,
+        packages: &Packages::new(cfg),
+    };
+    compiler::update(
+        &ctx,
+        &dirty,
+        &mut routing_table,
+        &mut site.deps,
+        &mut site.assets,
+        &mut site.redirects,
+    )?;
 
     ROUTING_TABLE.store(Arc::new(routing_table));
 
-    println!("Build done in {:?}", Instant::now() - start);
+    let elapsed = Instant::now() - start;
+    metrics::build(elapsed, report.failures().is_empty());
+    println!("Rebuild done in {elapsed:?}");
+    report.print();
 
     comemo::evict(10);
 
-    Ok(())
+    site.broken = report.failures().iter().map(|f| f.path.clone()).collect();
+    Ok(report)
+}
+
+fn check(cfg: &BuildArgs, watch: &WatchArgs, args: CheckArgs) -> Result<()> {
+    let start = Instant::now();
+
+    println!("Starting Check");
+
+    let report = Report::default();
+
+    println!("Indexing...");
+    let (slots, metamap) = indexer::run(&cfg.root, &report)?;
+    let fonts = Fonts::load(&slots, cfg, &report);
+
+    println!("Compiling...");
+    let ctx = Ctx {
+        slots: &slots,
+        metamap: &metamap,
+        cfg,
+        watch,
+        report: &report,
+        fonts: &fonts,
+        packages: &Packages::new(cfg),
+    };
+    let (routing_table, ..) = compiler::run(&ctx)?;
+
+    println!(
+        "Checked {} routes in {:?}",
+        routing_table.len(),
+        Instant::now() - start
+    );
+
+    report.finish(args.deny_warnings)
 }
@@ -0,0 +1,375 @@
+//! Prometheus metrics, served in the text format on their own listener
+//! (`--metrics-port`) so they aren't public alongside the site
+
+use anyhow::Result;
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PATH: &str = "/metrics";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Route → status → count
+type Requests = FxHashMap<String, FxHashMap<u16, u64>>;
+/// Every thread's `Requests`, summed when rendered so a worker
+/// counting a request only ever locks its own
+static REQUESTS: Mutex<Vec<Arc<Mutex<Requests>>>> = Mutex::new(Vec::new());
+thread_local! {
+    static THREAD_REQUESTS: Arc<Mutex<Requests>> = {
+        let requests = Arc::<Mutex<Requests>>::default();
+        REQUESTS.lock().unwrap().push(requests.clone());
+        requests
+    };
+}
+
+/// Indexed like `ENCODINGS`
+static BYTES: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+const ENCODINGS: [&str; 4] = ["identity", "br", "zstd", "gzip"];
+static CONDITIONAL: AtomicU64 = AtomicU64::new(0);
+static NOT_MODIFIED: AtomicU64 = AtomicU64::new(0);
+static OPEN_CONNECTIONS: AtomicI64 = AtomicI64::new(0);
+static REQUEST_DURATION: Histogram<9> =
+    Histogram::new([0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.]);
+
+static BUILD_DURATION: Histogram<8> = Histogram::new([0.01, 0.05, 0.1, 0.5, 1., 5., 10., 60.]);
+static BUILDS: AtomicU64 = AtomicU64::new(0);
+static BUILDS_SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+
+static UPDATES: AtomicU64 = AtomicU64::new(0);
+static UPDATES_FAILED: AtomicU64 = AtomicU64::new(0);
+
+struct Histogram<const N: usize> {
+    /// Upper bounds (seconds)
+    bounds: [f64; N],
+    /// Not cumulative, summed when rendered
+    counts: [AtomicU64; N],
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(bounds: [f64; N]) -> Self {
+        Histogram {
+            bounds,
+            counts: [const { AtomicU64::new(0) }; N],
+            sum_nanos: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|b| secs <= *b) {
+            self.counts[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// Binds the metrics listener, enabling per-request metrics
+pub fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("Serving metrics @ {addr}{PATH}");
+    ENABLED.store(true, Ordering::Relaxed);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle(stream) {
+                        eprintln!("Error handling metrics stream: {e}");
+                    }
+                }
+                Err(e) => eprintln!("metrics accept error: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut buf = [0u8; 4096];
+    let mut filled = 0;
+    while !buf[..filled].windows(4).any(|w| w == b"\r\n\r\n") && filled < buf.len() {
+        match stream.read(&mut buf[filled..])? {
+            0 => return Ok(()),
+            n => filled += n,
+        }
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut req = httparse::Request::new(&mut headers);
+    let path = match req.parse(&buf[..filled]) {
+        Ok(httparse::Status::Complete(_)) => req.path.unwrap_or(""),
+        _ => return stream.write_all(crate::web::route::BAD_REQUEST),
+    };
+    if req.method != Some("GET") || path.split('?').next() != Some(PATH) {
+        return stream.write_all(crate::web::route::NOT_FOUND);
+    }
+
+    let body = render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// If per-request metrics are being recorded
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A response to a request for `route` (empty if it had none)
+pub fn request(
+    route: &str,
+    status: u16,
+    encoding: Option<&str>,
+    bytes: usize,
+    conditional: bool,
+    latency: Duration,
+) {
+    THREAD_REQUESTS.with(|requests| {
+        let mut requests = requests.lock().unwrap();
+        let statuses = match requests.get_mut(route) {
+            Some(statuses) => statuses,
+            None => requests.entry(route.to_string()).or_default(),
+        };
+        *statuses.entry(status).or_default() += 1;
+    });
+
+    let encoding = encoding.unwrap_or("identity");
+    if let Some(i) = ENCODINGS.iter().position(|e| *e == encoding) {
+        BYTES[i].fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    if conditional {
+        CONDITIONAL.fetch_add(1, Ordering::Relaxed);
+    }
+    if status == 304 {
+        NOT_MODIFIED.fetch_add(1, Ordering::Relaxed);
+    }
+    REQUEST_DURATION.observe(latency);
+}
+
+/// Counts an open connection until dropped
+pub struct Connection(());
+
+impl Connection {
+    pub fn open() -> Self {
+        OPEN_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        Connection(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn build(duration: Duration, succeeded: bool) {
+    BUILD_DURATION.observe(duration);
+    BUILDS.fetch_add(1, Ordering::Relaxed);
+    if succeeded {
+        BUILDS_SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn update_attempt() {
+    UPDATES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn update_failed() {
+    UPDATES_FAILED.fetch_add(1, Ordering::Relaxed);
+}
+
+fn render() -> String {
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "http_requests_total",
+        "Responses sent by route and status",
+        "counter",
+    );
+    let mut requests = BTreeMap::<(String, u16), u64>::new();
+    for thread in REQUESTS.lock().unwrap().iter() {
+        for (route, statuses) in thread.lock().unwrap().iter() {
+            for (status, count) in statuses {
+                *requests.entry((escape(route), *status)).or_default() += count;
+            }
+        }
+    }
+    for ((route, status), count) in requests {
+        let _ = writeln!(
+            out,
+            "http_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+        );
+    }
+
+    header(
+        &mut out,
+        "http_response_bytes_total",
+        "Response body bytes sent by content encoding",
+        "counter",
+    );
+    for (encoding, bytes) in ENCODINGS.iter().zip(&BYTES) {
+        let bytes = bytes.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "http_response_bytes_total{{encoding=\"{encoding}\"}} {bytes}"
+        );
+    }
+
+    counter(
+        &mut out,
+        "http_conditional_requests_total",
+        "Requests with If-None-Match",
+        &CONDITIONAL,
+    );
+    counter(
+        &mut out,
+        "http_not_modified_total",
+        "304 Not Modified responses",
+        &NOT_MODIFIED,
+    );
+
+    header(
+        &mut out,
+        "http_open_connections",
+        "Connections currently being handled",
+        "gauge",
+    );
+    let open = OPEN_CONNECTIONS.load(Ordering::Relaxed);
+    let _ = writeln!(out, "http_open_connections {open}");
+
+    REQUEST_DURATION.render(
+        &mut out,
+        "http_request_duration_seconds",
+        "Time to respond to a request",
+    );
+    BUILD_DURATION.render(
+        &mut out,
+        "build_duration_seconds",
+        "Time taken by builds and rebuilds",
+    );
+    counter(&mut out, "builds_total", "Builds and rebuilds", &BUILDS);
+    counter(
+        &mut out,
+        "builds_succeeded_total",
+        "Builds and rebuilds without errors",
+        &BUILDS_SUCCEEDED,
+    );
+    counter(
+        &mut out,
+        "update_attempts_total",
+        "Self-updates triggered by the GitHub webhook",
+        &UPDATES,
+    );
+    counter(
+        &mut out,
+        "update_failures_total",
+        "Self-updates which failed",
+        &UPDATES_FAILED,
+    );
+
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, ty: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {ty}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+}
+
+/// Label values escape `\`, `"` and newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new([0.1, 1.]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(5));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "test_seconds", "Test");
+        assert_eq!(
+            out,
+            "# HELP test_seconds Test\n\
+             # TYPE test_seconds histogram\n\
+             test_seconds_bucket{le=\"0.1\"} 1\n\
+             test_seconds_bucket{le=\"1\"} 2\n\
+             test_seconds_bucket{le=\"+Inf\"} 3\n\
+             test_seconds_sum 5.55\n\
+             test_seconds_count 3\n"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        request("/a\"b", 200, Some("br"), 100, false, Duration::ZERO);
+        request("/a\"b", 304, None, 0, true, Duration::ZERO);
+        let _connection = Connection::open();
+
+        let out = render();
+        assert!(out.contains("http_requests_total{route=\"/a\\\"b\",status=\"200\"} 1\n"));
+        assert!(out.contains("http_requests_total{route=\"/a\\\"b\",status=\"304\"} 1\n"));
+        assert!(out.contains("# TYPE http_response_bytes_total counter\n"));
+        assert!(out.contains("http_open_connections "));
+        assert!(out.contains("http_request_duration_seconds_count "));
+    }
+
+    #[test]
+    fn requests_summed_across_threads() {
+        let count = || {
+            request("/summed", 200, None, 0, false, Duration::ZERO);
+        };
+        count();
+        thread::spawn(count).join().unwrap();
+
+        assert!(render().contains("http_requests_total{route=\"/summed\",status=\"200\"} 2\n"));
+    }
+}
@@ -0,0 +1,114 @@
+//! Collects every error and warning from a build so a single
+//! broken file doesn't hide the rest
+
+use anyhow::{Error, Result, bail};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Debug, Default)]
+pub struct Report {
+    failures: Mutex<Vec<Failure>>,
+    warnings: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    /// Relative to the content root
+    pub path: PathBuf,
+    /// The route this file would have been served at
+    /// None for hidden files
+    pub url: Option<String>,
+    pub error: Error,
+}
+
+impl Report {
+    /// Record a file which failed to index or compile
+    pub fn error(&self, path: &Path, url: Option<&str>, error: Error) {
+        self.failures.lock().unwrap().push(Failure {
+            path: path.to_path_buf(),
+            url: url.map(str::to_string),
+            error,
+        });
+    }
+
+    /// Record a warning (already printed by whoever emitted it)
+    pub fn warning(&self) {
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failures(&self) -> MutexGuard<'_, Vec<Failure>> {
+        self.failures.lock().unwrap()
+    }
+
+    pub fn num_warnings(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+
+    /// Prints every failure and a summary (if anything went wrong)
+    pub fn print(&self) {
+        let failures = self.failures();
+        let warnings = self.num_warnings();
+
+        let mut errors = failures
+            .iter()
+            .map(|f| format!("{:#}", f.error))
+            .collect::<Vec<_>>();
+        errors.sort();
+
+        for error in &errors {
+            eprintln!("error: {error}");
+        }
+
+        if !errors.is_empty() || warnings > 0 {
+            println!("{} error(s), {warnings} warning(s)", errors.len());
+        }
+    }
+
+    /// Prints the report, failing if there were any
+    /// errors (or warnings if `deny_warnings`)
+    pub fn finish(self, deny_warnings: bool) -> Result<()> {
+        self.print();
+
+        let errors = self.failures().len();
+        let warnings = self.num_warnings();
+        if errors > 0 || (deny_warnings && warnings > 0) {
+            bail!("build failed with {errors} error(s), {warnings} warning(s)");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn empty_is_ok() {
+        assert!(Report::default().finish(true).is_ok());
+    }
+
+    #[test]
+    fn errors_fail() {
+        let report = Report::default();
+        report.error(Path::new("one.typ"), Some("/one"), anyhow!("one"));
+        report.error(Path::new("_two.typ"), None, anyhow!("two"));
+        assert_eq!(report.failures().len(), 2);
+        assert_eq!(report.failures()[0].url.as_deref(), Some("/one"));
+        assert!(report.finish(false).is_err());
+    }
+
+    #[test]
+    fn warnings_only_fail_when_denied() {
+        let report = Report::default();
+        report.warning();
+        assert!(report.finish(false).is_ok());
+
+        let report = Report::default();
+        report.warning();
+        assert_eq!(report.num_warnings(), 1);
+        assert!(report.finish(true).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::UpdateArgs;
+use crate::{UpdateArgs, metrics};
 use anyhow::{Context, Result, bail};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
@@ -32,7 +32,7 @@ pub fn set_cfg(cfg: UpdateArgs) -> Result<()> {
     Ok(())
 }
 
-pub fn verify(sig: &[u8], body: Vec<u8>) -> bool {
+pub fn verify(sig: &[u8], body: &[u8]) -> bool {
     let sig = String::from_utf8_lossy(sig);
 
     let Some(secret) = SECRET.get() else {
@@ -51,11 +51,22 @@ pub fn verify(sig: &[u8], body: Vec<u8>) -> bool {
         return false;
     };
 
-    mac.update(&body);
+    mac.update(body);
     mac.verify_slice(&expected_sig).is_ok()
 }
 
 pub fn run() -> Result<()> {
+    metrics::update_attempt();
+    if let Err(e) = pull_and_build() {
+        metrics::update_failed();
+        return Err(e);
+    }
+
+    println!("Update complete. Exiting for restart...");
+    std::process::exit(0);
+}
+
+fn pull_and_build() -> Result<()> {
     println!("Starting self-update...");
 
     println!("  Pulling changes...");
@@ -74,6 +85,5 @@ pub fn run() -> Result<()> {
         bail!("cargo build failed with {status}");
     }
 
-    println!("Update complete. Exiting for restart...");
-    std::process::exit(0);
+    Ok(())
 }
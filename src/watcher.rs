@@ -1,6 +1,9 @@
-use crate::{WatchArgs, build};
+use crate::compiler::diagnostic::Diagnostics;
+use crate::report::Report;
+use crate::{BuildArgs, Site, WatchArgs, rebuild};
 use anyhow::Result;
 use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind};
+use serde_json::{Value, json};
 use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, mpsc};
@@ -9,31 +12,39 @@ use std::{mem, thread};
 use tungstenite::{Message, WebSocket, accept};
 
 type ClientList = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+/// Overlay message for the latest build, sent to clients as they connect
+type LastErrors = Arc<Mutex<Option<String>>>;
 
 const DEBOUNCE_MS: u64 = 1000;
 
-pub fn run(root: PathBuf, args: WatchArgs) -> Result<()> {
+pub fn run(cfg: BuildArgs, args: WatchArgs, site: Site, report: &Report) -> Result<()> {
     let addr = SocketAddr::new(args.watch_address, args.watch_port);
     let listener = TcpListener::bind(addr)?;
     let pending: ClientList = Arc::new(Mutex::new(Vec::new()));
+    let errors: LastErrors = Arc::new(Mutex::new(overlay_message(report)));
     let (tx, rx) = mpsc::channel();
 
-    println!("Watching {} for changes...", root.display());
+    println!("Watching {} for changes...", cfg.root.display());
 
-    spawn_accept_loop(listener, Arc::clone(&pending));
-    create_watcher(&root, tx)?;
-    spawn_rebuild_loop(rx, pending, root, args);
+    spawn_accept_loop(listener, Arc::clone(&pending), Arc::clone(&errors));
+    create_watcher(&cfg.root, RecursiveMode::Recursive, tx)?;
+    spawn_rebuild_loop(rx, pending, errors, cfg, site, args);
 
     Ok(())
 }
 
 /// Accepts new websocket connections
-fn spawn_accept_loop(listener: TcpListener, pending: ClientList) {
+fn spawn_accept_loop(listener: TcpListener, pending: ClientList, errors: LastErrors) {
     thread::spawn(move || {
         while let Ok((stream, addr)) = listener.accept() {
             match accept(stream) {
-                Ok(ws) => {
+                Ok(mut ws) => {
                     println!("WebSocket connected: {addr}");
+                    if let Some(msg) = errors.lock().unwrap().clone()
+                        && ws.send(Message::Text(msg.into())).is_err()
+                    {
+                        continue;
+                    }
                     pending.lock().unwrap().push(ws);
                 }
                 Err(e) => eprintln!("Handshake failed for {addr}: {e}"),
@@ -42,53 +53,108 @@ fn spawn_accept_loop(listener: TcpListener, pending: ClientList) {
     });
 }
 
-/// Recursively watches fs `root` directory
-fn create_watcher(root: &Path, tx: mpsc::Sender<()>) -> Result<()> {
+/// Watches fs `root` directory, sending changed paths relative to `root`
+pub fn create_watcher(
+    root: &Path,
+    mode: RecursiveMode,
+    tx: mpsc::Sender<Vec<PathBuf>>,
+) -> Result<()> {
+    let root = root.canonicalize()?;
+    let watch_root = root.clone();
     let mut watcher = RecommendedWatcher::new(
         move |res: std::result::Result<notify::Event, notify::Error>| {
             if let Ok(notify::Event {
                 kind:
-                    EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Data(_)),
+                    EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_)),
+                paths,
                 ..
             }) = res
             {
-                _ = tx.send(());
+                let paths = paths
+                    .iter()
+                    .filter_map(|path| path.strip_prefix(&root).ok())
+                    .map(Path::to_path_buf)
+                    .collect();
+                _ = tx.send(paths);
             }
         },
         notify::Config::default(),
     )?;
-    watcher.watch(root, RecursiveMode::Recursive)?;
+    watcher.watch(&watch_root, mode)?;
     mem::forget(watcher);
     Ok(())
 }
 
-/// Rebuilds on fs change & notifies websockets
+/// Incrementally rebuilds on fs change & notifies websockets
+/// (empty binary message to reload, JSON text of the failures otherwise)
 fn spawn_rebuild_loop(
-    rx: mpsc::Receiver<()>,
+    rx: mpsc::Receiver<Vec<PathBuf>>,
     pending: ClientList,
-    root: PathBuf,
+    errors: LastErrors,
+    cfg: BuildArgs,
+    mut site: Site,
     watch_args: WatchArgs,
 ) {
     thread::spawn(move || {
         let mut clients: Vec<WebSocket<TcpStream>> = Vec::new();
-        while rx.recv().is_ok() {
-            while rx.try_recv().is_ok() {}
+        while let Ok(mut paths) = rx.recv() {
+            while let Ok(more) = rx.try_recv() {
+                paths.extend(more);
+            }
 
             println!("Change detected, rebuilding...");
 
             clients.append(&mut pending.lock().unwrap());
 
-            match build(&root, &watch_args) {
+            match rebuild(&cfg, &watch_args, &mut site, paths) {
                 Err(e) => eprintln!("Rebuild failed: {e}"),
-                Ok(()) => {
-                    clients.retain_mut(|ws| ws.send(Message::Binary(vec![].into())).is_ok());
+                Ok(report) => {
+                    let overlay = overlay_message(&report);
+                    let msg = match &overlay {
+                        Some(json) => Message::Text(json.clone().into()),
+                        None => Message::Binary(vec![].into()),
+                    };
+                    *errors.lock().unwrap() = overlay;
+
+                    clients.retain_mut(|ws| ws.send(msg.clone()).is_ok());
                     println!("Notified {} client(s)", clients.len());
                 }
             }
 
+            // changes made in the meantime are picked up next loop
             thread::sleep(Duration::from_millis(DEBOUNCE_MS));
-            while rx.try_recv().is_ok() {}
         }
         println!("Watcher rebuild loop stopped.");
     });
 }
+
+/// JSON describing every failure for the browser overlay
+/// (see `live_reload.js`), None if the build succeeded
+fn overlay_message(report: &Report) -> Option<String> {
+    let failures = report.failures();
+    if failures.is_empty() {
+        return None;
+    }
+
+    let failures = failures
+        .iter()
+        .map(|failure| {
+            let diagnostics = failure
+                .error
+                .chain()
+                .filter_map(|e| e.downcast_ref::<Diagnostics>())
+                .flat_map(|d| &d.diagnostics)
+                .map(|d| d.to_json())
+                .collect::<Vec<_>>();
+
+            json!({
+                "error": format!("{:#}", failure.error),
+                "diagnostics": diagnostics,
+            })
+        })
+        .collect();
+
+    Some(Value::Array(failures).to_string())
+}
@@ -0,0 +1,492 @@
+//! A connection's state between readiness events: requests received so
+//! far and responses the socket hasn't taken yet
+
+use crate::metrics;
+use crate::web::h2::{self, Session};
+use crate::web::{Handled, KEEP_ALIVE_TIMEOUT, Service, TIMEOUT, handle};
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const READ_SIZE: usize = 4096;
+
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Conn<S> {
+    stream: S,
+    peer: SocketAddr,
+    service: Service,
+    /// Received but not yet responded to
+    buf: Vec<u8>,
+    /// Responses the socket wouldn't take yet, the first sent up to `sent`
+    pending: VecDeque<Queued>,
+    sent: usize,
+    /// The socket wouldn't take everything, including anything
+    /// buffered by the stream (TLS)
+    backed_up: bool,
+    /// Close once `pending` is sent
+    closing: bool,
+    /// When the first byte of the request in `buf` arrived
+    request_start: Option<Instant>,
+    /// Once the client starts with the HTTP/2 preface
+    h2: Option<Box<Session>>,
+    /// Closed by the event loop once passed
+    pub deadline: Instant,
+    _metrics: metrics::Connection,
+}
+
+/// Connections open across all threads
+pub fn count() -> usize {
+    OPEN.load(Ordering::Relaxed)
+}
+
+impl<S> Conn<S> {
+    pub fn new(stream: S, peer: SocketAddr, service: Service) -> Self {
+        OPEN.fetch_add(1, Ordering::Relaxed);
+        Conn {
+            stream,
+            peer,
+            service,
+            buf: Vec::new(),
+            pending: VecDeque::new(),
+            sent: 0,
+            backed_up: false,
+            closing: false,
+            request_start: None,
+            h2: None,
+            deadline: Instant::now() + KEEP_ALIVE_TIMEOUT,
+            _metrics: metrics::Connection::open(),
+        }
+    }
+}
+
+impl<S: Read + Write> Conn<S> {
+    /// Handles a readiness event, responding to whatever can be read
+    /// without blocking. False once the connection should be closed
+    pub fn ready(&mut self) -> io::Result<bool> {
+        loop {
+            self.flush()?;
+            if self.backed_up {
+                // wait until it's writable
+                break;
+            }
+            if self.closing {
+                return Ok(false);
+            }
+
+            if !self.respond()? {
+                self.closing = true;
+                continue;
+            }
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            let len = self.buf.len();
+            self.buf.resize(len + READ_SIZE, 0);
+            let read = self.stream.read(&mut self.buf[len..]);
+            self.buf.truncate(len + read.as_ref().map_or(0, |n| *n));
+            match read {
+                Ok(0) => return Ok(false),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return Ok(false),
+            }
+        }
+
+        self.settle();
+        Ok(true)
+    }
+
+    /// Responds to the complete requests in `buf`, stopping once the
+    /// socket backs up. False if the connection should be closed
+    fn respond(&mut self) -> io::Result<bool> {
+        if self.h2.is_none() && matches!(self.service, Service::Site) {
+            match h2::preface(&self.buf) {
+                Some(true) => self.h2 = Some(Box::default()),
+                Some(false) => {}
+                // could be either
+                None => return Ok(true),
+            }
+        }
+
+        let mut out = Out::new(&mut self.stream, &mut self.pending);
+        let mut consumed = 0;
+        let mut open = true;
+
+        if let Some(session) = &mut self.h2 {
+            (consumed, open) = session.serve(&mut out, &self.buf, self.peer)?;
+        }
+        while self.h2.is_none() && open && !out.backed_up() {
+            match handle(&mut out, &self.buf[consumed..], self.peer, self.service)? {
+                Handled::Done { len, close } => {
+                    consumed += len;
+                    open = !close;
+                }
+                Handled::Incomplete => break,
+            }
+        }
+
+        if consumed > 0 {
+            self.buf.drain(..consumed);
+            self.request_start = None;
+        }
+        Ok(open)
+    }
+
+    /// Writes as much of `pending` as the socket takes without blocking
+    fn flush(&mut self) -> io::Result<()> {
+        self.backed_up = true;
+        while let Some(queued) = self.pending.front() {
+            let bytes = queued.bytes();
+            if self.sent == bytes.len() {
+                self.pending.pop_front();
+                self.sent = 0;
+                continue;
+            }
+            match self.stream.write(&bytes[self.sent..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.pending = VecDeque::new();
+        match self.stream.flush() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+            Ok(()) => {
+                self.backed_up = false;
+                Ok(())
+            }
+        }
+    }
+
+    /// Frees the read buffer if idle and sets the deadline: a backed up
+    /// response has to make progress and a started request has to finish
+    /// within `TIMEOUT`, otherwise it may idle for `KEEP_ALIVE_TIMEOUT`
+    fn settle(&mut self) {
+        let now = Instant::now();
+        if self.buf.is_empty() {
+            self.buf = Vec::new();
+            self.request_start = None;
+        }
+
+        self.deadline = if self.backed_up {
+            now + TIMEOUT
+        } else if !self.buf.is_empty() {
+            *self.request_start.get_or_insert(now) + TIMEOUT
+        } else {
+            now + KEEP_ALIVE_TIMEOUT
+        };
+    }
+}
+
+impl<S> Drop for Conn<S> {
+    fn drop(&mut self) {
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Output the socket wouldn't take yet
+pub enum Queued {
+    /// Copied, for anything put together per request
+    Owned(Vec<u8>),
+    /// Part of a pre-serialized response, shared instead of copied
+    Shared(Arc<[u8]>, Range<usize>),
+}
+
+impl Queued {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Queued::Owned(bytes) => bytes,
+            Queued::Shared(data, range) => &data[range.clone()],
+        }
+    }
+}
+
+/// `range` of a pre-serialized response
+pub type Shared<'a> = (&'a Arc<[u8]>, Range<usize>);
+
+/// A `Write` which can take parts of shared responses without copying them
+pub trait WriteShared: Write {
+    /// Writes all of `parts` in order
+    fn write_shared(&mut self, parts: &[Shared]) -> io::Result<()> {
+        for (data, range) in parts {
+            self.write_all(&data[range.clone()])?;
+        }
+        Ok(())
+    }
+}
+
+impl WriteShared for Vec<u8> {}
+
+impl<S: WriteShared> WriteShared for &mut S {
+    fn write_shared(&mut self, parts: &[Shared]) -> io::Result<()> {
+        (**self).write_shared(parts)
+    }
+}
+
+/// Writes straight to the socket, queueing what it won't take yet
+pub struct Out<'a, S> {
+    stream: &'a mut S,
+    pending: &'a mut VecDeque<Queued>,
+}
+
+impl<'a, S> Out<'a, S> {
+    pub fn new(stream: &'a mut S, pending: &'a mut VecDeque<Queued>) -> Self {
+        Out { stream, pending }
+    }
+
+    /// Something is queued, anything more would be too
+    pub fn backed_up(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn queue(&mut self, buf: &[u8]) {
+        match self.pending.back_mut() {
+            Some(Queued::Owned(bytes)) => bytes.extend_from_slice(buf),
+            _ => self.pending.push_back(Queued::Owned(buf.to_vec())),
+        }
+    }
+}
+
+impl<S: Write> Write for Out<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.stream.write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+        }
+
+        self.queue(buf);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.stream.write_vectored(bufs) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+        }
+
+        for buf in bufs {
+            self.queue(buf);
+        }
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    /// Queued output is sent by the event loop
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Write> WriteShared for Out<'_, S> {
+    /// Writes `parts` together, queueing what the socket won't take
+    /// as `Queued::Shared`
+    fn write_shared(&mut self, parts: &[Shared]) -> io::Result<()> {
+        // the first part not fully written and how much of it was
+        let (mut next, mut sent) = (0, 0);
+        while self.pending.is_empty() && next < parts.len() {
+            let slices = parts[next..]
+                .iter()
+                .enumerate()
+                .map(|(i, (data, range))| {
+                    let skip = if i == 0 { sent } else { 0 };
+                    IoSlice::new(&data[range.start + skip..range.end])
+                })
+                .collect::<Vec<_>>();
+            if slices.iter().all(|slice| slice.is_empty()) {
+                break;
+            }
+
+            match self.stream.write_vectored(&slices) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(mut n) => {
+                    while n > 0 {
+                        let left = parts[next].1.len() - sent;
+                        if n < left {
+                            sent += n;
+                            break;
+                        }
+                        n -= left;
+                        (next, sent) = (next + 1, 0);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        for (i, (data, range)) in parts.iter().enumerate().skip(next) {
+            let skip = if i == next { sent } else { 0 };
+            if range.start + skip < range.end {
+                let queued = Queued::Shared((*data).clone(), range.start + skip..range.end);
+                self.pending.push_back(queued);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::route::NOT_FOUND;
+    use std::io::Cursor;
+
+    /// Reads would block once `input` runs out, and writes once
+    /// `output` holds `capacity` bytes
+    struct Socket {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Read for Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.capacity - self.output.len());
+            if n == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.output.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn conn(input: &[u8], capacity: usize) -> Conn<Socket> {
+        let socket = Socket {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+            capacity,
+        };
+        Conn::new(socket, "127.0.0.1:1234".parse().unwrap(), Service::Site)
+    }
+
+    fn feed(conn: &mut Conn<Socket>, input: &[u8]) {
+        conn.stream.input.get_mut().extend_from_slice(input);
+    }
+
+    #[test]
+    fn test_partial_request() {
+        let mut conn = conn(b"GET /nope HT", usize::MAX);
+        assert!(conn.ready().unwrap());
+        assert!(conn.stream.output.is_empty());
+        let deadline = conn.deadline;
+        assert!(deadline <= Instant::now() + TIMEOUT);
+
+        // trickling in doesn't extend it
+        feed(&mut conn, b"TP/1.1\r\n");
+        assert!(conn.ready().unwrap());
+        assert_eq!(conn.deadline, deadline);
+
+        feed(&mut conn, b"\r\n");
+        assert!(conn.ready().unwrap());
+        assert_eq!(conn.stream.output, NOT_FOUND);
+        assert_eq!(conn.buf.capacity(), 0);
+        assert!(conn.deadline > Instant::now() + TIMEOUT);
+    }
+
+    fn queued(conn: &Conn<Socket>) -> Vec<u8> {
+        let queued = conn.pending.iter().flat_map(|q| q.bytes());
+        queued.skip(conn.sent).copied().collect()
+    }
+
+    #[test]
+    fn test_backed_up() {
+        let request = b"GET /nope HTTP/1.1\r\n\r\n";
+        let mut conn = conn(&[&request[..], request].concat(), 10);
+        assert!(conn.ready().unwrap());
+        assert_eq!(conn.stream.output, &NOT_FOUND[..10]);
+        assert_eq!(queued(&conn), &NOT_FOUND[10..]);
+        // waits until the first response is sent
+        assert_eq!(conn.buf, request);
+
+        conn.stream.capacity = usize::MAX;
+        assert!(conn.ready().unwrap());
+        assert_eq!(conn.stream.output, [NOT_FOUND, NOT_FOUND].concat());
+        assert!(conn.pending.is_empty());
+        assert!(conn.buf.is_empty());
+    }
+
+    #[test]
+    fn test_shared_not_copied() {
+        let mut conn = conn(b"", 10);
+        let data: Arc<[u8]> = (0..100).collect::<Vec<u8>>().into();
+
+        let mut out = Out::new(&mut conn.stream, &mut conn.pending);
+        out.write_all(b"head").unwrap();
+        out.write_shared(&[(&data, 2..50), (&data, 60..100)])
+            .unwrap();
+        out.write_all(b"tail").unwrap();
+
+        assert_eq!(conn.stream.output, [&b"head"[..], &data[2..8]].concat());
+        let [
+            Queued::Shared(a, first),
+            Queued::Shared(b, second),
+            Queued::Owned(tail),
+        ] = conn.pending.make_contiguous()
+        else {
+            panic!("expected two shared and one owned");
+        };
+        assert!(Arc::ptr_eq(a, &data) && Arc::ptr_eq(b, &data));
+        assert_eq!((first.clone(), second.clone()), (8..50, 60..100));
+        assert_eq!(tail, b"tail");
+
+        conn.stream.capacity = usize::MAX;
+        conn.flush().unwrap();
+        let expected = [&b"head"[..], &data[2..50], &data[60..], b"tail"].concat();
+        assert_eq!(conn.stream.output, expected);
+        assert!(conn.pending.is_empty());
+    }
+
+    #[test]
+    fn test_h2_prior_knowledge() {
+        let mut conn = conn(b"PRI * HTTP/2.0\r\n", usize::MAX);
+        assert!(conn.ready().unwrap());
+        assert!(conn.stream.output.is_empty());
+
+        // rest of the preface then an empty SETTINGS
+        feed(&mut conn, b"\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0");
+        assert!(conn.ready().unwrap());
+        assert!(conn.h2.is_some());
+        // our SETTINGS then the ACK
+        assert!(conn.stream.output.starts_with(b"\0\0\x06\x04\0\0\0\0\0"));
+        assert!(conn.stream.output.ends_with(b"\0\0\0\x04\x01\0\0\0\0"));
+        assert!(conn.buf.is_empty());
+    }
+
+    #[test]
+    fn test_close_after_sent() {
+        let mut conn = conn(b"GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n", 10);
+        assert!(conn.ready().unwrap());
+
+        conn.stream.capacity = usize::MAX;
+        assert!(!conn.ready().unwrap());
+        assert_eq!(conn.stream.output, NOT_FOUND);
+    }
+}
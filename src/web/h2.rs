@@ -0,0 +1,823 @@
+//! HTTP/2 (RFC 9113), negotiated with ALPN over TLS or started with prior
+//! knowledge in cleartext. Responses are the same pre-serialized ones as
+//! HTTP/1.1: their precomputed HPACK block, then the body straight from
+//! the HTTP/1.1 bytes. Request bodies aren't read, the webhook is HTTP/1.1 only
+
+use crate::ROUTING_TABLE;
+use crate::metrics;
+use crate::web::conn::Out;
+use crate::web::hpack::{self, Decoder};
+use crate::web::log;
+use crate::web::route::{BAD_REQUEST, METHOD_NOT_ALLOWED};
+use crate::web::{MAX_HEADER_SIZE, Reply, record, reply};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_SIZE: usize = 9;
+/// Our SETTINGS_MAX_FRAME_SIZE, the default
+const MAX_FRAME_SIZE: usize = 16_384;
+const MAX_CONCURRENT_STREAMS: usize = 128;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+/// If `buf` starts with the client preface, None while it's too short to tell
+pub fn preface(buf: &[u8]) -> Option<bool> {
+    let len = buf.len().min(PREFACE.len());
+    if buf[..len] != PREFACE[..len] {
+        Some(false)
+    } else if len < PREFACE.len() {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+enum Error {
+    /// Sent in a GOAWAY before closing
+    Connection(u32),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// A connection's state once it's speaking HTTP/2
+pub struct Session {
+    /// Past the preface
+    started: bool,
+    decoder: Decoder,
+    /// The client's SETTINGS_INITIAL_WINDOW_SIZE
+    initial_window: i64,
+    /// The client's SETTINGS_MAX_FRAME_SIZE
+    max_frame_size: usize,
+    /// How much more DATA the client takes across all streams
+    window: i64,
+    /// Highest stream the client has opened
+    last_stream: u32,
+    /// A header block waiting on CONTINUATION frames
+    continuation: Option<Block>,
+    /// Responses with body left to send, in the order they were requested
+    streams: VecDeque<Stream>,
+    /// The client sent GOAWAY, close once everything is sent
+    closing: bool,
+}
+
+struct Block {
+    stream: u32,
+    end_stream: bool,
+    fragments: Vec<u8>,
+}
+
+struct Stream {
+    id: u32,
+    window: i64,
+    /// The body, sent from `pos`
+    data: Arc<[u8]>,
+    pos: usize,
+    /// The client is still sending its request, reset once responded to
+    open: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            started: false,
+            decoder: Decoder::new(),
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: MAX_FRAME_SIZE,
+            window: DEFAULT_WINDOW,
+            last_stream: 0,
+            continuation: None,
+            streams: VecDeque::new(),
+            closing: false,
+        }
+    }
+}
+
+impl Session {
+    /// Handles the complete frames in `buf` then sends as much body as flow
+    /// control and the socket allow. Returns how much of `buf` was consumed
+    /// and false if the connection should be closed
+    pub fn serve<S: Write>(
+        &mut self,
+        out: &mut Out<S>,
+        buf: &[u8],
+        peer: SocketAddr,
+    ) -> io::Result<(usize, bool)> {
+        let mut pos = 0;
+        if !self.started {
+            self.started = true;
+            pos = PREFACE.len();
+            let mut settings = SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes().to_vec();
+            settings.extend_from_slice(&(MAX_CONCURRENT_STREAMS as u32).to_be_bytes());
+            write_frame(out, SETTINGS, 0, 0, &settings)?;
+        }
+
+        let result = loop {
+            let Some(header) = buf.get(pos..pos + FRAME_HEADER_SIZE) else {
+                break Ok(());
+            };
+            let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            if len > MAX_FRAME_SIZE {
+                break Err(Error::Connection(FRAME_SIZE_ERROR));
+            }
+            let Some(payload) = buf.get(pos + FRAME_HEADER_SIZE..pos + FRAME_HEADER_SIZE + len)
+            else {
+                break Ok(());
+            };
+            pos += FRAME_HEADER_SIZE + len;
+
+            // without the reserved bit
+            let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+            if let Err(e) = self.frame(out, header[3], header[4], id, payload, peer) {
+                break Err(e);
+            }
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(Error::Io(e)) => return Err(e),
+            Err(Error::Connection(code)) => {
+                let mut payload = self.last_stream.to_be_bytes().to_vec();
+                payload.extend_from_slice(&code.to_be_bytes());
+                write_frame(out, GOAWAY, 0, 0, &payload)?;
+                return Ok((buf.len(), false));
+            }
+        }
+
+        self.send(out)?;
+        Ok((pos, !(self.closing && self.streams.is_empty())))
+    }
+
+    fn frame<S: Write>(
+        &mut self,
+        out: &mut Out<S>,
+        kind: u8,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+        peer: SocketAddr,
+    ) -> Result<(), Error> {
+        if let Some(block) = &self.continuation
+            && (kind != CONTINUATION || id != block.stream)
+        {
+            return Err(Error::Connection(PROTOCOL_ERROR));
+        }
+
+        match kind {
+            DATA => {
+                if id == 0 {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                // bodies are ignored, but count against the client's windows
+                // so are handed straight back, otherwise an upload would stall
+                let end = flags & END_STREAM != 0;
+                let stream = self.streams.iter_mut().find(|s| s.id == id);
+                if !payload.is_empty() {
+                    let increment = (payload.len() as u32).to_be_bytes();
+                    write_frame(out, WINDOW_UPDATE, 0, 0, &increment)?;
+                    if !end && stream.as_ref().is_some_and(|s| s.open) {
+                        write_frame(out, WINDOW_UPDATE, 0, id, &increment)?;
+                    }
+                }
+                if end && let Some(stream) = stream {
+                    stream.open = false;
+                }
+            }
+            HEADERS => {
+                if id == 0 || id.is_multiple_of(2) {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                let fragment = unpad(flags, payload)?;
+                let fragment = match flags & PRIORITY {
+                    0 => fragment,
+                    _ => fragment.get(5..).ok_or(Error::Connection(PROTOCOL_ERROR))?,
+                };
+                let block = Block {
+                    stream: id,
+                    end_stream: flags & END_STREAM != 0,
+                    fragments: fragment.to_vec(),
+                };
+                if flags & END_HEADERS != 0 {
+                    self.request(out, block, peer)?;
+                } else {
+                    self.continuation = Some(block);
+                }
+            }
+            CONTINUATION => {
+                let Some(block) = &mut self.continuation else {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                };
+                block.fragments.extend_from_slice(payload);
+                if block.fragments.len() > MAX_HEADER_SIZE {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                if flags & END_HEADERS != 0 {
+                    let block = self.continuation.take().unwrap();
+                    self.request(out, block, peer)?;
+                }
+            }
+            RST_STREAM => {
+                if payload.len() != 4 {
+                    return Err(Error::Connection(FRAME_SIZE_ERROR));
+                }
+                self.streams.retain(|s| s.id != id);
+            }
+            SETTINGS => {
+                if id != 0 {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                if flags & ACK != 0 {
+                    return match payload.len() {
+                        0 => Ok(()),
+                        _ => Err(Error::Connection(FRAME_SIZE_ERROR)),
+                    };
+                }
+                if !payload.len().is_multiple_of(6) {
+                    return Err(Error::Connection(FRAME_SIZE_ERROR));
+                }
+                for param in payload.chunks(6) {
+                    let value = u32::from_be_bytes([param[2], param[3], param[4], param[5]]);
+                    self.setting(u16::from_be_bytes([param[0], param[1]]), value)?;
+                }
+                write_frame(out, SETTINGS, ACK, 0, &[])?;
+            }
+            PING => {
+                if payload.len() != 8 {
+                    return Err(Error::Connection(FRAME_SIZE_ERROR));
+                }
+                if flags & ACK == 0 {
+                    write_frame(out, PING, ACK, 0, payload)?;
+                }
+            }
+            GOAWAY => self.closing = true,
+            WINDOW_UPDATE => {
+                let increment = match payload {
+                    [a, b, c, d] => u32::from_be_bytes([*a, *b, *c, *d]) & MAX_WINDOW as u32,
+                    _ => return Err(Error::Connection(FRAME_SIZE_ERROR)),
+                };
+                if increment == 0 {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                let window = match id {
+                    0 => &mut self.window,
+                    id => match self.streams.iter_mut().find(|s| s.id == id) {
+                        Some(stream) => &mut stream.window,
+                        // already sent
+                        None => return Ok(()),
+                    },
+                };
+                *window += increment as i64;
+                if *window > MAX_WINDOW {
+                    return Err(Error::Connection(FLOW_CONTROL_ERROR));
+                }
+            }
+            PUSH_PROMISE => return Err(Error::Connection(PROTOCOL_ERROR)),
+            // PRIORITY and unknown frames
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn setting(&mut self, id: u16, value: u32) -> Result<(), Error> {
+        match id {
+            SETTINGS_INITIAL_WINDOW_SIZE => {
+                let value = value as i64;
+                if value > MAX_WINDOW {
+                    return Err(Error::Connection(FLOW_CONTROL_ERROR));
+                }
+                // applies to streams already open too
+                let delta = value - self.initial_window;
+                self.initial_window = value;
+                for stream in &mut self.streams {
+                    stream.window = stream
+                        .window
+                        .checked_add(delta)
+                        .filter(|window| *window <= MAX_WINDOW)
+                        .ok_or(Error::Connection(FLOW_CONTROL_ERROR))?;
+                }
+            }
+            SETTINGS_MAX_FRAME_SIZE => {
+                let value = value as usize;
+                if !(MAX_FRAME_SIZE..=0xff_ffff).contains(&value) {
+                    return Err(Error::Connection(PROTOCOL_ERROR));
+                }
+                self.max_frame_size = value;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Responds to a complete header block
+    fn request<S: Write>(
+        &mut self,
+        out: &mut Out<S>,
+        block: Block,
+        peer: SocketAddr,
+    ) -> Result<(), Error> {
+        // decoded regardless, the dynamic table has to stay in sync
+        let fields = self
+            .decoder
+            .decode(&block.fragments)
+            .map_err(|_| Error::Connection(COMPRESSION_ERROR))?;
+
+        let id = block.stream;
+        if id <= self.last_stream {
+            // trailers
+            return Ok(());
+        }
+        self.last_stream = id;
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            let code = REFUSED_STREAM.to_be_bytes();
+            return Ok(write_frame(out, RST_STREAM, 0, id, &code)?);
+        }
+
+        let start = (log::enabled() || metrics::enabled()).then(Instant::now);
+        let mut method = None;
+        let mut target = None;
+        let mut headers = Vec::with_capacity(fields.len());
+        for (name, value) in &fields {
+            match name.as_slice() {
+                b":method" => method = str::from_utf8(value).ok(),
+                b":path" => target = str::from_utf8(value).ok(),
+                b":authority" => headers.push(httparse::Header {
+                    name: "host",
+                    value,
+                }),
+                name if name.starts_with(b":") => {}
+                name => {
+                    if let Ok(name) = str::from_utf8(name) {
+                        headers.push(httparse::Header { name, value });
+                    }
+                }
+            }
+        }
+
+        let table = ROUTING_TABLE.load();
+        let reply = match (method, target) {
+            (Some("GET" | "HEAD"), Some(target)) => reply(&table, target, &headers),
+            (Some(_), Some(_)) => Reply::fixed(METHOD_NOT_ALLOWED),
+            _ => Reply::fixed(BAD_REQUEST),
+        };
+        let head = method == Some("HEAD");
+        if let Some(start) = start {
+            let (method, target) = (method.unwrap_or(""), target.unwrap_or(""));
+            let sent = reply.sent(head);
+            record(&sent, peer, method, target, "HTTP/2.0", &headers, start);
+        }
+
+        // the body is sent from `pos`
+        let (fragment, data, pos) = match reply {
+            Reply::Response(response) => {
+                (Cow::Borrowed(&response.h2[..]), response.body.clone(), 0)
+            }
+            Reply::Chunks(chunks) => {
+                let data: Arc<[u8]> = chunks.iter().flat_map(|c| c.iter().copied()).collect();
+                let Some((fragment, body)) = hpack::encode_head(&data) else {
+                    let code = INTERNAL_ERROR.to_be_bytes();
+                    return Ok(write_frame(out, RST_STREAM, 0, id, &code)?);
+                };
+                (Cow::Owned(fragment), data, body)
+            }
+        };
+
+        let end = head || pos == data.len();
+        self.write_headers(out, id, &fragment, end)?;
+
+        let open = !block.end_stream;
+        if !end {
+            self.streams.push_back(Stream {
+                id,
+                window: self.initial_window,
+                data,
+                pos,
+                open,
+            });
+        } else if open {
+            write_frame(out, RST_STREAM, 0, id, &NO_ERROR.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// A header block, split into CONTINUATION frames if it doesn't fit one
+    fn write_headers<S: Write>(
+        &self,
+        out: &mut Out<S>,
+        id: u32,
+        fragment: &[u8],
+        end_stream: bool,
+    ) -> io::Result<()> {
+        let mut chunks = fragment.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            write_frame(out, kind, flags, id, chunk)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+        Ok(())
+    }
+
+    /// Sends bodies in DATA frames until flow control or the socket stops it
+    fn send<S: Write>(&mut self, out: &mut Out<S>) -> io::Result<()> {
+        while self.window > 0 && !out.backed_up() {
+            let Some(i) = self.streams.iter().position(|s| s.window > 0) else {
+                break;
+            };
+            let stream = &mut self.streams[i];
+            let remaining = stream.data.len() - stream.pos;
+            let window = self.window.min(stream.window) as usize;
+            let len = remaining.min(self.max_frame_size).min(window);
+            let end = len == remaining;
+
+            let data = &stream.data[stream.pos..stream.pos + len];
+            write_frame(out, DATA, if end { END_STREAM } else { 0 }, stream.id, data)?;
+            stream.pos += len;
+            stream.window -= len as i64;
+            self.window -= len as i64;
+
+            if end {
+                if stream.open {
+                    write_frame(out, RST_STREAM, 0, stream.id, &NO_ERROR.to_be_bytes())?;
+                }
+                self.streams.remove(i);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A HEADERS payload without its padding
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], Error> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&pad, rest) = payload
+        .split_first()
+        .ok_or(Error::Connection(PROTOCOL_ERROR))?;
+    let len = rest
+        .len()
+        .checked_sub(pad as usize)
+        .ok_or(Error::Connection(PROTOCOL_ERROR))?;
+    Ok(&rest[..len])
+}
+
+/// Writes a frame's header and payload together, as one TLS record
+fn write_frame<W: Write>(
+    out: &mut W,
+    kind: u8,
+    flags: u8,
+    id: u32,
+    payload: &[u8],
+) -> io::Result<()> {
+    let [_, a, b, c] = (payload.len() as u32).to_be_bytes();
+    let [d, e, f, g] = id.to_be_bytes();
+    let header = [a, b, c, kind, flags, d, e, f, g];
+
+    let mut bufs = [IoSlice::new(&header), IoSlice::new(payload)];
+    let mut bufs = &mut bufs[..if payload.is_empty() { 1 } else { 2 }];
+    while !bufs.is_empty() {
+        match out.write_vectored(bufs) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::tests::mock_routing_table;
+
+    const PEER: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 1234);
+
+    #[derive(Debug, PartialEq)]
+    struct Frame {
+        kind: u8,
+        flags: u8,
+        id: u32,
+        payload: Vec<u8>,
+    }
+
+    fn frame(kind: u8, flags: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, kind, flags, id, payload).unwrap();
+        buf
+    }
+
+    fn frames(mut buf: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        while !buf.is_empty() {
+            let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]) as usize;
+            frames.push(Frame {
+                kind: buf[3],
+                flags: buf[4],
+                id: u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]),
+                payload: buf[9..9 + len].to_vec(),
+            });
+            buf = &buf[9 + len..];
+        }
+        frames
+    }
+
+    /// GET (or HEAD) `path` on `id` with the request finished
+    fn get(id: u32, method: &str, path: &str, accept_encoding: Option<&str>) -> Vec<u8> {
+        // :method GET/HEAD, :scheme http, literal :path
+        let mut block = match method {
+            "GET" => vec![0x82],
+            _ => vec![0x02, method.len() as u8],
+        };
+        block.extend_from_slice(if method == "GET" {
+            b""
+        } else {
+            method.as_bytes()
+        });
+        block.extend_from_slice(&[0x86, 0x04, path.len() as u8]);
+        block.extend_from_slice(path.as_bytes());
+        if let Some(accept) = accept_encoding {
+            block.extend_from_slice(&[0x0f, 0x01, accept.len() as u8]);
+            block.extend_from_slice(accept.as_bytes());
+        }
+        frame(HEADERS, END_STREAM | END_HEADERS, id, &block)
+    }
+
+    /// Runs `input` through `session`, returning the frames sent
+    fn serve(session: &mut Session, input: &[u8]) -> (Vec<Frame>, bool) {
+        let (mut output, mut pending) = (Vec::new(), VecDeque::new());
+        let (consumed, open) = session
+            .serve(&mut Out::new(&mut output, &mut pending), input, PEER)
+            .unwrap();
+        assert_eq!(consumed, input.len());
+        (frames(&output), open)
+    }
+
+    fn start(settings: &[u8]) -> Session {
+        let mut session = Session::default();
+        let input = [PREFACE, &frame(SETTINGS, 0, 0, settings)].concat();
+        let (frames, open) = serve(&mut session, &input);
+        assert!(open);
+        assert_eq!(frames[0].kind, SETTINGS);
+        assert_eq!(
+            frames[1],
+            Frame {
+                kind: SETTINGS,
+                flags: ACK,
+                id: 0,
+                payload: vec![]
+            }
+        );
+        session
+    }
+
+    fn status(frame: &Frame) -> Vec<u8> {
+        assert_eq!(frame.kind, HEADERS);
+        let fields = Decoder::new().decode(&frame.payload).unwrap();
+        assert_eq!(fields[0].0, b":status");
+        fields[0].1.clone()
+    }
+
+    #[test]
+    fn test_preface() {
+        assert_eq!(preface(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0"), Some(true));
+        assert_eq!(preface(b"PRI * HTTP/2"), None);
+        assert_eq!(preface(b"GET / HTTP/1.1\r\n\r\n"), Some(false));
+        assert_eq!(preface(b"PRI / HTTP/1.1\r\n\r\n"), Some(false));
+    }
+
+    #[test]
+    fn test_get() {
+        mock_routing_table();
+        let mut session = start(&[]);
+
+        let (frames, _) = serve(&mut session, &get(1, "GET", "/test", None));
+        assert_eq!(frames.len(), 2);
+        assert_eq!(status(&frames[0]), b"200");
+        assert_eq!(frames[0].flags, END_HEADERS);
+        assert_eq!(
+            frames[1],
+            Frame {
+                kind: DATA,
+                flags: END_STREAM,
+                id: 1,
+                payload: b"identity-body".to_vec()
+            }
+        );
+
+        // the block is the one precomputed for the route
+        let table = ROUTING_TABLE.load();
+        let (frames, _) = serve(&mut session, &get(3, "GET", "/test", Some("br")));
+        assert_eq!(frames[0].payload, table["/test"].brotli.h2.as_ref());
+        assert_eq!(frames[1].payload, b"br-body");
+
+        let (frames, _) = serve(&mut session, &get(5, "HEAD", "/test", None));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].flags, END_STREAM | END_HEADERS);
+
+        let (frames, _) = serve(&mut session, &get(7, "GET", "/test/?a", None));
+        assert_eq!(status(&frames[0]), b"308");
+        let (frames, _) = serve(&mut session, &get(9, "DELETE", "/test", None));
+        assert_eq!(status(&frames[0]), b"405");
+    }
+
+    #[test]
+    fn test_flow_control() {
+        mock_routing_table();
+        // SETTINGS_INITIAL_WINDOW_SIZE of 4
+        let mut session = start(&[0, 4, 0, 0, 0, 4]);
+
+        let (frames, _) = serve(&mut session, &get(1, "GET", "/test", None));
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].payload, b"iden");
+        assert_eq!(frames[1].flags, 0);
+
+        let (frames, _) = serve(
+            &mut session,
+            &frame(WINDOW_UPDATE, 0, 1, &100u32.to_be_bytes()),
+        );
+        assert_eq!(
+            frames,
+            [Frame {
+                kind: DATA,
+                flags: END_STREAM,
+                id: 1,
+                payload: b"tity-body".to_vec()
+            }]
+        );
+        assert!(session.streams.is_empty());
+        assert_eq!(session.window, DEFAULT_WINDOW - 13);
+
+        // the connection's window holds everything back
+        session.window = 0;
+        let (frames, _) = serve(&mut session, &get(3, "GET", "/test", None));
+        assert_eq!(frames.len(), 1);
+        let (frames, _) = serve(
+            &mut session,
+            &frame(WINDOW_UPDATE, 0, 0, &2u32.to_be_bytes()),
+        );
+        assert_eq!(frames[0].payload, b"id");
+
+        // a window pushed past the maximum by a new initial size
+        let increment = (MAX_WINDOW as u32 - 2).to_be_bytes();
+        serve(&mut session, &frame(WINDOW_UPDATE, 0, 3, &increment));
+        let settings = [&[0, 4][..], &(MAX_WINDOW as u32).to_be_bytes()].concat();
+        let (frames, open) = serve(&mut session, &frame(SETTINGS, 0, 0, &settings));
+        assert!(!open);
+        assert_eq!(frames.last().unwrap().kind, GOAWAY);
+        assert_eq!(
+            frames.last().unwrap().payload[4..],
+            FLOW_CONTROL_ERROR.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn test_request_body() {
+        mock_routing_table();
+        let mut session = start(&[0, 4, 0, 0, 0, 4]);
+
+        // still sending its body while the response waits on flow control
+        let mut headers = get(1, "GET", "/test", None);
+        headers[4] = END_HEADERS;
+        serve(&mut session, &headers);
+
+        let update = |id, len: u32| Frame {
+            kind: WINDOW_UPDATE,
+            flags: 0,
+            id,
+            payload: len.to_be_bytes().to_vec(),
+        };
+        let (frames, _) = serve(&mut session, &frame(DATA, 0, 1, &[0; 100]));
+        assert_eq!(frames, [update(0, 100), update(1, 100)]);
+
+        // the stream is done, only the connection's window needs it back
+        let (frames, _) = serve(&mut session, &frame(DATA, END_STREAM, 1, &[0; 50]));
+        assert_eq!(frames, [update(0, 50)]);
+        assert!(!session.streams[0].open);
+        let (frames, _) = serve(&mut session, &frame(DATA, END_STREAM, 3, &[0; 10]));
+        assert_eq!(frames, [update(0, 10)]);
+    }
+
+    #[test]
+    fn test_control_frames() {
+        let mut session = start(&[]);
+
+        let (frames, _) = serve(&mut session, &frame(PING, 0, 0, b"12345678"));
+        assert_eq!(
+            frames,
+            [Frame {
+                kind: PING,
+                flags: ACK,
+                id: 0,
+                payload: b"12345678".to_vec()
+            }]
+        );
+
+        let (frames, open) = serve(&mut session, &frame(GOAWAY, 0, 0, &[0; 8]));
+        assert!(frames.is_empty());
+        assert!(!open);
+    }
+
+    #[test]
+    fn test_connection_errors() {
+        let goaway = |session: &mut Session, input: &[u8]| {
+            let (frames, open) = serve(session, input);
+            assert!(!open);
+            assert_eq!(frames.last().unwrap().kind, GOAWAY);
+            u32::from_be_bytes(frames.last().unwrap().payload[4..8].try_into().unwrap())
+        };
+
+        let oversized = [0x00, 0x40, 0x01, DATA, 0, 0, 0, 0, 1];
+        assert_eq!(goaway(&mut start(&[]), &oversized), FRAME_SIZE_ERROR);
+        assert_eq!(
+            goaway(&mut start(&[]), &frame(PING, 0, 0, b"1234")),
+            FRAME_SIZE_ERROR
+        );
+        assert_eq!(
+            goaway(&mut start(&[]), &frame(HEADERS, END_HEADERS, 2, &[0x82])),
+            PROTOCOL_ERROR
+        );
+        assert_eq!(
+            goaway(&mut start(&[]), &frame(HEADERS, END_HEADERS, 1, &[0x80])),
+            COMPRESSION_ERROR
+        );
+
+        // CONTINUATION interrupted
+        let input = [frame(HEADERS, 0, 1, &[0x82]), frame(PING, 0, 0, &[0; 8])].concat();
+        assert_eq!(goaway(&mut start(&[]), &input), PROTOCOL_ERROR);
+    }
+
+    #[test]
+    fn test_continuation() {
+        mock_routing_table();
+        let mut session = start(&[]);
+        let request = get(1, "GET", "/test", None);
+        let block = &request[FRAME_HEADER_SIZE..];
+
+        let input = [
+            frame(HEADERS, END_STREAM, 1, &block[..3]),
+            frame(CONTINUATION, 0, 1, &block[3..5]),
+            frame(CONTINUATION, END_HEADERS, 1, &block[5..]),
+        ]
+        .concat();
+        let (frames, _) = serve(&mut session, &input);
+        assert_eq!(status(&frames[0]), b"200");
+        assert_eq!(frames[1].flags, END_STREAM);
+    }
+
+    #[test]
+    fn test_partial_frame() {
+        let mut session = start(&[]);
+        let ping = frame(PING, 0, 0, b"12345678");
+        let (mut output, mut pending) = (Vec::new(), VecDeque::new());
+        let (consumed, open) = session
+            .serve(&mut Out::new(&mut output, &mut pending), &ping[..12], PEER)
+            .unwrap();
+        assert_eq!((consumed, open), (0, true));
+        assert!(output.is_empty());
+    }
+}
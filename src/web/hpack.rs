@@ -0,0 +1,536 @@
+//! HPACK (RFC 7541): decoding request header blocks, and encoding response
+//! heads without the dynamic table so one block can be sent on any connection
+
+use anyhow::{Context, Result, bail};
+use httparse::{EMPTY_HEADER, Response, Status};
+use rustc_hash::FxHashMap;
+use std::collections::VecDeque;
+use std::sync::LazyLock;
+
+/// Our SETTINGS_HEADER_TABLE_SIZE, the default
+const TABLE_SIZE: usize = 4096;
+/// Decoded header list size (as counted for SETTINGS_MAX_HEADER_LIST_SIZE)
+/// before giving up, references to the dynamic table are cheap to send
+const MAX_LIST_SIZE: usize = 65_536;
+/// Added to an entry's name and value length for its size in the table
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Not sent as HTTP/2 has its own framing and connection management
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// (bit length, code) of each byte and EOS (Appendix B)
+#[rustfmt::skip]
+const HUFFMAN: [(u8, u32); 257] = [
+    (13, 0x1ff8), (23, 0x7fffd8), (28, 0xfffffe2), (28, 0xfffffe3), (28, 0xfffffe4), (28, 0xfffffe5),
+    (28, 0xfffffe6), (28, 0xfffffe7), (28, 0xfffffe8), (24, 0xffffea), (30, 0x3ffffffc), (28, 0xfffffe9),
+    (28, 0xfffffea), (30, 0x3ffffffd), (28, 0xfffffeb), (28, 0xfffffec), (28, 0xfffffed), (28, 0xfffffee),
+    (28, 0xfffffef), (28, 0xffffff0), (28, 0xffffff1), (28, 0xffffff2), (30, 0x3ffffffe), (28, 0xffffff3),
+    (28, 0xffffff4), (28, 0xffffff5), (28, 0xffffff6), (28, 0xffffff7), (28, 0xffffff8), (28, 0xffffff9),
+    (28, 0xffffffa), (28, 0xffffffb), (6, 0x14), (10, 0x3f8), (10, 0x3f9), (12, 0xffa),
+    (13, 0x1ff9), (6, 0x15), (8, 0xf8), (11, 0x7fa), (10, 0x3fa), (10, 0x3fb),
+    (8, 0xf9), (11, 0x7fb), (8, 0xfa), (6, 0x16), (6, 0x17), (6, 0x18),
+    (5, 0x0), (5, 0x1), (5, 0x2), (6, 0x19), (6, 0x1a), (6, 0x1b),
+    (6, 0x1c), (6, 0x1d), (6, 0x1e), (6, 0x1f), (7, 0x5c), (8, 0xfb),
+    (15, 0x7ffc), (6, 0x20), (12, 0xffb), (10, 0x3fc), (13, 0x1ffa), (6, 0x21),
+    (7, 0x5d), (7, 0x5e), (7, 0x5f), (7, 0x60), (7, 0x61), (7, 0x62),
+    (7, 0x63), (7, 0x64), (7, 0x65), (7, 0x66), (7, 0x67), (7, 0x68),
+    (7, 0x69), (7, 0x6a), (7, 0x6b), (7, 0x6c), (7, 0x6d), (7, 0x6e),
+    (7, 0x6f), (7, 0x70), (7, 0x71), (7, 0x72), (8, 0xfc), (7, 0x73),
+    (8, 0xfd), (13, 0x1ffb), (19, 0x7fff0), (13, 0x1ffc), (14, 0x3ffc), (6, 0x22),
+    (15, 0x7ffd), (5, 0x3), (6, 0x23), (5, 0x4), (6, 0x24), (5, 0x5),
+    (6, 0x25), (6, 0x26), (6, 0x27), (5, 0x6), (7, 0x74), (7, 0x75),
+    (6, 0x28), (6, 0x29), (6, 0x2a), (5, 0x7), (6, 0x2b), (7, 0x76),
+    (6, 0x2c), (5, 0x8), (5, 0x9), (6, 0x2d), (7, 0x77), (7, 0x78),
+    (7, 0x79), (7, 0x7a), (7, 0x7b), (15, 0x7ffe), (11, 0x7fc), (14, 0x3ffd),
+    (13, 0x1ffd), (28, 0xffffffc), (20, 0xfffe6), (22, 0x3fffd2), (20, 0xfffe7), (20, 0xfffe8),
+    (22, 0x3fffd3), (22, 0x3fffd4), (22, 0x3fffd5), (23, 0x7fffd9), (22, 0x3fffd6), (23, 0x7fffda),
+    (23, 0x7fffdb), (23, 0x7fffdc), (23, 0x7fffdd), (23, 0x7fffde), (24, 0xffffeb), (23, 0x7fffdf),
+    (24, 0xffffec), (24, 0xffffed), (22, 0x3fffd7), (23, 0x7fffe0), (24, 0xffffee), (23, 0x7fffe1),
+    (23, 0x7fffe2), (23, 0x7fffe3), (23, 0x7fffe4), (21, 0x1fffdc), (22, 0x3fffd8), (23, 0x7fffe5),
+    (22, 0x3fffd9), (23, 0x7fffe6), (23, 0x7fffe7), (24, 0xffffef), (22, 0x3fffda), (21, 0x1fffdd),
+    (20, 0xfffe9), (22, 0x3fffdb), (22, 0x3fffdc), (23, 0x7fffe8), (23, 0x7fffe9), (21, 0x1fffde),
+    (23, 0x7fffea), (22, 0x3fffdd), (22, 0x3fffde), (24, 0xfffff0), (21, 0x1fffdf), (22, 0x3fffdf),
+    (23, 0x7fffeb), (23, 0x7fffec), (21, 0x1fffe0), (21, 0x1fffe1), (22, 0x3fffe0), (21, 0x1fffe2),
+    (23, 0x7fffed), (22, 0x3fffe1), (23, 0x7fffee), (23, 0x7fffef), (20, 0xfffea), (22, 0x3fffe2),
+    (22, 0x3fffe3), (22, 0x3fffe4), (23, 0x7ffff0), (22, 0x3fffe5), (22, 0x3fffe6), (23, 0x7ffff1),
+    (26, 0x3ffffe0), (26, 0x3ffffe1), (20, 0xfffeb), (19, 0x7fff1), (22, 0x3fffe7), (23, 0x7ffff2),
+    (22, 0x3fffe8), (25, 0x1ffffec), (26, 0x3ffffe2), (26, 0x3ffffe3), (26, 0x3ffffe4), (27, 0x7ffffde),
+    (27, 0x7ffffdf), (26, 0x3ffffe5), (24, 0xfffff1), (25, 0x1ffffed), (19, 0x7fff2), (21, 0x1fffe3),
+    (26, 0x3ffffe6), (27, 0x7ffffe0), (27, 0x7ffffe1), (26, 0x3ffffe7), (27, 0x7ffffe2), (24, 0xfffff2),
+    (21, 0x1fffe4), (21, 0x1fffe5), (26, 0x3ffffe8), (26, 0x3ffffe9), (28, 0xffffffd), (27, 0x7ffffe3),
+    (27, 0x7ffffe4), (27, 0x7ffffe5), (20, 0xfffec), (24, 0xfffff3), (20, 0xfffed), (21, 0x1fffe6),
+    (22, 0x3fffe9), (21, 0x1fffe7), (21, 0x1fffe8), (23, 0x7ffff3), (22, 0x3fffea), (22, 0x3fffeb),
+    (25, 0x1ffffee), (25, 0x1ffffef), (24, 0xfffff4), (24, 0xfffff5), (26, 0x3ffffea), (23, 0x7ffff4),
+    (26, 0x3ffffeb), (27, 0x7ffffe6), (26, 0x3ffffec), (26, 0x3ffffed), (27, 0x7ffffe7), (27, 0x7ffffe8),
+    (27, 0x7ffffe9), (27, 0x7ffffea), (27, 0x7ffffeb), (28, 0xffffffe), (27, 0x7ffffec), (27, 0x7ffffed),
+    (27, 0x7ffffee), (27, 0x7ffffef), (27, 0x7fffff0), (26, 0x3ffffee), (30, 0x3fffffff),
+];
+const EOS: u16 = 256;
+
+/// (bit length, code) → symbol
+static HUFFMAN_DECODE: LazyLock<FxHashMap<(u8, u32), u16>> = LazyLock::new(|| {
+    (0..)
+        .zip(HUFFMAN)
+        .map(|(symbol, (len, code))| ((len, code), symbol))
+        .collect()
+});
+
+pub type Field = (Vec<u8>, Vec<u8>);
+
+/// Decodes the header blocks of one connection, which share a dynamic table
+pub struct Decoder {
+    /// Newest first
+    table: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: TABLE_SIZE,
+        }
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<Field>> {
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        let mut pos = 0;
+
+        while let Some(&byte) = block.get(pos) {
+            let field = if byte & 0x80 != 0 {
+                // indexed
+                let index = integer(block, &mut pos, 7)?;
+                self.get(index)?.clone()
+            } else if byte & 0xe0 == 0x20 {
+                // dynamic table size update, only allowed at the start of a block
+                if !fields.is_empty() {
+                    bail!("table size update after a field");
+                }
+                let size = integer(block, &mut pos, 5)?;
+                if size > TABLE_SIZE {
+                    bail!("table size {size} over {TABLE_SIZE}");
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // literal, with incremental indexing if 01, otherwise without
+                let indexing = byte & 0xc0 == 0x40;
+                let index = integer(block, &mut pos, if indexing { 6 } else { 4 })?;
+                let name = match index {
+                    0 => string(block, &mut pos)?,
+                    index => self.get(index)?.0.clone(),
+                };
+                let field = (name, string(block, &mut pos)?);
+                if indexing {
+                    self.insert(field.clone());
+                }
+                field
+            };
+
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            if list_size > MAX_LIST_SIZE {
+                bail!("header list over {MAX_LIST_SIZE} bytes");
+            }
+            fields.push(field);
+        }
+
+        Ok(fields)
+    }
+
+    fn get(&self, index: usize) -> Result<&Field> {
+        static FIELDS: LazyLock<Vec<Field>> = LazyLock::new(|| {
+            STATIC_TABLE
+                .iter()
+                .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                .collect()
+        });
+
+        match index {
+            0 => bail!("index 0"),
+            1..=61 => Ok(&FIELDS[index - 1]),
+            _ => self.table.get(index - 62).context("index past the table"),
+        }
+    }
+
+    fn insert(&mut self, field: Field) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // larger than the whole table just empties it
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(field);
+        }
+    }
+
+    /// Evicts the oldest entries until `room` more bytes fit
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// The HPACK block for the head of an HTTP/1.1 `response` and where its
+/// body starts. None if the head isn't complete
+pub fn encode_head(response: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut headers = [EMPTY_HEADER; 32];
+    let mut resp = Response::new(&mut headers);
+    let Ok(Status::Complete(body)) = resp.parse(response) else {
+        return None;
+    };
+
+    let mut block = Vec::with_capacity(body);
+    let status = resp.code?.to_string();
+    match STATIC_TABLE
+        .iter()
+        .position(|(name, value)| *name == ":status" && *value == status)
+    {
+        Some(index) => encode_integer(&mut block, 0x80, 7, index + 1),
+        None => literal(&mut block, ":status", status.as_bytes()),
+    }
+
+    for header in resp.headers.iter() {
+        let name = header.name.to_ascii_lowercase();
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            literal(&mut block, &name, header.value);
+        }
+    }
+
+    Some((block, body))
+}
+
+/// Literal without indexing, with the name indexed if it's in the static table
+fn literal(block: &mut Vec<u8>, name: &str, value: &[u8]) {
+    match STATIC_TABLE.iter().position(|(n, _)| *n == name) {
+        Some(index) => encode_integer(block, 0x00, 4, index + 1),
+        None => {
+            block.push(0x00);
+            encode_string(block, name.as_bytes());
+        }
+    }
+    encode_string(block, value);
+}
+
+fn encode_integer(block: &mut Vec<u8>, pattern: u8, prefix: u32, mut value: usize) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        block.push(pattern | value as u8);
+        return;
+    }
+
+    block.push(pattern | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Huffman coded when that's shorter
+fn encode_string(block: &mut Vec<u8>, value: &[u8]) {
+    let bits: usize = value.iter().map(|b| HUFFMAN[*b as usize].0 as usize).sum();
+    let len = bits.div_ceil(8);
+    if len >= value.len() {
+        encode_integer(block, 0x00, 7, value.len());
+        block.extend_from_slice(value);
+        return;
+    }
+
+    encode_integer(block, 0x80, 7, len);
+    let mut acc = 0u64;
+    let mut acc_bits = 0;
+    for byte in value {
+        let (code_len, code) = HUFFMAN[*byte as usize];
+        acc = (acc << code_len) | code as u64;
+        acc_bits += code_len;
+        while acc_bits >= 8 {
+            acc_bits -= 8;
+            block.push((acc >> acc_bits) as u8);
+        }
+    }
+    if acc_bits > 0 {
+        // padded with the start of EOS (all ones)
+        let pad = 8 - acc_bits;
+        block.push(((acc << pad) | ((1 << pad) - 1)) as u8);
+    }
+}
+
+fn integer(block: &[u8], pos: &mut usize, prefix: u32) -> Result<usize> {
+    let max = (1 << prefix) - 1;
+    let mut value = (block[*pos] & max) as usize;
+    *pos += 1;
+    if value < max as usize {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*pos).context("truncated integer")?;
+        *pos += 1;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 28 {
+            bail!("integer too large");
+        }
+    }
+}
+
+fn string(block: &[u8], pos: &mut usize) -> Result<Vec<u8>> {
+    let huffman = *block.get(*pos).context("missing string")? & 0x80 != 0;
+    let len = integer(block, pos, 7)?;
+    let data = block.get(*pos..*pos + len).context("truncated string")?;
+    *pos += len;
+
+    if huffman {
+        decode_huffman(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+fn decode_huffman(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut len = 0u8;
+
+    for byte in data {
+        for bit in (0..8).rev() {
+            code = (code << 1) | ((byte >> bit) & 1) as u32;
+            len += 1;
+            match HUFFMAN_DECODE.get(&(len, code)) {
+                Some(&EOS) => bail!("EOS in huffman string"),
+                Some(&symbol) => {
+                    out.push(symbol as u8);
+                    code = 0;
+                    len = 0;
+                }
+                None if len >= 30 => bail!("invalid huffman code"),
+                None => {}
+            }
+        }
+    }
+
+    // padding is under a byte of EOS's leading ones
+    if len > 7 || code != (1 << len) - 1 {
+        bail!("invalid huffman padding");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<Field> {
+        pairs
+            .iter()
+            .map(|(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_integer() {
+        // C.1.2: 1337 with a 5 bit prefix
+        let mut block = Vec::new();
+        encode_integer(&mut block, 0, 5, 1337);
+        assert_eq!(block, [31, 154, 10]);
+        assert_eq!(integer(&block, &mut 0, 5).unwrap(), 1337);
+
+        assert_eq!(integer(&[10], &mut 0, 5).unwrap(), 10);
+        assert!(integer(&[31, 0xff], &mut 0, 5).is_err());
+        assert!(integer(&[31, 0xff, 0xff, 0xff, 0xff, 0xff], &mut 0, 5).is_err());
+    }
+
+    #[test]
+    fn test_huffman() {
+        // C.4.1
+        let encoded = hex("f1e3c2e5f23a6ba0ab90f4ff");
+        assert_eq!(decode_huffman(&encoded).unwrap(), b"www.example.com");
+
+        let mut block = Vec::new();
+        encode_string(&mut block, b"www.example.com");
+        assert_eq!(block[0], 0x80 | encoded.len() as u8);
+        assert_eq!(block[1..], encoded);
+
+        // padding of zeros, or a whole byte of it
+        assert!(decode_huffman(&[0x00]).is_err());
+        assert!(decode_huffman(&hex("f1e3c2e5f23a6ba0ab90f4ffff")).is_err());
+    }
+
+    #[test]
+    fn test_decode_requests() {
+        // C.4, a connection's requests with huffman coding
+        let mut decoder = Decoder::new();
+        let first = decoder
+            .decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff"))
+            .unwrap();
+        assert_eq!(
+            first,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+
+        let second = decoder.decode(&hex("828684be5886a8eb10649cbf")).unwrap();
+        assert_eq!(
+            second,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+
+        let third = decoder
+            .decode(&hex("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf"))
+            .unwrap();
+        assert_eq!(
+            third,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+        assert_eq!(decoder.size, 164);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut decoder = Decoder::new();
+        // size update to 64, then two 42 byte entries
+        let mut block = vec![0x3f, 0x21];
+        for value in ["aaaa", "bbbb"] {
+            block.extend_from_slice(&[0x40, 0x06]);
+            block.extend_from_slice(b"custom");
+            block.push(value.len() as u8);
+            block.extend_from_slice(value.as_bytes());
+        }
+        decoder.decode(&block).unwrap();
+        assert_eq!(decoder.table, fields(&[("custom", "bbbb")]));
+
+        assert!(decoder.decode(&[0xbf]).is_err());
+        assert!(decoder.decode(&[0x80]).is_err());
+        assert!(decoder.decode(&[0x3f, 0xe2, 0x1f]).is_err());
+        assert!(decoder.decode(&[0x82, 0x3f, 0x21]).is_err());
+    }
+
+    #[test]
+    fn test_encode_head() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5\r\n\
+                         Connection: close\r\nX-Custom: yes\r\n\r\nhello";
+        let (block, body) = encode_head(response).unwrap();
+        assert_eq!(&response[body..], b"hello");
+        assert_eq!(
+            Decoder::new().decode(&block).unwrap(),
+            fields(&[
+                (":status", "200"),
+                ("content-type", "text/html"),
+                ("content-length", "5"),
+                ("x-custom", "yes"),
+            ])
+        );
+
+        let (block, _) = encode_head(b"HTTP/1.1 308 Permanent Redirect\r\n\r\n").unwrap();
+        assert_eq!(
+            Decoder::new().decode(&block).unwrap(),
+            fields(&[(":status", "308")])
+        );
+        assert!(encode_head(b"HTTP/1.1 200 OK\r\n").is_none());
+    }
+}
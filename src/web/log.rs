@@ -0,0 +1,315 @@
+//! Access log, one line per response in Common/Combined Log Format or JSON
+
+use crate::WebArgs;
+use anyhow::{Context, Result};
+use memchr::memmem;
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+
+static LOG: OnceLock<Logger> = OnceLock::new();
+
+/// Lines waiting for the writer thread, past which new ones are dropped
+/// rather than holding up a worker on a slow disk
+const QUEUE_SIZE: usize = 8192;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Common,
+    Combined,
+    Json,
+}
+
+struct Logger {
+    format: LogFormat,
+    lines: SyncSender<String>,
+}
+
+enum Sink {
+    Stdout,
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+        max_size: u64,
+    },
+}
+
+/// Enables the access log if `--access-log` was given
+pub fn init(args: &WebArgs) -> Result<()> {
+    let Some(path) = &args.access_log else {
+        return Ok(());
+    };
+
+    let sink = match path.to_str() {
+        Some("-") => Sink::Stdout,
+        _ => {
+            let file = open(path)?;
+            let size = file.metadata()?.len();
+            Sink::File {
+                path: path.clone(),
+                file,
+                size,
+                max_size: args.access_log_max_size,
+            }
+        }
+    };
+
+    let (lines, queue) = mpsc::sync_channel(QUEUE_SIZE);
+    thread::Builder::new()
+        .name("access-log".into())
+        .spawn(move || drain(queue, sink))?;
+
+    let _ = LOG.set(Logger {
+        format: args.access_log_format,
+        lines,
+    });
+    Ok(())
+}
+
+/// Writes queued lines, batching whatever piled up since the last write
+fn drain(queue: Receiver<String>, mut sink: Sink) {
+    while let Ok(mut batch) = queue.recv() {
+        batch.extend(queue.try_iter());
+        if let Err(e) = sink.write(batch.as_bytes()) {
+            eprintln!("Error writing access log: {e}");
+        }
+    }
+}
+
+pub fn enabled() -> bool {
+    LOG.get().is_some()
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening access log {}", path.display()))
+}
+
+/// A request and what was sent back
+pub struct Entry<'a> {
+    pub peer: SocketAddr,
+    pub method: &'a str,
+    /// Path and query as requested
+    pub target: &'a str,
+    /// `HTTP/1.1` or `HTTP/2.0`
+    pub protocol: &'a str,
+    pub referer: Option<&'a [u8]>,
+    pub user_agent: Option<&'a [u8]>,
+    pub status: u16,
+    /// Response body size
+    pub bytes: usize,
+    pub encoding: Option<&'a str>,
+    pub latency: Duration,
+}
+
+pub fn write(entry: &Entry) {
+    let Some(logger) = LOG.get() else {
+        return;
+    };
+
+    let mut line = format(entry, logger.format, OffsetDateTime::now_utc());
+    line.push('\n');
+
+    if let Err(TrySendError::Full(_)) = logger.lines.try_send(line) {
+        eprintln!("Access log backed up, dropping a line");
+    }
+}
+
+impl Sink {
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        match self {
+            Sink::Stdout => io::stdout().lock().write_all(line)?,
+            Sink::File {
+                path,
+                file,
+                size,
+                max_size,
+            } => {
+                if *size > 0 && *size + line.len() as u64 > *max_size {
+                    let mut rotated = path.clone().into_os_string();
+                    rotated.push(".1");
+                    fs::rename(&*path, rotated)?;
+                    *file = open(path)?;
+                    *size = 0;
+                }
+                file.write_all(line)?;
+                *size += line.len() as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format(entry: &Entry, format: LogFormat, now: OffsetDateTime) -> String {
+    let referer = entry.referer.map(String::from_utf8_lossy);
+    let user_agent = entry.user_agent.map(String::from_utf8_lossy);
+
+    if format == LogFormat::Json {
+        return json!({
+            "time": now.format(&Rfc3339).unwrap_or_default(),
+            "addr": entry.peer.ip().to_string(),
+            "method": entry.method,
+            "path": entry.target,
+            "protocol": entry.protocol,
+            "status": entry.status,
+            "bytes": entry.bytes,
+            "encoding": entry.encoding,
+            "etag_hit": entry.status == 304,
+            "latency_ms": entry.latency.as_secs_f64() * 1000.,
+            "referer": referer,
+            "user_agent": user_agent,
+        })
+        .to_string();
+    }
+
+    let time = now
+        .format(format_description!(
+            "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] +0000"
+        ))
+        .unwrap_or_default();
+    let bytes = match entry.bytes {
+        0 => "-".to_string(),
+        n => n.to_string(),
+    };
+    let mut line = format!(
+        "{} - - [{time}] \"{} {} {}\" {} {bytes}",
+        entry.peer.ip(),
+        entry.method,
+        entry.target,
+        entry.protocol,
+        entry.status,
+    );
+
+    if format == LogFormat::Combined {
+        let quote = |v: Option<std::borrow::Cow<str>>| match v {
+            Some(v) => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => "\"-\"".to_string(),
+        };
+        line.push_str(&format!(" {} {}", quote(referer), quote(user_agent)));
+    }
+
+    line
+}
+
+/// What a response sent back, read from its full head
+pub struct Sent<'a> {
+    pub status: u16,
+    pub encoding: Option<&'a str>,
+    /// Body size
+    pub bytes: usize,
+}
+
+impl<'a> Sent<'a> {
+    pub fn new(head: &'a [u8], bytes: usize) -> Self {
+        let status = head
+            .get(9..12)
+            .and_then(|s| str::from_utf8(s).ok())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        let encoding = memmem::find(head, b"\r\nContent-Encoding: ").and_then(|start| {
+            let start = start + 20;
+            let len = memmem::find(&head[start..], b"\r\n")?;
+            str::from_utf8(&head[start..start + len]).ok()
+        });
+        Sent {
+            status,
+            encoding,
+            bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn entry() -> Entry<'static> {
+        Entry {
+            peer: "10.0.0.1:5555".parse().unwrap(),
+            method: "GET",
+            target: "/blog?a=1",
+            protocol: "HTTP/1.1",
+            referer: Some(b"https://example.com/"),
+            user_agent: Some(b"curl/8.0 \"quoted\""),
+            status: 200,
+            bytes: 1234,
+            encoding: Some("br"),
+            latency: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let now = datetime!(2026-02-03 04:05:06 UTC);
+
+        assert_eq!(
+            format(&entry(), LogFormat::Common, now),
+            "10.0.0.1 - - [03/Feb/2026:04:05:06 +0000] \"GET /blog?a=1 HTTP/1.1\" 200 1234"
+        );
+        assert_eq!(
+            format(&entry(), LogFormat::Combined, now),
+            "10.0.0.1 - - [03/Feb/2026:04:05:06 +0000] \"GET /blog?a=1 HTTP/1.1\" 200 1234 \
+             \"https://example.com/\" \"curl/8.0 \\\"quoted\\\"\""
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format(&entry(), LogFormat::Json, now)).unwrap();
+        assert_eq!(json["time"], "2026-02-03T04:05:06Z");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["encoding"], "br");
+        assert_eq!(json["etag_hit"], false);
+        assert_eq!(json["latency_ms"], 1.5);
+    }
+
+    #[test]
+    fn test_sent() {
+        let padding = "X-Padding: ".to_string() + &"a".repeat(1000);
+        let head =
+            format!("HTTP/1.1 206 Partial Content\r\n{padding}\r\nContent-Encoding: gzip\r\n\r\n");
+        let sent = Sent::new(head.as_bytes(), 1000);
+        assert_eq!(sent.status, 206);
+        assert_eq!(sent.encoding, Some("gzip"));
+        assert_eq!(sent.bytes, 1000);
+
+        let sent = Sent::new(b"", 0);
+        assert_eq!(sent.status, 0);
+        assert_eq!(sent.encoding, None);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let mut sink = Sink::File {
+            path: path.clone(),
+            file: open(&path).unwrap(),
+            size: 0,
+            max_size: 10,
+        };
+        sink.write(b"first\n").unwrap();
+        sink.write(b"second\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("access.log.1")).unwrap(),
+            "first\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,227 +1,667 @@
-use crate::web::route::{BAD_REQUEST, NOT_FOUND, OK, UNAUTHORIZED};
-use crate::{ROUTING_TABLE, WebArgs, update};
+use crate::web::conn::{Conn, WriteShared};
+use crate::web::log::Sent;
+use crate::web::path::Lookup;
+use crate::web::route::{
+    BAD_REQUEST, Encoding, NOT_FOUND, NOT_FOUND_PAGE, OK, Response, Route, SERVICE_UNAVAILABLE,
+    UNAUTHORIZED,
+};
+use crate::web::tls::TlsStream;
+use crate::{ROUTING_TABLE, RoutingTable, WebArgs, metrics, update};
 use anyhow::Result;
 use httparse::{EMPTY_HEADER, Request, Status};
 use memchr::memmem;
-use std::io::{self, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
+use rustc_hash::FxHashMap;
+use rustls::ServerConfig;
+use std::borrow::Cow;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::SocketAddr;
+use std::ops::Deref;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
-
+use std::time::{Duration, Instant};
+
+mod conn;
+mod h2;
+mod hpack;
+pub mod log;
+mod path;
+mod range;
 pub mod route;
+mod tls;
 
 const MAX_HEADER_SIZE: usize = 16_384;
 const MAX_BODY_SIZE: usize = 65_536;
+/// To finish sending a request once started, or for a backed up
+/// response to make progress
 const TIMEOUT: Duration = Duration::from_secs(5);
-const BACKOFF: Duration = Duration::from_millis(10);
+/// Between requests on an idle connection
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often deadlines are checked
+const TICK: Duration = Duration::from_secs(1);
+const EVENTS: usize = 1024;
+const LISTENER: Token = Token(0);
+const REDIRECT_LISTENER: Token = Token(1);
 const HEADER_END: &[u8] = b"\r\n\r\n";
 const UPDATE_PATH: &str = "/_update";
 
 pub fn run(args: WebArgs, num_threads: usize) -> Result<()> {
-    let addr = SocketAddr::new(args.address, args.port);
-    let listener = TcpListener::bind(addr)?;
+    log::init(&args)?;
+    if let Some(port) = args.metrics_port {
+        metrics::serve(SocketAddr::new(args.metrics_address, port))?;
+    }
 
-    println!("Hosting @ {addr} with {num_threads} threads");
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::config(cert, key)?),
+        _ => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
 
-    let listener = Arc::new(listener);
+    let addr = SocketAddr::new(args.address, args.port);
+    let redirect = args
+        .http_redirect_port
+        .map(|port| bind(SocketAddr::new(args.address, port)))
+        .transpose()?;
+    let server = Arc::new(Server {
+        listener: bind(addr)?,
+        redirect,
+        tls,
+        port: args.port,
+        max_connections: args.max_connections,
+    });
 
-    for _ in 0..num_threads - 1 {
-        let listener = listener.clone();
-        thread::spawn(move || accept_loop(&listener));
+    println!("Hosting @ {scheme}://{addr} with {num_threads} threads");
+    if let Some(port) = args.http_redirect_port {
+        println!("Redirecting http://{}:{port} to HTTPS", args.address);
     }
 
-    accept_loop(&listener);
+    for _ in 0..num_threads.saturating_sub(1) {
+        let server = server.clone();
+        thread::spawn(move || {
+            if let Err(e) = Worker::new(&server).and_then(Worker::run) {
+                eprintln!("Worker failed: {e}");
+            }
+        });
+    }
+
+    Worker::new(&server)?.run()
 }
 
-fn accept_loop(listener: &TcpListener) -> ! {
-    loop {
-        match listener.accept() {
-            Ok((mut stream, _)) => {
-                if let Err(e) = cfg_stream(&mut stream) {
-                    eprintln!("Error configuring stream: {e}");
-                }
+fn bind(addr: SocketAddr) -> Result<std::net::TcpListener> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
 
-                if let Err(e) = handle(&mut stream) {
-                    eprintln!("Error handling stream: {e}");
-                }
-            }
-            Err(e) => {
-                eprintln!("accept error: {e}");
-                thread::sleep(BACKOFF);
-            }
+/// Listeners and settings every worker shares
+struct Server {
+    listener: std::net::TcpListener,
+    /// Plain HTTP redirecting to HTTPS
+    redirect: Option<std::net::TcpListener>,
+    tls: Option<Arc<ServerConfig>>,
+    port: u16,
+    max_connections: usize,
+}
+
+/// What a connection is served
+#[derive(Clone, Copy)]
+enum Service {
+    Site,
+    /// Redirects everything to HTTPS on this port
+    HttpsRedirect(u16),
+}
+
+/// A connection's socket, possibly behind TLS
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write_vectored(bufs),
+            Stream::Tls(stream) => stream.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
         }
     }
 }
 
-fn cfg_stream(stream: &mut TcpStream) -> io::Result<()> {
-    stream.set_read_timeout(Some(TIMEOUT))?;
-    stream.set_write_timeout(Some(TIMEOUT))?;
-    stream.set_nodelay(true)
+/// Event loop for the connections one thread accepts, all
+/// threads accepting from the same listeners
+struct Worker {
+    poll: Poll,
+    listener: TcpListener,
+    redirect: Option<TcpListener>,
+    tls: Option<Arc<ServerConfig>>,
+    port: u16,
+    max_connections: usize,
+    conns: FxHashMap<Token, Conn<Stream>>,
+    next_token: usize,
 }
 
-fn handle<S: Read + Write>(mut stream: S) -> io::Result<()> {
-    let mut buf = [0u8; MAX_HEADER_SIZE];
-    let mut filled = 0;
+impl Worker {
+    fn new(server: &Server) -> Result<Self> {
+        let poll = Poll::new()?;
+
+        let mut listener = TcpListener::from_std(server.listener.try_clone()?);
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+
+        let mut redirect = match &server.redirect {
+            Some(redirect) => Some(TcpListener::from_std(redirect.try_clone()?)),
+            None => None,
+        };
+        if let Some(redirect) = &mut redirect {
+            poll.registry()
+                .register(redirect, REDIRECT_LISTENER, Interest::READABLE)?;
+        }
+
+        Ok(Worker {
+            poll,
+            listener,
+            redirect,
+            tls: server.tls.clone(),
+            port: server.port,
+            max_connections: server.max_connections,
+            conns: FxHashMap::default(),
+            next_token: REDIRECT_LISTENER.0,
+        })
+    }
+
+    fn run(mut self) -> Result<()> {
+        let mut events = Events::with_capacity(EVENTS);
+        let mut last_tick = Instant::now();
+        // accepting failed (ex. out of fds), retried every tick
+        let mut backlogged = false;
 
-    loop {
         loop {
-            if memmem::find(&buf[..filled], HEADER_END).is_some() {
-                break;
+            if let Err(e) = self.poll.poll(&mut events, Some(TICK))
+                && e.kind() != io::ErrorKind::Interrupted
+            {
+                return Err(e.into());
             }
 
-            if filled >= MAX_HEADER_SIZE {
-                stream.write_all(BAD_REQUEST)?;
-                return Ok(());
+            for event in &events {
+                let token = event.token();
+                if token == LISTENER || token == REDIRECT_LISTENER {
+                    backlogged |= !self.accept(token);
+                    continue;
+                }
+
+                let Some(conn) = self.conns.get_mut(&token) else {
+                    continue;
+                };
+                match conn.ready() {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.conns.remove(&token);
+                    }
+                    Err(e) => {
+                        eprintln!("Error handling stream: {e}");
+                        self.conns.remove(&token);
+                    }
+                }
             }
 
-            match stream.read(&mut buf[filled..]) {
-                Ok(0) => return Ok(()),
-                Ok(n) => filled += n,
-                Err(_) => return Ok(()),
+            let now = Instant::now();
+            if now - last_tick >= TICK {
+                last_tick = now;
+                self.conns.retain(|_, conn| conn.deadline > now);
+                if backlogged {
+                    backlogged = !(self.accept(LISTENER) & self.accept(REDIRECT_LISTENER));
+                }
             }
         }
+    }
 
-        let mut headers = [EMPTY_HEADER; 32];
-        let mut req = Request::new(&mut headers);
-
-        let body_offset = match req.parse(&buf[..filled]) {
-            Ok(Status::Complete(n)) => n,
-            _ => {
-                stream.write_all(BAD_REQUEST)?;
-                return Ok(());
-            }
+    /// Accepts every connection queued on `token`'s listener. False if
+    /// accepting failed and the rest have to be retried later
+    fn accept(&mut self, token: Token) -> bool {
+        let (listener, service) = match token {
+            LISTENER => (&self.listener, Service::Site),
+            _ => match &self.redirect {
+                Some(redirect) => (redirect, Service::HttpsRedirect(self.port)),
+                None => return true,
+            },
         };
 
-        let method = req.method.unwrap_or("");
-        let path = cut_query(req.path.unwrap_or("/"));
-        let wants_close = connection_close(req.headers);
+        loop {
+            let (mut stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::ConnectionAborted
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("accept error: {e}");
+                    return false;
+                }
+            };
 
-        match method {
-            "GET" | "HEAD" => {
-                handle_get(&mut stream, path, req.headers, method == "HEAD")?;
-            }
-            "POST" if path == UPDATE_PATH => {
-                handle_webhook(&mut stream, req.headers, &buf[body_offset..filled])?;
-                return Ok(());
+            // can be exceeded by a connection per thread at once
+            if conn::count() >= self.max_connections {
+                let _ = stream.write(SERVICE_UNAVAILABLE);
+                continue;
             }
-            "POST" => {
-                stream.write_all(NOT_FOUND)?;
-                return Ok(());
-            }
-            _ => {
-                stream.write_all(route::METHOD_NOT_ALLOWED)?;
-                return Ok(());
+
+            if let Err(e) = stream.set_nodelay(true) {
+                eprintln!("Error configuring stream: {e}");
             }
-        }
 
-        if wants_close {
-            return Ok(());
-        }
+            self.next_token += 1;
+            let token = Token(self.next_token);
+            let interest = Interest::READABLE | Interest::WRITABLE;
+            if let Err(e) = self.poll.registry().register(&mut stream, token, interest) {
+                eprintln!("Error registering stream: {e}");
+                continue;
+            }
 
-        let rem = filled - body_offset;
-        if rem > 0 {
-            buf.copy_within(body_offset..filled, 0);
+            let stream = match (&self.tls, service) {
+                (Some(tls), Service::Site) => match TlsStream::new(tls.clone(), stream) {
+                    Ok(stream) => Stream::Tls(Box::new(stream)),
+                    Err(e) => {
+                        eprintln!("Error starting TLS: {e}");
+                        continue;
+                    }
+                },
+                _ => Stream::Plain(stream),
+            };
+            self.conns.insert(token, Conn::new(stream, peer, service));
         }
-        filled = rem;
     }
 }
 
-fn handle_get<S: Read + Write>(
+/// What became of the request at the start of a connection's buffer
+enum Handled {
+    /// Responded to, consuming `len` bytes
+    Done { len: usize, close: bool },
+    /// Needs more of its head or body
+    Incomplete,
+}
+
+/// Responds to the request at the start of `buf`, if it's all there
+fn handle<S: WriteShared>(
     stream: &mut S,
-    path: &str,
-    headers: &[httparse::Header],
-    head: bool,
-) -> io::Result<()> {
-    let table = ROUTING_TABLE.load();
+    buf: &[u8],
+    peer: SocketAddr,
+    service: Service,
+) -> io::Result<Handled> {
+    if memmem::find(&buf[..buf.len().min(MAX_HEADER_SIZE)], HEADER_END).is_none() {
+        if buf.len() < MAX_HEADER_SIZE {
+            return Ok(Handled::Incomplete);
+        }
+        stream.write_all(BAD_REQUEST)?;
+        return Ok(Handled::Done {
+            len: buf.len(),
+            close: true,
+        });
+    }
 
-    let Some(route) = table.get(path) else {
-        return stream.write_all(NOT_FOUND);
+    let mut headers = [EMPTY_HEADER; 32];
+    let mut req = Request::new(&mut headers);
+
+    let body_offset = match req.parse(buf) {
+        Ok(Status::Complete(n)) => n,
+        _ => {
+            stream.write_all(BAD_REQUEST)?;
+            return Ok(Handled::Done {
+                len: buf.len(),
+                close: true,
+            });
+        }
     };
 
-    let response = if etag_matches(headers, &route.etag) {
-        route.not_modified.as_ref()
-    } else if accepts_brotli(headers) {
-        route.brotli.as_ref()
-    } else {
-        route.identity.as_ref()
+    let method = req.method.unwrap_or("");
+    let target = req.path.unwrap_or("/");
+    let path = cut_query(target);
+    let is_webhook = method == "POST" && path == UPDATE_PATH;
+    let content_length = content_length(req.headers);
+
+    if is_webhook
+        && let Some(len) = content_length
+        && len <= MAX_BODY_SIZE
+        && buf.len() < body_offset + len
+    {
+        return Ok(Handled::Incomplete);
+    }
+
+    // nothing reads a GET's body, so close rather than parse it as the next request
+    let has_body = content_length.is_some_and(|len| len > 0)
+        || find_header(req.headers, "transfer-encoding").is_some();
+    let wants_close = connection_close(req.headers) || has_body;
+    let start = (log::enabled() || metrics::enabled()).then(Instant::now);
+    let table = ROUTING_TABLE.load();
+    let head = method == "HEAD";
+
+    let (reply, close) = match (service, method) {
+        (Service::HttpsRedirect(port), _) => {
+            (redirect_https(target, req.headers, port), wants_close)
+        }
+        (_, "GET" | "HEAD") => (reply(&table, target, req.headers), wants_close),
+        (_, "POST") if is_webhook => {
+            let reply = match content_length {
+                None | Some(0) => Reply::fixed(BAD_REQUEST),
+                Some(len) if len > MAX_BODY_SIZE => Reply::fixed(route::PAYLOAD_TOO_LARGE),
+                Some(len) => handle_webhook(req.headers, &buf[body_offset..body_offset + len]),
+            };
+            (reply, true)
+        }
+        (_, "POST") => (Reply::fixed(NOT_FOUND), true),
+        _ => (Reply::fixed(route::METHOD_NOT_ALLOWED), true),
     };
+    reply.write(stream, head)?;
 
-    if head && let Some(pos) = memmem::find(response, HEADER_END) {
-        return stream.write_all(&response[..pos + 4]);
+    if let Some(start) = start {
+        let sent = reply.sent(head);
+        record(&sent, peer, method, target, "HTTP/1.1", req.headers, start);
     }
 
-    stream.write_all(response)
+    Ok(Handled::Done {
+        len: body_offset,
+        close,
+    })
 }
 
-fn handle_webhook<S: Read + Write>(
-    stream: &mut S,
+/// Logs and counts a response
+fn record(
+    sent: &Sent,
+    peer: SocketAddr,
+    method: &str,
+    target: &str,
+    protocol: &str,
     headers: &[httparse::Header],
-    partial_body: &[u8],
-) -> io::Result<()> {
-    let Some(content_length) = find_header(headers, "content-length")
-        .and_then(|val| str::from_utf8(val).ok())
-        .and_then(|s| s.parse::<usize>().ok())
-    else {
-        stream.write_all(BAD_REQUEST)?;
-        return Ok(());
-    };
-
-    if content_length == 0 {
-        stream.write_all(BAD_REQUEST)?;
-        return Ok(());
+    start: Instant,
+) {
+    let latency = start.elapsed();
+    let status = sent.status;
+
+    if metrics::enabled() {
+        // only label served routes, unknown paths are unbounded
+        let route = match status {
+            200 | 206 | 304 | 416 => cut_query(target),
+            _ => "",
+        };
+        let conditional = find_header(headers, "if-none-match").is_some();
+        metrics::request(
+            route,
+            status,
+            sent.encoding,
+            sent.bytes,
+            conditional,
+            latency,
+        );
     }
 
-    if content_length > MAX_BODY_SIZE {
-        stream.write_all(route::PAYLOAD_TOO_LARGE)?;
-        return Ok(());
+    log::write(&log::Entry {
+        peer,
+        method,
+        target,
+        protocol,
+        referer: find_header(headers, "referer"),
+        user_agent: find_header(headers, "user-agent"),
+        status,
+        bytes: sent.bytes,
+        encoding: sent.encoding,
+        latency,
+    });
+}
+
+/// The response to a GET or HEAD, sent as either HTTP/1.1 or HTTP/2
+enum Reply<'a> {
+    Response(&'a Response),
+    /// Put together for this request, starting with the head
+    Chunks(Vec<Chunk<'a>>),
+}
+
+impl Reply<'_> {
+    fn fixed(response: &'static [u8]) -> Self {
+        Reply::Chunks(vec![Chunk::Bytes(response.into())])
     }
 
-    let mut body = vec![0u8; content_length];
-    let to_copy = partial_body.len().min(content_length);
-    body[..to_copy].copy_from_slice(&partial_body[..to_copy]);
+    /// Writes the reply as HTTP/1.1, without its body for HEAD requests
+    fn write<S: WriteShared>(&self, stream: &mut S, head: bool) -> io::Result<()> {
+        match self {
+            Reply::Response(response) => {
+                let body = if head { 0 } else { response.body.len() };
+                stream.write_shared(&[
+                    (&response.head, 0..response.head.len()),
+                    (&response.body, 0..body),
+                ])
+            }
+            Reply::Chunks(chunks) => {
+                write_response(stream, &chunks[0], head)?;
+                if !head {
+                    for chunk in &chunks[1..] {
+                        chunk.write(stream)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
 
-    let mut body_filled = to_copy;
-    while body_filled < content_length {
-        match stream.read(&mut body[body_filled..]) {
-            Ok(0) => {
-                stream.write_all(BAD_REQUEST)?;
-                return Ok(());
+    /// Status, encoding and body size, for the access log and metrics
+    fn sent(&self, head: bool) -> Sent<'_> {
+        let (response_head, body) = match self {
+            Reply::Response(response) => (&response.head[..], response.body.len()),
+            Reply::Chunks(chunks) => {
+                let first = &chunks[0];
+                let end = memmem::find(first, HEADER_END).map_or(first.len(), |pos| pos + 4);
+                let rest = chunks[1..].iter().map(|chunk| chunk.len()).sum::<usize>();
+                (&first[..end], first.len() - end + rest)
             }
-            Ok(n) => body_filled += n,
-            Err(_) => return Ok(()),
+        };
+        Sent::new(response_head, if head { 0 } else { body })
+    }
+}
+
+/// Part of a `Reply::Chunks`
+enum Chunk<'a> {
+    Bytes(Cow<'a, [u8]>),
+    /// `range` of a pre-serialized response (ex. a `Range` request's body),
+    /// queued without copying if the socket backs up
+    Shared(&'a Arc<[u8]>, std::ops::Range<usize>),
+}
+
+impl Chunk<'_> {
+    fn write<S: WriteShared>(&self, stream: &mut S) -> io::Result<()> {
+        match self {
+            Chunk::Bytes(bytes) => stream.write_all(bytes),
+            Chunk::Shared(data, range) => stream.write_shared(&[(data, range.clone())]),
+        }
+    }
+}
+
+impl Deref for Chunk<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Chunk::Bytes(bytes) => bytes,
+            Chunk::Shared(data, range) => &data[range.clone()],
+        }
+    }
+}
+
+fn reply<'a>(table: &'a RoutingTable, target: &str, headers: &[httparse::Header]) -> Reply<'a> {
+    let path = cut_query(target);
+
+    let route = match path::lookup(table, path) {
+        Lookup::Found(route) => route,
+        Lookup::Redirect(url) => {
+            let query = &target[path.len()..];
+            let response = format!(
+                "HTTP/1.1 308 Permanent Redirect\r\nLocation: {url}{query}\r\nContent-Length: 0\r\n\r\n"
+            );
+            return Reply::Chunks(vec![Chunk::Bytes(response.into_bytes().into())]);
         }
+        Lookup::NotFound => return not_found(table, headers),
+        Lookup::Invalid => return Reply::fixed(BAD_REQUEST),
+    };
+
+    if !etag_matches(headers, &route.etag)
+        && let Some(range) = find_header(headers, "range")
+        && if_range_matches(headers, &route.etag)
+        && let Some(chunks) = range::respond(&route.identity, range)
+    {
+        return Reply::Chunks(chunks);
+    }
+
+    if etag_matches(headers, &route.etag) {
+        Reply::Response(&route.not_modified)
+    } else {
+        Reply::Response(negotiate(route, headers))
+    }
+}
+
+/// 301 to the same url over HTTPS on `port`
+fn redirect_https(target: &str, headers: &[httparse::Header], port: u16) -> Reply<'static> {
+    let host = find_header(headers, "host")
+        .and_then(|host| str::from_utf8(host).ok())
+        .map(strip_port)
+        .filter(|host| {
+            !host.is_empty()
+                && host
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b".-:[]".contains(&b))
+        });
+    let Some(host) = host else {
+        return Reply::fixed(BAD_REQUEST);
+    };
+
+    let port = match port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let response = format!(
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: https://{host}{port}{target}\r\nContent-Length: 0\r\n\r\n"
+    );
+    Reply::Chunks(vec![Chunk::Bytes(response.into_bytes().into())])
+}
+
+/// `example.com:80` → `example.com`, `[::1]:80` → `[::1]`
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+/// `404.typ`'s page, or an empty response if there isn't one
+fn not_found<'a>(table: &'a RoutingTable, headers: &[httparse::Header]) -> Reply<'a> {
+    match table.get(NOT_FOUND_PAGE) {
+        Some(page) => Reply::Response(negotiate(page, headers)),
+        None => Reply::fixed(NOT_FOUND),
+    }
+}
+
+/// Writes `response`, without its body for HEAD requests
+fn write_response<S: Write>(stream: &mut S, response: &[u8], head: bool) -> io::Result<()> {
+    if head && let Some(pos) = memmem::find(response, HEADER_END) {
+        return stream.write_all(&response[..pos + 4]);
     }
 
+    stream.write_all(response)
+}
+
+fn handle_webhook(headers: &[httparse::Header], body: &[u8]) -> Reply<'static> {
     let Some(sig) = find_header(headers, update::GH_HEADER) else {
-        stream.write_all(BAD_REQUEST)?;
-        return Ok(());
+        return Reply::fixed(BAD_REQUEST);
     };
 
     if update::SECRET.get().is_none() {
-        stream.write_all(NOT_FOUND)?;
-        return Ok(());
+        return Reply::fixed(NOT_FOUND);
     }
 
     if !update::verify(sig, body) {
-        stream.write_all(UNAUTHORIZED)?;
-        return Ok(());
+        return Reply::fixed(UNAUTHORIZED);
     }
 
-    stream.write_all(OK)?;
-    stream.flush()?;
-
     thread::spawn(|| {
         if let Err(e) = update::run() {
             eprintln!("Update failed: {e}");
         }
     });
 
-    Ok(())
+    Reply::fixed(OK)
 }
 
-fn accepts_brotli(headers: &[httparse::Header]) -> bool {
-    find_header(headers, "accept-encoding").is_some_and(|v| v.windows(2).any(|w| w == b"br"))
+/// The smallest response in an encoding the client accepts, identity if
+/// `Accept-Encoding` is missing or nothing is acceptable
+fn negotiate<'a>(route: &'a Route, headers: &[httparse::Header]) -> &'a Response {
+    let Some(accept) = find_header(headers, "accept-encoding") else {
+        return &route.identity;
+    };
+    Encoding::ALL
+        .into_iter()
+        .filter(|encoding| accepts(accept, encoding.token()))
+        .map(|encoding| route.response(encoding))
+        .min_by_key(|response| response.len())
+        .unwrap_or(&route.identity)
+}
+
+/// If `coding` has a non-zero q-value in `Accept-Encoding`, either listed
+/// or through `*`. identity is acceptable unless excluded
+fn accepts(accept: &[u8], coding: &str) -> bool {
+    let mut listed = None;
+    let mut star = None;
+
+    for item in accept.split(|b| *b == b',') {
+        let Ok(item) = str::from_utf8(item) else {
+            continue;
+        };
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or("");
+        let q = params
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.), |(_, v)| v.trim().parse::<f32>().ok());
+        let Some(q) = q else {
+            continue;
+        };
+
+        if name.eq_ignore_ascii_case(coding)
+            || (coding == "gzip" && name.eq_ignore_ascii_case("x-gzip"))
+        {
+            listed = Some(q);
+        } else if name == "*" {
+            star = Some(q);
+        }
+    }
+
+    match listed.or(star) {
+        Some(q) => q > 0.,
+        None => coding == "identity",
+    }
 }
 
 fn find_header<'a>(headers: &[httparse::Header<'a>], name: &str) -> Option<&'a [u8]> {
@@ -231,6 +671,12 @@ fn find_header<'a>(headers: &[httparse::Header<'a>], name: &str) -> Option<&'a [
         .map(|h| h.value)
 }
 
+fn content_length(headers: &[httparse::Header]) -> Option<usize> {
+    find_header(headers, "content-length")
+        .and_then(|val| str::from_utf8(val).ok())
+        .and_then(|s| s.parse().ok())
+}
+
 fn connection_close(headers: &[httparse::Header]) -> bool {
     find_header(headers, "connection").is_some_and(|v| v.eq_ignore_ascii_case(b"close"))
 }
@@ -240,6 +686,11 @@ fn etag_matches(headers: &[httparse::Header], etag: &[u8]) -> bool {
         .is_some_and(|v| v == etag || v.windows(etag.len()).any(|w| w == etag))
 }
 
+/// A `Range` only applies if `If-Range` is absent or is the current etag
+fn if_range_matches(headers: &[httparse::Header], etag: &[u8]) -> bool {
+    find_header(headers, "if-range").is_none_or(|v| v.trim_ascii() == etag)
+}
+
 fn cut_query(path: &str) -> &str {
     match memchr::memchr(b'?', path.as_bytes()) {
         Some(index) => &path[0..index],
@@ -251,7 +702,9 @@ fn cut_query(path: &str) -> &str {
 mod tests {
     use std::io::Cursor;
 
-    use crate::{RoutingTable, web::route::Route};
+    use crate::web::route::Route;
+    use mime_guess::mime;
+    use typst::syntax::{FileId, VirtualPath};
 
     use super::*;
 
@@ -302,25 +755,52 @@ mod tests {
     }
 
     #[test]
-    fn test_accepts_brotli() {
-        let yes = [httparse::Header {
-            name: "Accept-Encoding",
-            value: b"gzip, br",
-        }];
-        let no = [httparse::Header {
-            name: "Accept-Encoding",
-            value: b"gzip, deflate",
-        }];
-        let only = [httparse::Header {
-            name: "Accept-Encoding",
-            value: b"br",
-        }];
-        let empty: [httparse::Header; 0] = [];
+    fn test_accepts() {
+        assert!(accepts(b"gzip, br", "br"));
+        assert!(accepts(b"gzip, br", "gzip"));
+        assert!(!accepts(b"gzip, br", "zstd"));
+        assert!(accepts(b"gzip, br", "identity"));
+        assert!(accepts(b"x-gzip", "gzip"));
+
+        // q-values
+        assert!(!accepts(b"br;q=0, gzip", "br"));
+        assert!(!accepts(b"br; q=0.000", "br"));
+        assert!(accepts(b"br;q=0.5", "br"));
+        assert!(!accepts(b"gzip;q=0.5, identity;q=0", "identity"));
+
+        // wildcard
+        assert!(accepts(b"*", "zstd"));
+        assert!(accepts(b"*;q=0.1", "br"));
+        assert!(!accepts(b"*;q=0", "identity"));
+        assert!(accepts(b"*;q=0, identity", "identity"));
+        assert!(!accepts(b"gzip;q=0, *", "gzip"));
+
+        // brotli isn't a substring match
+        assert!(!accepts(b"brotli-ish", "br"));
+        assert!(!accepts(b"", "br"));
+    }
 
-        assert!(accepts_brotli(&yes));
-        assert!(!accepts_brotli(&no));
-        assert!(accepts_brotli(&only));
-        assert!(!accepts_brotli(&empty));
+    #[test]
+    fn test_negotiate() {
+        mock_routing_table();
+        let table = ROUTING_TABLE.load();
+        let route = &table["/test"];
+        let negotiate = |accept: &'static [u8]| {
+            let headers = [httparse::Header {
+                name: "Accept-Encoding",
+                value: accept,
+            }];
+            negotiate(route, &headers)
+        };
+
+        // smallest is zstd, then brotli, then gzip
+        assert_eq!(negotiate(b"gzip, br, zstd"), &route.zstd);
+        assert_eq!(negotiate(b"gzip, br"), &route.brotli);
+        assert_eq!(negotiate(b"gzip, br;q=0"), &route.gzip);
+        assert_eq!(negotiate(b"*"), &route.zstd);
+        assert_eq!(negotiate(b"deflate"), &route.identity);
+        assert_eq!(negotiate(b"identity;q=0"), &route.identity);
+        assert_eq!(super::negotiate(route, &[]), &route.identity);
     }
 
     #[test]
@@ -369,6 +849,9 @@ mod tests {
         assert!(!etag_matches(&empty, etag));
     }
 
+    const PEER: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 1234);
+
     struct MockStream {
         input: Cursor<Vec<u8>>,
         output: Vec<u8>,
@@ -403,22 +886,35 @@ mod tests {
         }
     }
 
-    fn mock_routing_table() {
+    /// Runs a connection over `stream` until it closes
+    fn serve(stream: &mut MockStream) {
+        assert!(!Conn::new(stream, PEER, Service::Site).ready().unwrap());
+    }
+
+    fn mock_response(encoding: Option<&str>, body: &str) -> Response {
+        let header = match encoding {
+            Some(encoding) => format!("Content-Encoding: {encoding}"),
+            None => "Accept-Ranges: bytes".into(),
+        };
+        let response = format!("HTTP/1.1 200 OK\r\n{header}\r\nETag: \"t1\"\r\n\r\n{body}");
+        Response::new(response.into_bytes()).unwrap()
+    }
+
+    pub(super) fn mock_routing_table() {
         let mut table = RoutingTable::default();
         table.insert(
             "/test".into(),
             Route {
-                identity: b"HTTP/1.1 200 OK\r\nETag: \"t1\"\r\n\r\nidentity-body"
-                    .to_vec()
-                    .into_boxed_slice(),
-                brotli:
-                    b"HTTP/1.1 200 OK\r\nContent-Encoding: br\r\nETag: \"t1\"\r\n\r\nbrotli-body"
-                        .to_vec()
-                        .into_boxed_slice(),
-                not_modified: b"HTTP/1.1 304 Not Modified\r\nETag: \"t1\"\r\n\r\n"
-                    .to_vec()
-                    .into_boxed_slice(),
+                identity: mock_response(None, "identity-body"),
+                brotli: mock_response(Some("br"), "br-body"),
+                zstd: mock_response(Some("zstd"), "zstd"),
+                gzip: mock_response(Some("gzip"), "gzip-body"),
+                not_modified: Response::new(
+                    b"HTTP/1.1 304 Not Modified\r\nETag: \"t1\"\r\n\r\n".to_vec(),
+                )
+                .unwrap(),
                 etag: b"\"t1\"".to_vec().into_boxed_slice(),
+                hash: 0,
             },
         );
         ROUTING_TABLE.store(Arc::new(table));
@@ -429,7 +925,7 @@ mod tests {
         mock_routing_table();
         let mut stream = MockStream::new(b"GET /test HTTP/1.1\r\nConnection: close\r\n\r\n");
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert!(stream.output().starts_with(b"HTTP/1.1 200 OK"));
         assert!(stream.output().windows(13).any(|w| w == b"identity-body"));
@@ -442,9 +938,9 @@ mod tests {
             b"GET /test HTTP/1.1\r\nAccept-Encoding: gzip, br\r\nConnection: close\r\n\r\n",
         );
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
-        assert!(stream.output().windows(11).any(|w| w == b"brotli-body"));
+        assert!(stream.output().ends_with(b"br-body"));
     }
 
     #[test]
@@ -452,7 +948,7 @@ mod tests {
         mock_routing_table();
         let mut stream = MockStream::new(b"GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n");
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert_eq!(stream.output(), NOT_FOUND);
     }
@@ -462,7 +958,7 @@ mod tests {
         mock_routing_table();
         let mut stream = MockStream::new(b"HEAD /test HTTP/1.1\r\nConnection: close\r\n\r\n");
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         let out = stream.output();
         assert!(out.starts_with(b"HTTP/1.1 200 OK"));
@@ -477,11 +973,149 @@ mod tests {
             b"GET /test HTTP/1.1\r\nIf-None-Match: \"t1\"\r\nConnection: close\r\n\r\n",
         );
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert!(stream.output().starts_with(b"HTTP/1.1 304"));
     }
 
+    fn get(request: &[u8]) -> Vec<u8> {
+        mock_routing_table();
+        let mut stream = MockStream::new(request);
+        serve(&mut stream);
+        stream.output
+    }
+
+    #[test]
+    fn get_range() {
+        let out = get(b"GET /test HTTP/1.1\r\nRange: bytes=0-7\r\nConnection: close\r\n\r\n");
+        let out = str::from_utf8(&out).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(out.contains("Content-Range: bytes 0-7/13\r\n"));
+        assert!(out.contains("Content-Length: 8\r\n"));
+        assert!(out.contains("ETag: \"t1\"\r\n"));
+        assert!(out.ends_with("\r\n\r\nidentity"));
+    }
+
+    #[test]
+    fn get_range_ignores_brotli() {
+        let out = get(
+            b"GET /test HTTP/1.1\r\nAccept-Encoding: br\r\nRange: bytes=-4\r\nConnection: close\r\n\r\n",
+        );
+
+        assert!(out.starts_with(b"HTTP/1.1 206"));
+        assert!(out.ends_with(b"\r\n\r\nbody"));
+        assert!(!out.windows(16).any(|w| w == b"Content-Encoding"));
+    }
+
+    #[test]
+    fn get_multi_range() {
+        let out = get(b"GET /test HTTP/1.1\r\nRange: bytes=0-1, 9-\r\nConnection: close\r\n\r\n");
+        let out = str::from_utf8(&out).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        let boundary = head
+            .split("boundary=")
+            .nth(1)
+            .and_then(|s| s.lines().next())
+            .unwrap();
+        let length = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .unwrap();
+        assert_eq!(length.parse::<usize>().unwrap(), body.len());
+        assert!(body.contains("Content-Range: bytes 0-1/13\r\n\r\nid\r\n"));
+        assert!(body.contains("Content-Range: bytes 9-12/13\r\n\r\nbody\r\n"));
+        assert!(body.ends_with(&format!("--{boundary}--\r\n")));
+    }
+
+    #[test]
+    fn get_range_not_satisfiable() {
+        let out = get(b"GET /test HTTP/1.1\r\nRange: bytes=13-\r\nConnection: close\r\n\r\n");
+        let out = str::from_utf8(&out).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(out.contains("Content-Range: bytes */13\r\n"));
+    }
+
+    #[test]
+    fn get_range_if_range() {
+        let hit = get(
+            b"GET /test HTTP/1.1\r\nRange: bytes=0-1\r\nIf-Range: \"t1\"\r\nConnection: close\r\n\r\n",
+        );
+        assert!(hit.starts_with(b"HTTP/1.1 206"));
+
+        // changed since, send everything
+        let miss = get(
+            b"GET /test HTTP/1.1\r\nRange: bytes=0-1\r\nIf-Range: \"t0\"\r\nConnection: close\r\n\r\n",
+        );
+        assert!(miss.starts_with(b"HTTP/1.1 200"));
+        assert!(miss.ends_with(b"identity-body"));
+    }
+
+    #[test]
+    fn get_range_invalid() {
+        let out = get(b"GET /test HTTP/1.1\r\nRange: bytes=5-1\r\nConnection: close\r\n\r\n");
+
+        assert!(out.starts_with(b"HTTP/1.1 200"));
+        assert!(out.ends_with(b"identity-body"));
+    }
+
+    #[test]
+    fn head_range() {
+        let out = get(b"HEAD /test HTTP/1.1\r\nRange: bytes=0-7\r\nConnection: close\r\n\r\n");
+
+        assert!(out.starts_with(b"HTTP/1.1 206"));
+        assert!(out.ends_with(HEADER_END));
+    }
+
+    #[test]
+    fn test_not_found() {
+        let headers = [httparse::Header {
+            name: "Accept-Encoding",
+            value: b"br",
+        }];
+        let mut table = RoutingTable::default();
+        assert!(matches!(not_found(&table, &headers), Reply::Chunks(c) if *c[0] == *NOT_FOUND));
+
+        let id = FileId::new_fake(VirtualPath::new("404.typ"));
+        let page = Route::compile_status(
+            &id,
+            "<h1>Not Found</h1>".repeat(20).into(),
+            &mime::TEXT_HTML,
+            "404 Not Found",
+            false,
+        )
+        .unwrap();
+        table.insert(NOT_FOUND_PAGE.into(), page.clone());
+        assert!(matches!(not_found(&table, &headers), Reply::Response(r) if *r == page.brotli));
+        assert!(matches!(not_found(&table, &[]), Reply::Response(r) if *r == page.identity));
+        assert!(
+            page.identity
+                .head
+                .starts_with(b"HTTP/1.1 404 Not Found\r\n")
+        );
+        assert!(!page.identity.head.windows(5).any(|w| w == b"close"));
+    }
+
+    #[test]
+    fn get_non_canonical() {
+        let out = get(b"GET /test/?a=1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let out = str::from_utf8(&out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+        assert!(out.contains("Location: /test?a=1\r\n"));
+
+        let out = get(b"GET /%74est HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(out.starts_with(b"HTTP/1.1 308"));
+    }
+
+    #[test]
+    fn get_traversal() {
+        let out = get(b"GET /../../etc/passwd HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(out, BAD_REQUEST);
+    }
+
     #[test]
     fn conn_close() {
         mock_routing_table();
@@ -490,7 +1124,7 @@ mod tests {
               GET /test HTTP/1.1\r\nConnection: close\r\n\r\n",
         );
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         let count = stream
             .output()
@@ -500,12 +1134,56 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn get_body_closes() {
+        mock_routing_table();
+        for body in [
+            &b"Content-Length: 24\r\n\r\n"[..],
+            b"Transfer-Encoding: chunked\r\n\r\n18\r\n",
+        ] {
+            let mut request = b"GET /test HTTP/1.1\r\n".to_vec();
+            request.extend_from_slice(body);
+            request.extend_from_slice(b"GET /test HTTP/1.1\r\n\r\n");
+            let mut stream = MockStream::new(&request);
+
+            serve(&mut stream);
+
+            let count = stream
+                .output()
+                .windows(8)
+                .filter(|w| *w == b"HTTP/1.1")
+                .count();
+            assert_eq!(count, 1);
+        }
+    }
+
+    #[test]
+    fn reply_sent() {
+        let response = mock_response(Some("br"), "br-body");
+        let reply = Reply::Response(&response);
+        let sent = reply.sent(false);
+        assert_eq!(
+            (sent.status, sent.encoding, sent.bytes),
+            (200, Some("br"), 7)
+        );
+        assert_eq!(reply.sent(true).bytes, 0);
+
+        let data: Arc<[u8]> = b"0123456789"[..].into();
+        let reply = Reply::Chunks(vec![
+            Chunk::Bytes(b"HTTP/1.1 206 Partial Content\r\n\r\n--a\r\n"[..].into()),
+            Chunk::Shared(&data, 2..6),
+        ]);
+        let sent = reply.sent(false);
+        assert_eq!((sent.status, sent.encoding, sent.bytes), (206, None, 9));
+        assert_eq!(Reply::fixed(NOT_FOUND).sent(false).status, 404);
+    }
+
     #[test]
     fn invalid_post() {
         mock_routing_table();
         let mut stream = MockStream::new(b"POST /test HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert_eq!(stream.output(), NOT_FOUND);
     }
@@ -515,7 +1193,7 @@ mod tests {
         mock_routing_table();
         let mut stream = MockStream::new(b"DELETE /test HTTP/1.1\r\nConnection: close\r\n\r\n");
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert_eq!(stream.output(), route::METHOD_NOT_ALLOWED);
     }
@@ -526,7 +1204,7 @@ mod tests {
         let garbage = vec![b'A'; MAX_HEADER_SIZE];
         let mut stream = MockStream::new(&garbage);
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert_eq!(stream.output(), BAD_REQUEST);
     }
@@ -536,11 +1214,57 @@ mod tests {
         mock_routing_table();
         let mut stream = MockStream::new(b"NOT A REAL REQUEST\r\n\r\n");
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         assert_eq!(stream.output(), BAD_REQUEST);
     }
 
+    #[test]
+    fn webhook_body() {
+        let request = b"POST /_update HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123";
+        let handled = handle(&mut Vec::new(), request, PEER, Service::Site).unwrap();
+        assert!(matches!(handled, Handled::Incomplete));
+
+        let request = b"POST /_update HTTP/1.1\r\nContent-Length: 100000\r\n\r\n0123";
+        let mut out = Vec::new();
+        let handled = handle(&mut out, request, PEER, Service::Site).unwrap();
+        assert!(matches!(handled, Handled::Done { close: true, .. }));
+        assert_eq!(out, route::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_strip_port() {
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("example.com:80"), "example.com");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+    }
+
+    #[test]
+    fn https_redirect() {
+        let redirect = |request: &[u8], port| {
+            let mut out = Vec::new();
+            handle(&mut out, request, PEER, Service::HttpsRedirect(port)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = redirect(b"GET /blog?a=1 HTTP/1.1\r\nHost: example.com\r\n\r\n", 443);
+        assert!(out.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(out.contains("Location: https://example.com/blog?a=1\r\n"));
+
+        let out = redirect(b"POST /x HTTP/1.1\r\nHost: localhost:8080\r\n\r\n", 8443);
+        assert!(out.contains("Location: https://localhost:8443/x\r\n"));
+
+        assert_eq!(
+            redirect(b"GET / HTTP/1.1\r\n\r\n", 443).as_bytes(),
+            BAD_REQUEST
+        );
+        assert_eq!(
+            redirect(b"GET / HTTP/1.1\r\nHost: a.com/@evil\r\n\r\n", 443).as_bytes(),
+            BAD_REQUEST
+        );
+    }
+
     #[test]
     fn keepalive() {
         mock_routing_table();
@@ -549,7 +1273,7 @@ mod tests {
               GET /test HTTP/1.1\r\nConnection: close\r\n\r\n",
         );
 
-        handle(&mut stream).unwrap();
+        serve(&mut stream);
 
         let count = stream
             .output()
@@ -0,0 +1,173 @@
+//! Resolving request paths which aren't routes as-is (`/blog/`,
+//! `/blog/index.html`, `//blog`, `/projects/liamsnow%5Fcom`) to their
+//! canonical url, as made by `indexer::make_url`
+
+use crate::RoutingTable;
+use crate::indexer::make_url;
+use crate::web::route::Route;
+
+pub enum Lookup<'a> {
+    Found(&'a Route),
+    /// Percent-encoded canonical url
+    Redirect(String),
+    NotFound,
+    /// Not decodable or escapes the root with `..`
+    Invalid,
+}
+
+pub fn lookup<'a>(table: &'a RoutingTable, path: &str) -> Lookup<'a> {
+    if let Some(route) = table.get(path) {
+        return Lookup::Found(route);
+    }
+
+    let Some(rel) = normalize(path) else {
+        return Lookup::Invalid;
+    };
+
+    let canonical = [Some(make_url(&rel)), page_url(&rel)]
+        .into_iter()
+        .flatten()
+        .find_map(|url| table.get(&url).map(|route| (url, route)));
+
+    match canonical {
+        // only differs by required encoding (ex. `%20`)
+        Some((url, route)) if encode(&url) == path => Lookup::Found(route),
+        Some((url, _)) => Lookup::Redirect(encode(&url)),
+        None => Lookup::NotFound,
+    }
+}
+
+/// Percent-decodes `path`, dropping empty and `.` segments.
+/// None if it isn't UTF-8 or has a `..` segment
+fn normalize(path: &str) -> Option<String> {
+    let decoded = decode(path)?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// The url of the Typst page `rel` could be the output of
+/// (`blog/index.html` → `/blog`, `blog/post.html` → `/blog/post`)
+fn page_url(rel: &str) -> Option<String> {
+    let stem = match rel.strip_suffix(".html") {
+        Some(stem) => stem,
+        None if rel == "index" || rel.ends_with("/index") => rel,
+        None => return None,
+    };
+    Some(make_url(&format!("{stem}.typ")))
+}
+
+fn decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit));
+        match (bytes[i], hex) {
+            (b'%', Some(hex)) => {
+                // both ascii hex digits
+                let hex = str::from_utf8(hex).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Percent-encodes everything but unreserved characters, sub-delims, `:`, `@` and `/`
+fn encode(url: &str) -> String {
+    let mut out = String::with_capacity(url.len());
+    for byte in url.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => out.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'@' => out.push(byte as char),
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(urls: &[&str]) -> RoutingTable {
+        urls.iter()
+            .map(|url| (url.to_string(), Route::empty("200 OK", None).unwrap()))
+            .collect()
+    }
+
+    fn redirect(table: &RoutingTable, path: &str) -> Option<String> {
+        match lookup(table, path) {
+            Lookup::Redirect(url) => Some(url),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/").as_deref(), Some(""));
+        assert_eq!(normalize("//blog//post/").as_deref(), Some("blog/post"));
+        assert_eq!(normalize("/./blog/.").as_deref(), Some("blog"));
+        assert_eq!(normalize("/a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(normalize("/100%").as_deref(), Some("100%"));
+        assert_eq!(normalize("/blog/../etc"), None);
+        assert_eq!(normalize("/%2e%2e/etc"), None);
+        assert_eq!(normalize("/%ff"), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("/projects/liamsnow_com"), "/projects/liamsnow_com");
+        assert_eq!(encode("/a b/ü"), "/a%20b/%C3%BC");
+        assert_eq!(encode("/100%"), "/100%25");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let table = table(&["/", "/blog", "/blog/post", "/demo.html", "/a b.png"]);
+
+        assert!(matches!(lookup(&table, "/blog"), Lookup::Found(_)));
+        assert!(matches!(lookup(&table, "/a%20b.png"), Lookup::Found(_)));
+        assert!(matches!(lookup(&table, "/nope/"), Lookup::NotFound));
+        assert!(matches!(lookup(&table, "/../etc/passwd"), Lookup::Invalid));
+
+        assert_eq!(redirect(&table, "/blog/").as_deref(), Some("/blog"));
+        assert_eq!(redirect(&table, "//blog").as_deref(), Some("/blog"));
+        assert_eq!(
+            redirect(&table, "/blog/index.html").as_deref(),
+            Some("/blog")
+        );
+        assert_eq!(redirect(&table, "/blog/index").as_deref(), Some("/blog"));
+        assert_eq!(
+            redirect(&table, "/blog/post.html").as_deref(),
+            Some("/blog/post")
+        );
+        assert_eq!(redirect(&table, "/index.html").as_deref(), Some("/"));
+        assert_eq!(redirect(&table, "/%62log").as_deref(), Some("/blog"));
+        assert_eq!(
+            redirect(&table, "/demo.html/").as_deref(),
+            Some("/demo.html")
+        );
+        assert_eq!(
+            redirect(&table, "/a%20b.png/").as_deref(),
+            Some("/a%20b.png")
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! `Range` requests (206 Partial Content), served from the identity response
+
+use crate::web::Chunk;
+use crate::web::route::Response;
+use httparse::{EMPTY_HEADER, Status};
+use std::io::Write;
+
+/// More ranges than this and we just send everything
+const MAX_RANGES: usize = 16;
+const BOUNDARY: &str = "8b1f3c5e2d7a9046";
+
+#[derive(Debug, PartialEq)]
+enum Ranges {
+    /// Inclusive, sorted and coalesced
+    Satisfiable(Vec<(usize, usize)>),
+    Unsatisfiable,
+}
+
+/// The response to `range` (a `Range` header) as chunks to write, the first
+/// being the head. `None` if the full `identity` response should be sent
+pub fn respond<'a>(identity: &'a Response, range: &[u8]) -> Option<Vec<Chunk<'a>>> {
+    let mut headers = [EMPTY_HEADER; 16];
+    let mut resp = httparse::Response::new(&mut headers);
+    let Ok(Status::Complete(_)) = resp.parse(&identity.head) else {
+        return None;
+    };
+    if resp.code != Some(200) {
+        return None;
+    }
+    let len = identity.body.len();
+
+    let ranges = match parse(range, len)? {
+        Ranges::Satisfiable(ranges) => ranges,
+        Ranges::Unsatisfiable => {
+            let head = format!(
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{len}\r\nContent-Length: 0\r\n\r\n"
+            );
+            return Some(vec![Chunk::Bytes(head.into_bytes().into())]);
+        }
+    };
+
+    let content_type = resp
+        .headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("content-type"))
+        .map(|h| h.value);
+
+    let mut head = Vec::with_capacity(256);
+    head.extend_from_slice(b"HTTP/1.1 206 Partial Content\r\n");
+    for h in resp.headers.iter() {
+        let skip = h.name.eq_ignore_ascii_case("content-length")
+            || (ranges.len() > 1 && h.name.eq_ignore_ascii_case("content-type"));
+        if !skip {
+            head.extend_from_slice(h.name.as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(h.value);
+            head.extend_from_slice(b"\r\n");
+        }
+    }
+
+    if let [(start, end)] = ranges[..] {
+        let _ = write!(head, "Content-Range: bytes {start}-{end}/{len}\r\n");
+        let _ = write!(head, "Content-Length: {}\r\n\r\n", end - start + 1);
+        let body = Chunk::Shared(&identity.body, start..end + 1);
+        return Some(vec![Chunk::Bytes(head.into()), body]);
+    }
+
+    let mut chunks = vec![Chunk::Bytes((&[][..]).into())];
+    let mut content_length = 0;
+    for (start, end) in ranges {
+        let mut part = format!("\r\n--{BOUNDARY}\r\n").into_bytes();
+        if let Some(ct) = content_type {
+            part.extend_from_slice(b"Content-Type: ");
+            part.extend_from_slice(ct);
+            part.extend_from_slice(b"\r\n");
+        }
+        let _ = write!(part, "Content-Range: bytes {start}-{end}/{len}\r\n\r\n");
+        content_length += part.len() + end - start + 1;
+        chunks.push(Chunk::Bytes(part.into()));
+        chunks.push(Chunk::Shared(&identity.body, start..end + 1));
+    }
+    let close = format!("\r\n--{BOUNDARY}--\r\n");
+    content_length += close.len();
+    chunks.push(Chunk::Bytes(close.into_bytes().into()));
+
+    let _ = write!(
+        head,
+        "Content-Type: multipart/byteranges; boundary={BOUNDARY}\r\n"
+    );
+    let _ = write!(head, "Content-Length: {content_length}\r\n\r\n");
+    chunks[0] = Chunk::Bytes(head.into());
+    Some(chunks)
+}
+
+/// Parses `bytes=0-99, 200-, -50` for a body of `len` bytes.
+/// `None` if the header is invalid and should be ignored
+fn parse(range: &[u8], len: usize) -> Option<Ranges> {
+    let range = str::from_utf8(range).ok()?.trim();
+    let (unit, specs) = range.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (first, last) = spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+
+        let range = match (first, last) {
+            ("", "") => return None,
+            // suffix
+            ("", n) => {
+                let n = n.parse::<usize>().ok()?;
+                (n > 0 && len > 0).then(|| (len - n.min(len), len - 1))
+            }
+            (first, last) => {
+                let first = first.parse::<usize>().ok()?;
+                let last = match last {
+                    "" => usize::MAX,
+                    last => last.parse::<usize>().ok()?,
+                };
+                if last < first {
+                    return None;
+                }
+                (first < len).then(|| (first, last.min(len - 1)))
+            }
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+    if ranges.is_empty() {
+        return Some(Ranges::Unsatisfiable);
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last)) if start <= last.saturating_add(1) => *last = (*last).max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(Ranges::Satisfiable(merged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(range: &str, len: usize) -> Option<Vec<(usize, usize)>> {
+        match parse(range.as_bytes(), len)? {
+            Ranges::Satisfiable(ranges) => Some(ranges),
+            Ranges::Unsatisfiable => Some(vec![]),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ranges("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(ranges("bytes=500-", 1000), Some(vec![(500, 999)]));
+        assert_eq!(ranges("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(ranges("bytes=-2000", 1000), Some(vec![(0, 999)]));
+        assert_eq!(ranges("bytes=900-5000", 1000), Some(vec![(900, 999)]));
+        assert_eq!(
+            ranges("Bytes = 0-0 , 10-19", 1000),
+            Some(vec![(0, 0), (10, 19)])
+        );
+    }
+
+    #[test]
+    fn test_parse_coalesce() {
+        assert_eq!(
+            ranges("bytes=50-99,0-49,200-299,250-", 1000),
+            Some(vec![(0, 99), (200, 999)])
+        );
+    }
+
+    #[test]
+    fn test_parse_unsatisfiable() {
+        assert_eq!(parse(b"bytes=1000-", 1000), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse(b"bytes=-0", 1000), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse(b"bytes=0-", 0), Some(Ranges::Unsatisfiable));
+        // the satisfiable ones are still served
+        assert_eq!(ranges("bytes=2000-,0-9", 1000), Some(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(parse(b"items=0-9", 1000), None);
+        assert_eq!(parse(b"bytes=9-0", 1000), None);
+        assert_eq!(parse(b"bytes=a-b", 1000), None);
+        assert_eq!(parse(b"bytes=-", 1000), None);
+        assert_eq!(parse(b"bytes=5", 1000), None);
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(many.as_bytes(), 1000), None);
+    }
+}
@@ -1,21 +1,69 @@
-use anyhow::Result;
+use crate::web::hpack;
+use anyhow::{Context, Result, ensure};
 use brotli::enc::backward_references::BrotliEncoderMode;
 use brotli::{BrotliCompress, enc::BrotliEncoderParams};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use mime_guess::Mime;
-use mime_guess::mime::{APPLICATION, FONT, IMAGE, SVG, TEXT};
+use mime_guess::mime::{APPLICATION, FONT, IMAGE, PDF, SVG, TEXT};
 use std::io::Write;
+use std::sync::Arc;
 use typst::syntax::FileId;
 use xxhash_rust::xxh3::xxh3_64;
 
 /// A pre-serialized response
 /// Making zero-copy dispatching since 2026 😀
+#[derive(Clone)]
 pub struct Route {
     /// brotli compress HTTP response
-    pub brotli: Box<[u8]>,
+    pub brotli: Response,
+    pub zstd: Response,
+    pub gzip: Response,
     /// uncompressed HTTP response
-    pub identity: Box<[u8]>,
-    pub not_modified: Box<[u8]>,
+    pub identity: Response,
+    pub not_modified: Response,
     pub etag: Box<[u8]>,
+    /// xxh3 of the content
+    pub hash: u64,
+}
+
+/// An HTTP/1.1 response, with its head HPACK encoded for HTTP/2
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    /// Status line and headers, through the blank line
+    pub head: Arc<[u8]>,
+    /// Shared by responses which only differ in their head, and
+    /// by HTTP/2 streams holding onto it while flow controlled
+    pub body: Arc<[u8]>,
+    pub h2: Box<[u8]>,
+}
+
+impl Response {
+    /// Splits a serialized response into its head and body
+    pub fn new(http1: Vec<u8>) -> Result<Self> {
+        let (h2, body) = hpack::encode_head(&http1).context("incomplete response head")?;
+        Ok(Response {
+            head: http1[..body].into(),
+            body: http1[body..].into(),
+            h2: h2.into(),
+        })
+    }
+
+    /// `head` (through the blank line) in front of a shared `body`
+    pub fn with_body(head: Vec<u8>, body: Arc<[u8]>) -> Result<Self> {
+        let (h2, len) = hpack::encode_head(&head).context("incomplete response head")?;
+        ensure!(len == head.len(), "response head followed by a body");
+        Ok(Response {
+            head: head.into(),
+            body,
+            h2: h2.into(),
+        })
+    }
+
+    /// Length of the whole HTTP/1.1 response
+    pub fn len(&self) -> usize {
+        self.head.len() + self.body.len()
+    }
 }
 
 macro_rules! empty_response {
@@ -35,17 +83,163 @@ pub static METHOD_NOT_ALLOWED: &[u8] = empty_response!("405 Method Not Allowed")
 pub static PAYLOAD_TOO_LARGE: &[u8] = empty_response!("413 Payload Too Large");
 pub static OK: &[u8] = empty_response!("200 OK");
 pub static UNAUTHORIZED: &[u8] = empty_response!("401 Unauthorized");
+pub static SERVICE_UNAVAILABLE: &[u8] = empty_response!("503 Service Unavailable");
+
+/// Url of the page served for unknown routes (`404.typ`)
+pub const NOT_FOUND_PAGE: &str = "/404";
+/// Url of the page served for `410 Gone` redirects (`410.typ`)
+pub const GONE_PAGE: &str = "/410";
+/// Url of the page served for routes which failed to compile (`500.typ`)
+pub const ERROR_PAGE: &str = "/500";
+
+/// Pages compiled with an error status instead of `200 OK`
+const STATUS_PAGES: [(&str, &str); 3] = [
+    (NOT_FOUND_PAGE, "404 Not Found"),
+    (GONE_PAGE, "410 Gone"),
+    (ERROR_PAGE, "500 Internal Server Error"),
+];
+
+/// The status `url` is served with if it is a status page
+pub fn status_page(url: &str) -> Option<&'static str> {
+    STATUS_PAGES
+        .iter()
+        .find(|(page, _)| *page == url)
+        .map(|(_, status)| *status)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// In order of preference when sizes are equal
+    pub const ALL: [Encoding; 4] = [
+        Encoding::Identity,
+        Encoding::Brotli,
+        Encoding::Zstd,
+        Encoding::Gzip,
+    ];
+
+    /// `Content-Encoding`/`Accept-Encoding` name
+    pub fn token(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
 
 impl Route {
+    pub fn response(&self, encoding: Encoding) -> &Response {
+        match encoding {
+            Encoding::Identity => &self.identity,
+            Encoding::Brotli => &self.brotli,
+            Encoding::Zstd => &self.zstd,
+            Encoding::Gzip => &self.gzip,
+        }
+    }
+
     pub fn compile(id: &FileId, content: Vec<u8>, mime: &Mime, fast: bool) -> Result<Self> {
-        let cache_control = cache_control(mime);
+        Self::compile_status(id, content, mime, "200 OK", fast)
+    }
+
+    /// Like `compile` but responds with `status` (ex. `500 Internal Server Error`)
+    pub fn compile_status(
+        id: &FileId,
+        content: Vec<u8>,
+        mime: &Mime,
+        status: &str,
+        fast: bool,
+    ) -> Result<Self> {
+        Self::build(id, content, mime, status, cache_control(mime), fast)
+    }
+
+    /// Like `compile` but cached forever, for urls which change with their content
+    pub fn compile_immutable(
+        id: &FileId,
+        content: Vec<u8>,
+        mime: &Mime,
+        fast: bool,
+    ) -> Result<Self> {
+        Self::build(id, content, mime, "200 OK", Some(IMMUTABLE), fast)
+    }
+
+    /// This route with `cache_control` (`None` to drop it) instead,
+    /// reusing the compressed bodies
+    pub fn with_cache_control(&self, cache_control: Option<&str>) -> Result<Self> {
+        let identity = replace_cache_control(&self.identity, cache_control)?;
+        // compressed responses which fell back to identity
+        let reuse = |response: &Response| -> Result<Response> {
+            if Arc::ptr_eq(&response.body, &self.identity.body) {
+                Ok(identity.clone())
+            } else {
+                replace_cache_control(response, cache_control)
+            }
+        };
+
+        Ok(Route {
+            brotli: reuse(&self.brotli)?,
+            zstd: reuse(&self.zstd)?,
+            gzip: reuse(&self.gzip)?,
+            identity,
+            not_modified: self.not_modified.clone(),
+            etag: self.etag.clone(),
+            hash: self.hash,
+        })
+    }
+
+    /// Like `with_cache_control` but cached forever,
+    /// for urls which change with their content
+    pub fn immutable(&self) -> Result<Self> {
+        self.with_cache_control(Some(IMMUTABLE))
+    }
+
+    /// A response without a body (ex. `301 Moved Permanently` to `location`)
+    pub fn empty(status: &str, location: Option<&str>) -> Result<Self> {
+        let mut buf = Vec::with_capacity(128);
+        write!(buf, "HTTP/1.1 {status}\r\n")?;
+        if let Some(location) = location {
+            write!(buf, "Location: {location}\r\n")?;
+        }
+        write!(buf, "Content-Length: 0\r\n\r\n")?;
+
+        let response = Response::new(buf)?;
+        let hash = xxh3_64(&response.head);
+        Ok(Route {
+            brotli: response.clone(),
+            zstd: response.clone(),
+            gzip: response.clone(),
+            // without an ETag header this never matches
+            not_modified: response.clone(),
+            identity: response,
+            etag: format!("\"{hash:016x}\"").into_bytes().into(),
+            hash,
+        })
+    }
+
+    fn build(
+        id: &FileId,
+        content: Vec<u8>,
+        mime: &Mime,
+        status: &str,
+        cache_control: Option<&str>,
+        fast: bool,
+    ) -> Result<Self> {
         let brotli_settings = brotli_settings(mime, fast);
 
         let hash = xxh3_64(&content);
         let etag = format!("\"{hash:016x}\"");
 
+        let content: Arc<[u8]> = content.into();
         let identity = serialize(
-            &content,
+            status,
+            content.clone(),
             mime.as_ref(),
             cache_control,
             brotli_settings.is_some(),
@@ -53,41 +247,52 @@ impl Route {
             &etag,
         )?;
 
-        let brotli = match brotli_settings {
-            Some(settings) => {
-                let compressed = compress_brotli(&content, settings);
-                if compressed.len() < content.len() {
-                    serialize(
-                        &compressed,
-                        mime.as_ref(),
-                        cache_control,
-                        true,
-                        Some("br"),
-                        &etag,
-                    )?
-                } else {
+        // compressed responses fall back to identity when they aren't smaller
+        let encode = |encoding: Encoding, compressed: Option<Vec<u8>>| -> Result<Response> {
+            match compressed {
+                Some(compressed) if compressed.len() < content.len() => serialize(
+                    status,
+                    compressed.into(),
+                    mime.as_ref(),
+                    cache_control,
+                    true,
+                    Some(encoding.token()),
+                    &etag,
+                ),
+                Some(compressed) => {
                     // dont log robots.txt being smaller
                     if content.len() > 100 {
                         println!(
-                            "Compressing {id:?} ({mime:?}) yielded a worse size! {} -> {}",
+                            "Compressing {id:?} ({mime:?}) with {} yielded a worse size! {} -> {}",
+                            encoding.token(),
                             content.len(),
                             compressed.len()
                         );
                     }
-
-                    identity.clone()
+                    Ok(identity.clone())
                 }
+                None => Ok(identity.clone()),
             }
-            None => identity.clone(),
         };
 
+        let compress = brotli_settings.is_some();
+        let brotli = encode(
+            Encoding::Brotli,
+            brotli_settings.map(|settings| compress_brotli(&content, settings)),
+        )?;
+        let zstd = encode(Encoding::Zstd, compress.then(|| compress_zstd(&content)))?;
+        let gzip = encode(Encoding::Gzip, compress.then(|| compress_gzip(&content)))?;
+
         let not_modified = serialize_304(&etag)?;
 
         Ok(Route {
             brotli,
+            zstd,
+            gzip,
             identity,
             not_modified,
             etag: etag.into_bytes().into(),
+            hash,
         })
     }
 }
@@ -100,6 +305,8 @@ fn brotli_settings(mime: &Mime, fast: bool) -> Option<BrotliEncoderParams> {
     }
 
     match mime.type_() {
+        // streams are already deflated
+        APPLICATION if mime.subtype() == PDF => None,
         TEXT | APPLICATION => Some(BrotliEncoderParams {
             quality: 11,
             mode: BrotliEncoderMode::BROTLI_MODE_TEXT,
@@ -114,25 +321,30 @@ fn brotli_settings(mime: &Mime, fast: bool) -> Option<BrotliEncoderParams> {
     }
 }
 
+const ZSTD_LEVEL: i32 = 19;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
 fn cache_control(mime: &Mime) -> Option<&'static str> {
     match mime.type_() {
-        FONT => Some("public, max-age=31536000, immutable"),
+        FONT => Some(IMMUTABLE),
         IMAGE => Some("public, max-age=86400"),
         _ => None,
     }
 }
 
 fn serialize(
-    body: &[u8],
+    status: &str,
+    body: Arc<[u8]>,
     content_type: &str,
     cache_control: Option<&str>,
     vary: bool,
     encoding: Option<&str>,
     etag: &str,
-) -> Result<Box<[u8]>> {
-    let mut buf = Vec::with_capacity(body.len() + 256);
+) -> Result<Response> {
+    let mut buf = Vec::with_capacity(256);
 
-    write!(buf, "HTTP/1.1 200 OK\r\n")?;
+    write!(buf, "HTTP/1.1 {status}\r\n")?;
     write!(buf, "Content-Type: {content_type}\r\n")?;
     write!(buf, "Content-Length: {}\r\n", body.len())?;
     write!(buf, "ETag: {etag}\r\n")?;
@@ -146,14 +358,40 @@ fn serialize(
     if vary {
         write!(buf, "Vary: Accept-Encoding\r\n")?;
     }
+    // ranges are served from the identity response
+    if encoding.is_none() && status.starts_with("200") {
+        write!(buf, "Accept-Ranges: bytes\r\n")?;
+    }
 
     buf.extend_from_slice(b"\r\n");
-    buf.extend_from_slice(body);
 
-    Ok(buf.into())
+    Response::with_body(buf, body)
 }
 
-fn serialize_304(etag: &str) -> Result<Box<[u8]>> {
+/// Re-serializes the head of `response` with `cache_control`, sharing its body
+fn replace_cache_control(response: &Response, cache_control: Option<&str>) -> Result<Response> {
+    let mut buf = Vec::with_capacity(response.head.len() + 64);
+
+    for line in response.head.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let is_cache_control = line
+            .get(..14)
+            .is_some_and(|name| name.eq_ignore_ascii_case(b"cache-control:"));
+        if !line.is_empty() && !is_cache_control {
+            buf.extend_from_slice(line);
+            buf.extend_from_slice(b"\r\n");
+        }
+    }
+    if let Some(cc) = cache_control {
+        write!(buf, "Cache-Control: {cc}\r\n")?;
+    }
+
+    buf.extend_from_slice(b"\r\n");
+
+    Response::with_body(buf, response.body.clone())
+}
+
+fn serialize_304(etag: &str) -> Result<Response> {
     let mut buf = Vec::with_capacity(64);
 
     write!(buf, "HTTP/1.1 304 Not Modified\r\n")?;
@@ -162,7 +400,7 @@ fn serialize_304(etag: &str) -> Result<Box<[u8]>> {
 
     buf.extend_from_slice(b"\r\n");
 
-    Ok(buf.into())
+    Response::new(buf)
 }
 
 fn compress_brotli(input: &[u8], mut params: BrotliEncoderParams) -> Vec<u8> {
@@ -172,12 +410,23 @@ fn compress_brotli(input: &[u8], mut params: BrotliEncoderParams) -> Vec<u8> {
     buf
 }
 
+fn compress_zstd(input: &[u8]) -> Vec<u8> {
+    zstd::encode_all(input, ZSTD_LEVEL).unwrap()
+}
+
+fn compress_gzip(input: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(input).unwrap();
+    encoder.finish().unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use brotli::BrotliDecompress;
     use httparse::{EMPTY_HEADER, Header, Response, Status};
     use mime_guess::mime;
+    use std::io::Read;
     use typst::syntax::VirtualPath;
 
     #[test]
@@ -189,11 +438,36 @@ mod tests {
         assert!(cc.contains("immutable"));
     }
 
+    #[test]
+    fn empty_redirect() {
+        let route = Route::empty("308 Permanent Redirect", Some("/blog")).unwrap();
+        let mut headers = [EMPTY_HEADER; 16];
+        let raw = http1(&route.identity);
+        let (resp, body) = parse_response(&raw, &mut headers);
+
+        assert_eq!(resp.code.unwrap(), 308);
+        assert_header(resp.headers, "Location", b"/blog");
+        assert_header(resp.headers, "Content-Length", b"0");
+        assert_no_header(resp.headers, "ETag");
+        assert!(body.is_empty());
+        assert_eq!(route.brotli, route.identity);
+    }
+
+    #[test]
+    fn test_status_page() {
+        assert_eq!(status_page("/404"), Some("404 Not Found"));
+        assert_eq!(status_page("/410"), Some("410 Gone"));
+        assert_eq!(status_page("/500"), Some("500 Internal Server Error"));
+        assert_eq!(status_page("/blog/404"), None);
+        assert_eq!(status_page("/"), None);
+    }
+
     #[test]
     fn test_brotli_settings() {
         assert!(brotli_settings(&mime::TEXT_HTML, false).is_some());
         assert!(brotli_settings(&mime::APPLICATION_JSON, false).is_some());
         assert!(brotli_settings(&mime::IMAGE_SVG, false).is_some());
+        assert!(brotli_settings(&mime::APPLICATION_PDF, false).is_none());
         assert!(brotli_settings(&mime::IMAGE_PNG, false).is_none());
         assert!(brotli_settings(&mime::IMAGE_JPEG, false).is_none());
         assert!(brotli_settings(&mime::FONT_WOFF2, false).is_none());
@@ -214,6 +488,10 @@ mod tests {
         assert_eq!(decompressed, input);
     }
 
+    fn http1(response: &super::Response) -> Vec<u8> {
+        [&response.head[..], &response.body[..]].concat()
+    }
+
     fn parse_response<'a>(
         raw: &'a [u8],
         headers: &'a mut [Header<'a>],
@@ -271,7 +549,17 @@ mod tests {
 
     #[test]
     fn serialize_minimal() {
-        let raw = serialize(b"hello", "text/plain", None, false, None, "\"e1\"").unwrap();
+        let raw = serialize(
+            "200 OK",
+            b"hello"[..].into(),
+            "text/plain",
+            None,
+            false,
+            None,
+            "\"e1\"",
+        )
+        .unwrap();
+        let raw = http1(&raw);
         let mut headers = [EMPTY_HEADER; 16];
         let (resp, body) = parse_response(&raw, &mut headers);
 
@@ -279,6 +567,7 @@ mod tests {
         assert_header(resp.headers, "Content-Type", b"text/plain");
         assert_header(resp.headers, "Content-Length", b"5");
         assert_header(resp.headers, "ETag", b"\"e1\"");
+        assert_header(resp.headers, "Accept-Ranges", b"bytes");
         assert_no_header(resp.headers, "Content-Encoding");
         assert_no_header(resp.headers, "Cache-Control");
         assert_no_header(resp.headers, "Vary");
@@ -288,7 +577,8 @@ mod tests {
     #[test]
     fn serialize_all_optional_headers() {
         let raw = serialize(
-            b"data",
+            "200 OK",
+            b"data"[..].into(),
             "text/html",
             Some("public, max-age=86400"),
             true,
@@ -296,6 +586,7 @@ mod tests {
             "\"e2\"",
         )
         .unwrap();
+        let raw = http1(&raw);
         let mut headers = [EMPTY_HEADER; 16];
         let (resp, body) = parse_response(&raw, &mut headers);
 
@@ -303,12 +594,22 @@ mod tests {
         assert_header(resp.headers, "Content-Encoding", b"br");
         assert_header(resp.headers, "Cache-Control", b"public, max-age=86400");
         assert_header(resp.headers, "Vary", b"Accept-Encoding");
+        assert_no_header(resp.headers, "Accept-Ranges");
         assert_eq!(body, b"data");
     }
 
     #[test]
     fn serialize_empty_body() {
-        let raw = serialize(b"", "text/plain", None, false, None, "\"e0\"").unwrap();
+        let raw = serialize(
+            "200 OK",
+            [][..].into(),
+            "text/plain",
+            None,
+            false,
+            None,
+            "\"e0\"",
+        );
+        let raw = http1(&raw.unwrap());
         let mut headers = [EMPTY_HEADER; 16];
         let (resp, body) = parse_response(&raw, &mut headers);
 
@@ -319,7 +620,7 @@ mod tests {
 
     #[test]
     fn serialize_304_structure() {
-        let raw = serialize_304("\"abc\"").unwrap();
+        let raw = http1(&serialize_304("\"abc\"").unwrap());
         let mut headers = [EMPTY_HEADER; 16];
         let (resp, body) = parse_response(&raw, &mut headers);
 
@@ -350,7 +651,8 @@ mod tests {
         assert_ne!(route.identity, route.brotli);
 
         let mut headers = [EMPTY_HEADER; 16];
-        let (resp, _) = parse_response(&route.brotli, &mut headers);
+        let raw = http1(&route.brotli);
+        let (resp, _) = parse_response(&raw, &mut headers);
         assert_header(resp.headers, "Content-Encoding", b"br");
     }
 
@@ -361,6 +663,36 @@ mod tests {
             Route::compile(&test_file_id("t.png"), content, &mime::IMAGE_PNG, false).unwrap();
 
         assert_eq!(route.identity, route.brotli);
+        assert_eq!(route.identity, route.zstd);
+        assert_eq!(route.identity, route.gzip);
+    }
+
+    #[test]
+    fn compile_html_produces_zstd_gzip() {
+        let content = compressible_body();
+        let route = Route::compile(
+            &test_file_id("t.html"),
+            content.clone(),
+            &mime::TEXT_HTML,
+            false,
+        )
+        .unwrap();
+
+        let mut headers = [EMPTY_HEADER; 16];
+        let raw = http1(&route.zstd);
+        let (resp, body) = parse_response(&raw, &mut headers);
+        assert_header(resp.headers, "Content-Encoding", b"zstd");
+        assert_eq!(zstd::decode_all(body).unwrap(), content);
+
+        let mut headers = [EMPTY_HEADER; 16];
+        let raw = http1(&route.gzip);
+        let (resp, body) = parse_response(&raw, &mut headers);
+        assert_header(resp.headers, "Content-Encoding", b"gzip");
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, content);
     }
 
     #[test]
@@ -382,6 +714,88 @@ mod tests {
         assert_ne!(r1.etag, r2.etag);
     }
 
+    #[test]
+    fn immutable_reuses_bodies() {
+        let route = Route::compile(
+            &test_file_id("a.css"),
+            compressible_body(),
+            &mime::TEXT_CSS,
+            false,
+        )
+        .unwrap();
+        let immutable = route.immutable().unwrap();
+
+        for encoding in Encoding::ALL {
+            let (before, after) = (route.response(encoding), immutable.response(encoding));
+            assert!(Arc::ptr_eq(&before.body, &after.body));
+
+            let mut headers = [EMPTY_HEADER; 16];
+            let raw = http1(after);
+            let (resp, _) = parse_response(&raw, &mut headers);
+            assert_header(resp.headers, "Cache-Control", IMMUTABLE.as_bytes());
+            assert_header(resp.headers, "ETag", &route.etag);
+        }
+        assert_eq!(immutable.hash, route.hash);
+
+        let image = Route::compile(&test_file_id("a.png"), vec![0; 64], &mime::IMAGE_PNG, false)
+            .unwrap()
+            .with_cache_control(None)
+            .unwrap();
+        let mut headers = [EMPTY_HEADER; 16];
+        let raw = http1(&image.identity);
+        let (resp, _) = parse_response(&raw, &mut headers);
+        assert_no_header(resp.headers, "Cache-Control");
+        assert_header(resp.headers, "Content-Length", b"64");
+    }
+
+    #[test]
+    fn compile_status_line() {
+        let route = Route::compile_status(
+            &test_file_id("t.html"),
+            compressible_body(),
+            &mime::TEXT_HTML,
+            "500 Internal Server Error",
+            false,
+        )
+        .unwrap();
+
+        for response in [&route.identity, &route.brotli] {
+            let raw = http1(response);
+            let mut headers = [EMPTY_HEADER; 16];
+            let (resp, _) = parse_response(&raw, &mut headers);
+            assert_eq!(resp.code.unwrap(), 500);
+        }
+    }
+
+    #[test]
+    fn compile_h2_heads() {
+        let content = compressible_body();
+        let route = Route::compile(
+            &test_file_id("t.html"),
+            content.clone(),
+            &mime::TEXT_HTML,
+            false,
+        )
+        .unwrap();
+
+        let fields = hpack::Decoder::new().decode(&route.brotli.h2).unwrap();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| n == name.as_bytes())
+                .map(|(_, v)| v.as_slice())
+        };
+        assert_eq!(field(":status"), Some(&b"200"[..]));
+        assert_eq!(field("content-encoding"), Some(&b"br"[..]));
+        assert_eq!(field("etag"), Some(route.etag.as_ref()));
+
+        let body = &route.brotli.body;
+        let mut decompressed = Vec::new();
+        BrotliDecompress(&mut &body[..], &mut decompressed).unwrap();
+        assert_eq!(decompressed, content);
+        assert!(route.not_modified.body.is_empty());
+    }
+
     #[test]
     fn compile_304_contains_etag() {
         let route = Route::compile(
@@ -393,7 +807,8 @@ mod tests {
         .unwrap();
 
         let mut headers = [EMPTY_HEADER; 16];
-        let (resp, _) = parse_response(&route.not_modified, &mut headers);
+        let raw = http1(&route.not_modified);
+        let (resp, _) = parse_response(&raw, &mut headers);
 
         assert_eq!(resp.code.unwrap(), 304);
         assert_header(resp.headers, "ETag", &route.etag);